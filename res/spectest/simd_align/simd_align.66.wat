@@ -0,0 +1 @@
+(memory 1) (func (result v128) (i16x8.load8x8_u align=0 (i32.const 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(memory 1) (func (result v128) (i32x4.load16x4_u align=-1 (i32.const 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(memory 1) (func (result v128) (i64x2.load32x2_s align=0 (i32.const 0)))
\ No newline at end of file
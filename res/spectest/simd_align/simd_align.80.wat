@@ -0,0 +1 @@
+(memory 1) (func (result v128) (v8x16.load_splat align=-1 (i32.const 0)))
\ No newline at end of file
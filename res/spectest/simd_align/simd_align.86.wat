@@ -0,0 +1 @@
+(memory 1) (func (result v128) (v32x4.load_splat align=3 (i32.const 0)))
\ No newline at end of file
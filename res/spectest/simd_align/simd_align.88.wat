@@ -0,0 +1 @@
+(memory 1) (func (result v128) (v64x2.load_splat align=0 (i32.const 0)))
\ No newline at end of file
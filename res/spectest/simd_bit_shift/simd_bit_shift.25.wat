@@ -0,0 +1 @@
+(memory 1) (func (result v128) (i64x2.shr   (v128.const i32x4 0 0 0 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(memory 1) (func (result i32) (f32x4.any_true (v128.const i32x4 0 0 0 0)))
\ No newline at end of file
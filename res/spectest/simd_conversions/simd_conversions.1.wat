@@ -0,0 +1 @@
+(func (result v128) (i32x4.trunc_sat_f32x4 (v128.const f32x4 0.0 0.0 0.0 0.0)))
\ No newline at end of file
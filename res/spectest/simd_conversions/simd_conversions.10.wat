@@ -0,0 +1 @@
+(func (result v128) (f64x2.convert_i64x2_u (v128.const i64x2 1 2)))
\ No newline at end of file
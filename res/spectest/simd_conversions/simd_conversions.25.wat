@@ -0,0 +1 @@
+(func (result v128) (i32x4.widen_low_i16x8 (v128.const i16x8 0 0 0 0 0 0 0 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (result v128) (i16x8.widen_low_i32x4_u (v128.const i32x4 0 0 0 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(memory 1) (func (result v128) (i8x16.max (v128.const i32x4 0 0 0 0) (v128.const i32x4 0 0 0 0)))
\ No newline at end of file
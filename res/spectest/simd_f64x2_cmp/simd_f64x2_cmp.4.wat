@@ -0,0 +1 @@
+(memory 1) (func  (param $x v128) (param $y v128) (result v128) (f2x64.le (local.get $x) (local.get $y)))
\ No newline at end of file
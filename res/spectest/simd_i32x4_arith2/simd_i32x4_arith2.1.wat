@@ -0,0 +1 @@
+(memory 1) (func (result v128) (f32x4.min_s (v128.const i32x4 0 0 0 0) (v128.const i32x4 1 1 1 1)))
\ No newline at end of file
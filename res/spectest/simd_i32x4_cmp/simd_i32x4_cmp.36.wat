@@ -0,0 +1 @@
+(memory 1) (func (param $x v128) (param $y v128) (result v128) (i4x32.le_s (local.get $x) (local.get $y)))
\ No newline at end of file
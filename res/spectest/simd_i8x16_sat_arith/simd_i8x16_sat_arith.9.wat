@@ -0,0 +1 @@
+(func (result v128) (f32x4.add_saturate_s (v128.const f32x4 0 0 0 0) (v128.const f32x4 0 0 0 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (result i32) (i32x4.extract_lane 256 (v128.const i32x4 0 0 0 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (param i32) (result i32) (i32x4.extract_lane (local.get 0) (v128.const i32x4 0 0 0 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (param i32) (result v128) (f32x4.replace_lane (local.get 0) (v128.const f32x4 0 0 0 0) (f32.const 1.0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (param v128) (result v128) (v8x16.shuffle (local.get 0) (v128.const i8x16 15 14 13 12 11 10 9 8 7 6 5 4 3 2 1 0) (v128.const i8x16 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)))
\ No newline at end of file
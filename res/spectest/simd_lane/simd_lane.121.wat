@@ -0,0 +1 @@
+(func (param i32) (result v128) (f64x2.replace_lane (local.get 0) (v128.const f64x2 0 0) (f64.const 1.0)))
\ No newline at end of file
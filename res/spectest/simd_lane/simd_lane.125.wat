@@ -0,0 +1 @@
+(func (result i32) (i16x8.extract_lane_u -inf (v128.const i16x8 0 0 0 0 0 0 0 0)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (result v128) (f64x2.replace_lane -inf (v128.const f64x2 0 0) (f64.const 1.1)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (result v128) (i16x8.replace_lane  -1 (v128.const i16x8 0 0 0 0 0 0 0 0) (i32.const 1)))
\ No newline at end of file
@@ -0,0 +1 @@
+(func $f32x4.extract_lane-arg-empty (result f32)  (f32x4.extract_lane))
\ No newline at end of file
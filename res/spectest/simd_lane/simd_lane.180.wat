@@ -0,0 +1 @@
+(func $i8x16.replace_lane-1st-arg-empty (result v128)  (i8x16.replace_lane (v128.const i8x16 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0) (i32.const 1)))
\ No newline at end of file
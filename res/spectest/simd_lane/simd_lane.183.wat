@@ -0,0 +1 @@
+(func $i8x16.replace_lane-arg-empty (result v128)  (i8x16.replace_lane))
\ No newline at end of file
@@ -0,0 +1 @@
+(func $i16x8.replace_lane-arg-empty (result v128)  (i16x8.replace_lane))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (result v128) (f32x4.replace_lane  -1 (v128.const f32x4 0 0 0 0) (i32.const 1)))
\ No newline at end of file
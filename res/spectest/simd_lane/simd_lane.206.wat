@@ -0,0 +1 @@
+(func $v8x16.shuffle-arg-empty (result v128)  (v8x16.shuffle))
\ No newline at end of file
@@ -0,0 +1 @@
+(func (result i64) (i64x2.extract_lane 256 (v128.const i64x2 0 0)))
\ No newline at end of file
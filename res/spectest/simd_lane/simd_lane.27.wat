@@ -0,0 +1 @@
+(func (result v128) (f64x2.replace_lane  -1 (v128.const f64x2 0 0) (f64.const 1)))
\ No newline at end of file
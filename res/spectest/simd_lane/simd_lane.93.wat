@@ -0,0 +1 @@
+(func (param v128) (result v128)local.get 0local.get 0v8x16.shuffle 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16)
\ No newline at end of file
@@ -0,0 +1 @@
+(memory 1) (func (drop (i8x16.load_splat (i32.const 0))))
\ No newline at end of file
@@ -0,0 +1,12 @@
+(module
+ (import "spectest" "table" (table $table 10 funcref))
+ (import "spectest" "global_i32" (global $global_i32 i32))
+ (export "main" (func $main))
+ (func $f (result i32)
+   (i32.const 1)
+ )
+ (elem (global.get $global_i32) $f)
+ (func $main (result i32)
+   (i32.const 0)
+ )
+)
@@ -0,0 +1,28 @@
+(module
+ (memory 1)
+ (data (i32.const 0) "\07")
+ (global $global (mut i32) (i32.const 11))
+ (table $table 2 funcref)
+ (export "main" (func $main))
+ (type $t (func (result i32)))
+ (func $f (result i32)
+   (i32.const 13)
+ )
+ (func $g (result i32)
+   (i32.const 100)
+ )
+ (elem (i32.const 0) $f $g)
+ (func $main (result i32)
+   (local $result i32)
+   (local.set $result
+     (i32.add
+       (i32.add
+         (i32.load8_u (i32.const 0))
+         (global.get $global))
+       (call_indirect (type $t) (i32.const 0))))
+   (i32.store8 (i32.const 0) (i32.const 100))
+   (global.set $global (i32.const 100))
+   (table.set $table (i32.const 0) (ref.func $g))
+   (local.get $result)
+ )
+)
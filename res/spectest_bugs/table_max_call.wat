@@ -0,0 +1,14 @@
+(module
+ (table $table 2 3 funcref)
+ (export "main" (func $main))
+ (type $t (func (result i32)))
+ (func $f (result i32)
+   (i32.const 1)
+ )
+ (elem (i32.const 1) $f)
+ (func $main (result i32)
+   (i32.add
+     (call_indirect (type $t) (i32.const 1))
+     (call_indirect (type $t) (i32.const -1)))
+ )
+)
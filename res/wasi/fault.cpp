@@ -0,0 +1,37 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <wasi/api.h>
+
+int main(int argc, char** argv)
+{
+	if(argc != 2)
+	{
+		fprintf(stderr, "Usage: %s <directory>\n", argv[0]);
+		return 1;
+	}
+
+	__wasi_size_t numBytesWritten;
+	__wasi_ciovec_t iov = {(const uint8_t*)0xfffffff0, 100};
+	int result = __wasi_fd_write(1, &iov, 1, &numBytesWritten);
+	printf("fd_write(bad buffer): %s\n", strerror(result));
+
+	result = __wasi_fd_write(1, (const __wasi_ciovec_t*)0xfffffffc, 1, &numBytesWritten);
+	printf("fd_write(bad iovs): %s\n", strerror(result));
+
+	__wasi_timestamp_t* time = (__wasi_timestamp_t*)0xfffffffc;
+	result = __wasi_clock_time_get(__WASI_CLOCKID_MONOTONIC, 1, time);
+	printf("clock_time_get(bad result): %s\n", strerror(result));
+
+	char path[4096];
+	size_t prefixLength = strlen(argv[1]);
+	memcpy(path, argv[1], prefixLength);
+	memset(path + prefixLength, 'a', sizeof(path) - prefixLength - 1);
+	path[prefixLength] = '/';
+	path[sizeof(path) - 1] = '\0';
+	if(open(path, O_RDONLY) != -1) { return 1; }
+	printf("open(long path): %s\n", strerror(errno));
+
+	return 0;
+}
@@ -0,0 +1,41 @@
+#include <errno.h>
+#include <poll.h>
+#include <stdio.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+static double now()
+{
+	struct timespec ts;
+	clock_gettime(CLOCK_MONOTONIC, &ts);
+	return ts.tv_sec + ts.tv_nsec / 1e9;
+}
+
+int main(int argc, char** argv)
+{
+	double start = now();
+	struct timespec duration = {0, 200000000};
+	if(nanosleep(&duration, NULL))
+	{
+		fprintf(stderr, "nanosleep failed: %s\n", strerror(errno));
+		return 1;
+	}
+	printf("nanosleep(200ms): %s\n", now() - start >= 0.2 ? "ok" : "too short");
+
+	start = now();
+	struct pollfd fds[1] = {{STDIN_FILENO, POLLIN, 0}};
+	int result = poll(fds, 1, 1000);
+	if(result < 0)
+	{
+		fprintf(stderr, "poll failed: %s\n", strerror(errno));
+		return 1;
+	}
+	printf("poll(stdin): %d %s %s\n", result, fds[0].revents & POLLIN ? "POLLIN" : "-", now() - start < 1 ? "ok" : "too long");
+
+	start = now();
+	result = poll(NULL, 0, 100);
+	printf("poll(100ms): %d %s\n", result, now() - start >= 0.1 ? "ok" : "too short");
+
+	return 0;
+}
@@ -0,0 +1,63 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+#include <wasi/api.h>
+
+int main(int argc, char** argv)
+{
+	if(argc != 2)
+	{
+		fprintf(stderr, "Usage: %s <file>\n", argv[0]);
+		return 1;
+	}
+
+	const char string[] = "Hello file!\n";
+	size_t stringLength = sizeof(string) - 1;
+
+	int fd = open(argv[1], O_WRONLY | O_CREAT | O_TRUNC);
+	if(fd == -1)
+	{
+		fprintf(stderr, "Failed to open '%s' for writing: %s\n", argv[1], strerror(errno));
+		return 1;
+	}
+	close(fd);
+
+	fd = open(argv[1], O_RDONLY);
+	if(fd == -1)
+	{
+		fprintf(stderr, "Failed to open '%s' for reading: %s\n", argv[1], strerror(errno));
+		return 1;
+	}
+	if(write(fd, string, stringLength) != -1) { return 1; }
+	printf("write(O_RDONLY): %s\n", strerror(errno));
+	close(fd);
+
+	fd = open(argv[1], O_WRONLY);
+	if(fd == -1)
+	{
+		fprintf(stderr, "Failed to open '%s' for writing: %s\n", argv[1], strerror(errno));
+		return 1;
+	}
+	__wasi_fdstat_t stat;
+	if(__wasi_fd_fdstat_get(fd, &stat)) { return 1; }
+	__wasi_rights_t rights = stat.fs_rights_base & ~__WASI_RIGHT_FD_WRITE;
+	int result = __wasi_fd_fdstat_set_rights(fd, rights, stat.fs_rights_inheriting);
+	if(result)
+	{
+		fprintf(stderr, "__wasi_fd_fdstat_set_rights failed: %s\n", strerror(result));
+		return 1;
+	}
+	if(write(fd, string, stringLength) != -1) { return 1; }
+	printf("write(dropped FD_WRITE): %s\n", strerror(errno));
+
+	result = __wasi_fd_fdstat_set_rights(fd, stat.fs_rights_base, stat.fs_rights_inheriting);
+	printf("fd_fdstat_set_rights(add FD_WRITE): %s\n", strerror(result));
+
+	close(fd);
+	if(write(fd, string, stringLength) != -1) { return 1; }
+	printf("write(closed): %s\n", strerror(errno));
+
+	return 0;
+}
@@ -0,0 +1,21 @@
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+int main(int argc, char** argv)
+{
+	if(argc != 3)
+	{
+		fprintf(stderr, "Usage: %s <target> <link path>\n", argv[0]);
+		return 1;
+	}
+
+	if(symlink(argv[1], argv[2]))
+	{
+		fprintf(stderr, "Failed to create symlink '%s': %s\n", argv[2], strerror(errno));
+		return 1;
+	}
+
+	return 0;
+}
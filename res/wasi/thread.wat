@@ -0,0 +1,27 @@
+;; A thread of wasi-threads shares the memory the module imports. Spawning it must neither write the data segments into
+;; the memory again nor run the start function again. Exit with 0 on success.
+(module
+  (import "wasi" "thread-spawn" (func $thread_spawn (param i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (import "env" "memory" (memory 1 1 shared))
+  (data (i32.const 8) "\01\00\00\00")
+  (func $start
+    (drop (i32.atomic.rmw.add (i32.const 4) (i32.const 1))))
+  (start $start)
+  (func (export "wasi_thread_start") (param $tid i32) (param $arg i32)
+    (i32.atomic.store (i32.const 0) (i32.const 1))
+    (drop (atomic.notify (i32.const 0) (i32.const 1))))
+  (func (export "_start")
+    (i32.store (i32.const 8) (i32.const 7))
+    (if (i32.lt_s (call $thread_spawn (i32.const 0)) (i32.const 0))
+      (then (call $proc_exit (i32.const 1))))
+    (block
+      (loop
+        (br_if 1 (i32.atomic.load (i32.const 0)))
+        (drop (i32.atomic.wait (i32.const 0) (i32.const 0) (i64.const -1)))
+        (br 0)))
+    (if (i32.ne (i32.atomic.load (i32.const 4)) (i32.const 1))
+      (then (call $proc_exit (i32.const 2))))
+    (if (i32.ne (i32.load (i32.const 8)) (i32.const 7))
+      (then (call $proc_exit (i32.const 3))))
+    (call $proc_exit (i32.const 0))))
@@ -33,6 +33,24 @@ for e in glob.glob('./res/testsuite/*.wast'):
     os.makedirs(f'./res/testsuite/spectest/{a}')
     call(f'wast2json {e} -o ./res/testsuite/spectest/{a}/{a}.json')
 
+# simd_const is left out, wast2json rejects the binary modules it contains.
+simd_list = [os.path.splitext(os.path.basename(e))[0] for e in glob.glob('./res/testsuite/proposals/simd/*.wast')]
+simd_list = [e for e in simd_list if e != 'simd_const']
+
+# Suites of proposals, which wast2json only reads with the proposal enabled. They keep the name of their file, and
+# replace the core suite of the same name.
+proposal_list = [
+    ('bulk-memory-operations', '--enable-bulk-memory',
+     ['memory_copy', 'memory_fill', 'memory_init', 'table_copy', 'table_init']),
+    ('simd', '--enable-simd', simd_list),
+    ('threads', '--enable-threads', ['atomic']),
+]
+
+for proposal, flag, suite_list in proposal_list:
+    for a in suite_list:
+        os.makedirs(f'./res/testsuite/spectest/{a}', exist_ok=True)
+        call(f'wast2json {flag} ./res/testsuite/proposals/{proposal}/{a}.wast -o ./res/testsuite/spectest/{a}/{a}.json')
+
 for e in os.scandir('./res/testsuite/spectest/'):
     if len(os.listdir(e)) == 0:
         call(f'rm -rf {e.path}')
@@ -1,32 +1,135 @@
 use super::code_builder;
 use super::context;
+use super::error::{Location, WascError};
+use std::convert::TryFrom;
 use wasmparser::WasmDecoder;
 
-// See: https://webassembly.github.io/spec/core/valid/instructions.html#constant-expressions.
+// See: https://webassembly.github.io/spec/core/valid/instructions.html#constant-expressions. The extended constant
+// expressions proposal adds the add, sub and mul instructions of i32 and i64.
 #[derive(Debug)]
 pub enum ConstantOperator {
     I32Const { value: i32 },
     I64Const { value: i64 },
     F32Const { value: u32 },
     F64Const { value: u64 },
+    V128Const { value: u128 },
     GlobalGet { global_index: u32 },
+    RefNull { ty: wasmparser::Type },
+    RefFunc { function_index: u32 },
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I64Add,
+    I64Sub,
+    I64Mul,
 }
 
-impl<'a> From<wasmparser::Operator<'a>> for ConstantOperator {
-    fn from(o: wasmparser::Operator) -> Self {
+// A constant expression is a sequence of constant instructions, which leaves a single value on the stack.
+type ConstantExpression = Vec<ConstantOperator>;
+
+impl<'a> TryFrom<wasmparser::Operator<'a>> for ConstantOperator {
+    type Error = WascError;
+
+    fn try_from(o: wasmparser::Operator) -> Result<Self, Self::Error> {
         match o {
-            wasmparser::Operator::I32Const { value } => ConstantOperator::I32Const { value: value },
-            wasmparser::Operator::I64Const { value } => ConstantOperator::I64Const { value: value },
-            wasmparser::Operator::F32Const { value } => ConstantOperator::F32Const { value: value.bits() },
-            wasmparser::Operator::F64Const { value } => ConstantOperator::F64Const { value: value.bits() },
-            wasmparser::Operator::GlobalGet { global_index } => ConstantOperator::GlobalGet {
-                global_index: global_index,
-            },
-            _ => panic!("unreachable"),
+            wasmparser::Operator::I32Const { value } => Ok(ConstantOperator::I32Const { value }),
+            wasmparser::Operator::I64Const { value } => Ok(ConstantOperator::I64Const { value }),
+            wasmparser::Operator::F32Const { value } => Ok(ConstantOperator::F32Const { value: value.bits() }),
+            wasmparser::Operator::F64Const { value } => Ok(ConstantOperator::F64Const { value: value.bits() }),
+            wasmparser::Operator::V128Const { value } => Ok(ConstantOperator::V128Const {
+                value: u128::from_le_bytes(*value.bytes()),
+            }),
+            wasmparser::Operator::GlobalGet { global_index } => Ok(ConstantOperator::GlobalGet { global_index }),
+            wasmparser::Operator::I32Add => Ok(ConstantOperator::I32Add),
+            wasmparser::Operator::I32Sub => Ok(ConstantOperator::I32Sub),
+            wasmparser::Operator::I32Mul => Ok(ConstantOperator::I32Mul),
+            wasmparser::Operator::I64Add => Ok(ConstantOperator::I64Add),
+            wasmparser::Operator::I64Sub => Ok(ConstantOperator::I64Sub),
+            wasmparser::Operator::I64Mul => Ok(ConstantOperator::I64Mul),
+            o => Err(WascError::unsupported(format!("{:?} in constant expression", o))),
         }
     }
 }
 
+// wasmparser 0.51 follows an early draft of the reference types proposal, in which ref.null has no type immediate, and
+// fails on the element segments of the final proposal. The global and element sections are decoded here instead, both
+// forms of ref.null are accepted.
+fn read_constant_expression(reader: &mut wasmparser::BinaryReader) -> Result<ConstantExpression, WascError> {
+    let mut expr = vec![];
+    loop {
+        let operator = match reader.read_u8().map_err(reader_error)? {
+            0x0b => return Ok(expr),
+            0x41 => ConstantOperator::I32Const {
+                value: reader.read_var_i32().map_err(reader_error)?,
+            },
+            0x42 => ConstantOperator::I64Const {
+                value: reader.read_var_i64().map_err(reader_error)?,
+            },
+            0x43 => ConstantOperator::F32Const {
+                value: reader.read_f32().map_err(reader_error)?.bits(),
+            },
+            0x44 => ConstantOperator::F64Const {
+                value: reader.read_f64().map_err(reader_error)?.bits(),
+            },
+            0x23 => ConstantOperator::GlobalGet {
+                global_index: reader.read_var_u32().map_err(reader_error)?,
+            },
+            0x6a => ConstantOperator::I32Add,
+            0x6b => ConstantOperator::I32Sub,
+            0x6c => ConstantOperator::I32Mul,
+            0x7c => ConstantOperator::I64Add,
+            0x7d => ConstantOperator::I64Sub,
+            0x7e => ConstantOperator::I64Mul,
+            0xfd => match reader.read_var_u32().map_err(reader_error)? {
+                0x0c => {
+                    let mut value = [0u8; 16];
+                    value.copy_from_slice(reader.read_bytes(16).map_err(reader_error)?);
+                    ConstantOperator::V128Const {
+                        value: u128::from_le_bytes(value),
+                    }
+                }
+                e => {
+                    return Err(WascError::unsupported(format!(
+                        "opcode 0xfd 0x{:02x} in constant expression",
+                        e
+                    )))
+                }
+            },
+            0xd0 => match reader.read_u8().map_err(reader_error)? {
+                // The draft form, the byte read is the end of the expression.
+                0x0b => {
+                    expr.push(ConstantOperator::RefNull {
+                        ty: wasmparser::Type::AnyFunc,
+                    });
+                    return Ok(expr);
+                }
+                0x70 => ConstantOperator::RefNull {
+                    ty: wasmparser::Type::AnyFunc,
+                },
+                0x6f => ConstantOperator::RefNull {
+                    ty: wasmparser::Type::AnyRef,
+                },
+                e => return Err(WascError::malformed(format!("reference type 0x{:02x}", e))),
+            },
+            0xd2 => ConstantOperator::RefFunc {
+                function_index: reader.read_var_u32().map_err(reader_error)?,
+            },
+            e => {
+                return Err(WascError::unsupported(format!(
+                    "opcode 0x{:02x} in constant expression",
+                    e
+                )))
+            }
+        };
+        expr.push(operator);
+    }
+}
+
+// The callers attach the location of the entry being decoded.
+fn reader_error(err: wasmparser::BinaryReaderError) -> WascError {
+    WascError::malformed(err.message())
+}
+
 // Custom sections have the id 0. They are intended to be used for debugging
 // information or third-party extensions, and are ignored by the WebAssembly
 // semantics. Their contents consist of a name further identifying the custom
@@ -37,37 +140,94 @@ struct Custom {
     data: Vec<u8>,
 }
 
+// The type of an import. wasmparser 0.51 has no tag imports, see read_import_section.
+#[derive(Debug)]
+enum ImportKind {
+    Function(u32),
+    Table(wasmparser::TableType),
+    Memory(MemoryType),
+    Global(wasmparser::GlobalType),
+    Tag(u32),
+}
+
+// The type of a memory, like wasmparser::MemoryType with the u64 limits of 64-bit memories.
+#[derive(Clone, Copy, Debug)]
+struct MemoryType {
+    initial: u64,
+    maximum: Option<u64>,
+    shared: bool,
+}
+
 // The imports component of a module defines a set of imports that are required for instantiation.
 #[derive(Debug)]
 struct Import {
     module: String,
     field: String,
-    ty: wasmparser::ImportSectionEntryType,
+    ty: ImportKind,
+    location: Location,
+}
+
+// The funcs component of a module defines a vector of functions. Only the type of each function is kept, the code is
+// compiled by WAVM.
+#[derive(Debug)]
+struct Function {
+    type_index: u32,
+    location: Location,
+}
+
+// The mems component of a module defines a vector of linear memories.
+#[derive(Debug)]
+struct Memory {
+    memory_type: MemoryType,
+    location: Location,
 }
 
 // The globals component of a module defines a vector of global variables.
 #[derive(Debug)]
 struct Global {
     global_type: wasmparser::GlobalType,
-    expr: Option<ConstantOperator>,
+    expr: ConstantExpression,
+    location: Location,
 }
 
 // The initial contents of a memory are zero-valued bytes. The data component of a module defines a vector of data
-// segments that initialize a range of memory, at a given offset, with a static vector of bytes.
+// segments that initialize a range of memory, at a given offset, with a static vector of bytes. A passive segment has
+// no offset, its bytes are copied by memory.init.
 #[derive(Debug)]
 struct Data {
     memory_index: u32,
-    offset: Option<ConstantOperator>,
+    offset: Option<ConstantExpression>,
     init: Vec<u8>,
+    location: Location,
 }
 
 // The initial contents of a table is uninitialized. The elem component of a module defines a vector of element
-// segments that initialize a subrange of a table, at a given offset, from a static vector of elements.
+// segments that initialize a subrange of a table, at a given offset, from a static vector of elements. A passive segment
+// has no offset, its elements are copied by table.init.
 #[derive(Debug)]
 struct Element {
     table_index: u32,
-    offset: Option<ConstantOperator>,
+    offset: Option<ConstantExpression>,
     init: Vec<wasmparser::ElementItem>,
+    location: Location,
+}
+
+// The tags component of a module defines a vector of exception tags. The type of a tag gives the values an exception
+// of the tag carries, it has no results.
+#[derive(Debug)]
+struct Tag {
+    type_index: u32,
+    location: Location,
+}
+
+// The kind of an export, like wasmparser::ExternalKind with tags.
+#[derive(Clone, Copy, Debug)]
+enum ExternalKind {
+    Function,
+    Table,
+    Memory,
+    Global,
+    Tag,
 }
 
 // The exports component of a module defines a set of exports that become accessible to the host environment once
@@ -75,7 +235,7 @@ struct Element {
 #[derive(Clone, Debug)]
 struct Export {
     field: String,
-    kind: wasmparser::ExternalKind,
+    kind: ExternalKind,
     index: u32,
 }
 
@@ -84,34 +244,94 @@ struct Export {
 struct Module {
     custom_list: Vec<Custom>,
     type_list: Vec<wasmparser::FuncType>,
-    function_list: Vec<u32>,
+    function_list: Vec<Function>,
     table_list: Vec<wasmparser::TableType>,
-    memory_list: Vec<wasmparser::MemoryType>,
+    memory_list: Vec<Memory>,
     global_list: Vec<Global>,
+    tag_list: Vec<Tag>,
     element_list: Vec<Element>,
     data_list: Vec<Data>,
     start: Option<u32>,
     import_list: Vec<Import>,
     export_list: Vec<Export>,
+    // Function names from the name section, they are only used to report traps.
+    function_name_map: std::collections::HashMap<u32, String>,
 }
 
 impl Module {
     // Build the module from raw bytes.
-    fn from(wasm: Vec<u8>) -> Self {
+    fn from(mut wasm: Vec<u8>) -> Result<Self, WascError> {
         let mut wasm_module: Module = Module::default();
+        // wasmparser 0.51 does not know the tag section of the exception handling proposal. It is decoded here, then
+        // turned into a custom section without a name, so that wasmparser skips it and the offsets stay the same.
+        if let Some((id_offset, range)) = find_section(&wasm, 13)? {
+            wasm_module.read_tag_section(&wasm[range.clone()], range.start)?;
+            wasm[id_offset] = 0;
+            wasm[range.start] = 0;
+        }
         let mut parser = wasmparser::Parser::new(&wasm);
         let mut section_code: Option<wasmparser::SectionCode> = None;
+        let mut section_entry_start = 0;
+        let mut section_begun = false;
+        let mut next_input = wasmparser::ParserInput::Default;
         while !parser.eof() {
-            let state = parser.read();
+            // The position before reading a state is where the entry it describes begins. The parser only starts
+            // tracking positions inside a section once its first entry is read, until then it reports the position of
+            // the previous section, or the end of the section when the previous one was skipped.
+            let offset = if section_begun {
+                section_entry_start
+            } else {
+                std::cmp::max(parser.current_position(), section_entry_start)
+            };
+            section_begun = false;
+            let state = parser.read_with_input(next_input);
+            next_input = wasmparser::ParserInput::Default;
+            let section = section_code.as_ref().map(section_name).unwrap_or("module");
+            let at = |index: usize| Location {
+                section,
+                index: Some(index as u32),
+                offset,
+            };
             match *state {
                 wasmparser::ParserState::StartSectionEntry(function_index) => {
                     wasm_module.start = Some(function_index);
                 }
-                wasmparser::ParserState::BeginSection { code, .. } => {
+                wasmparser::ParserState::BeginSection { code, range } => {
                     section_code = Some(code);
+                    // Skip the leb128 encoded entry count.
+                    let count_len = wasm[range.start..range.end]
+                        .iter()
+                        .position(|e| e & 0x80 == 0)
+                        .map_or(0, |e| e + 1);
+                    section_entry_start = range.start + count_len;
+                    section_begun = true;
+                    match code {
+                        wasmparser::SectionCode::Global => {
+                            wasm_module.read_global_section(&wasm[range.start..range.end], range.start)?;
+                            next_input = wasmparser::ParserInput::SkipSection;
+                        }
+                        wasmparser::SectionCode::Element => {
+                            wasm_module.read_element_section(&wasm[range.start..range.end], range.start)?;
+                            next_input = wasmparser::ParserInput::SkipSection;
+                        }
+                        wasmparser::SectionCode::Memory => {
+                            wasm_module.read_memory_section(&wasm[range.start..range.end], range.start)?;
+                            next_input = wasmparser::ParserInput::SkipSection;
+                        }
+                        wasmparser::SectionCode::Import => {
+                            wasm_module.read_import_section(&wasm[range.start..range.end], range.start)?;
+                            next_input = wasmparser::ParserInput::SkipSection;
+                        }
+                        wasmparser::SectionCode::Export => {
+                            wasm_module.read_export_section(&wasm[range.start..range.end], range.start)?;
+                            next_input = wasmparser::ParserInput::SkipSection;
+                        }
+                        _ => {}
+                    }
                 }
                 wasmparser::ParserState::EndSection => {
                     section_code = None;
+                    section_entry_start = 0;
                 }
                 wasmparser::ParserState::SectionRawData(data) => {
                     if let Some(wasmparser::SectionCode::Custom { name, .. }) = section_code {
@@ -124,107 +344,490 @@ impl Module {
                 wasmparser::ParserState::TypeSectionEntry(ref func_type) => {
                     wasm_module.type_list.push(func_type.clone());
                 }
-                wasmparser::ParserState::FunctionSectionEntry(func_type_index) => {
-                    wasm_module.function_list.push(func_type_index);
+                wasmparser::ParserState::FunctionSectionEntry(type_index) => {
+                    let location = at(wasm_module.function_list.len());
+                    wasm_module.function_list.push(Function { type_index, location });
+                }
+                // Function bodies are already compiled by WAVM, there is no need to decode them again.
+                wasmparser::ParserState::BeginFunctionBody { .. } => {
+                    next_input = wasmparser::ParserInput::SkipFunctionBody;
                 }
                 wasmparser::ParserState::TableSectionEntry(table_type) => {
                     wasm_module.table_list.push(table_type);
                 }
-                wasmparser::ParserState::MemorySectionEntry(memory_type) => {
-                    wasm_module.memory_list.push(memory_type);
-                }
-                wasmparser::ParserState::BeginGlobalSectionEntry(global_type) => {
-                    wasm_module.global_list.push(Global {
-                        global_type: global_type,
-                        expr: None,
-                    });
-                }
-                wasmparser::ParserState::EndGlobalSectionEntry => {}
-                wasmparser::ParserState::InitExpressionOperator(ref value) => match section_code {
-                    Some(wasmparser::SectionCode::Global) => {
-                        wasm_module.global_list.last_mut().unwrap().expr = Some(value.clone().into())
-                    }
-                    Some(wasmparser::SectionCode::Data) => {
-                        wasm_module.data_list.last_mut().unwrap().offset = Some(value.clone().into())
+                wasmparser::ParserState::InitExpressionOperator(ref value) => {
+                    if let Some(wasmparser::SectionCode::Data) = section_code {
+                        let e = last_entry(&mut wasm_module.data_list)?;
+                        let operator = ConstantOperator::try_from(value.clone()).map_err(|err| err.at(&e.location))?;
+                        e.offset.get_or_insert_with(Vec::new).push(operator);
                     }
-                    Some(wasmparser::SectionCode::Element) => {
-                        wasm_module.element_list.last_mut().unwrap().offset = Some(value.clone().into())
-                    }
-                    _ => {}
-                },
+                }
                 wasmparser::ParserState::BeginActiveDataSectionEntry(memory_index) => {
                     wasm_module.data_list.push(Data {
-                        memory_index: memory_index,
+                        memory_index,
                         offset: None,
                         init: vec![],
+                        location: at(wasm_module.data_list.len()),
                     });
                 }
-                wasmparser::ParserState::EndDataSectionEntry => {}
-                wasmparser::ParserState::DataSectionEntryBodyChunk(init) => {
-                    wasm_module.data_list.last_mut().unwrap().init = init.to_vec();
-                }
-                wasmparser::ParserState::BeginElementSectionEntry {
-                    table: wasmparser::ElemSectionEntryTable::Active(table_index),
-                    ty: wasmparser::Type::AnyFunc,
-                } => {
-                    wasm_module.element_list.push(Element {
-                        table_index: table_index,
+                wasmparser::ParserState::BeginPassiveDataSectionEntry => {
+                    wasm_module.data_list.push(Data {
+                        memory_index: 0,
                         offset: None,
                         init: vec![],
+                        location: at(wasm_module.data_list.len()),
                     });
                 }
-                wasmparser::ParserState::EndElementSectionEntry => {}
-                wasmparser::ParserState::ElementSectionEntryBody(ref element_list) => {
-                    for e in element_list.iter() {
-                        match e {
-                            wasmparser::ElementItem::Null => {
-                                wasm_module
-                                    .element_list
-                                    .last_mut()
-                                    .unwrap()
-                                    .init
-                                    .push(wasmparser::ElementItem::Null);
-                            }
-                            wasmparser::ElementItem::Func(func_index) => {
-                                wasm_module
-                                    .element_list
-                                    .last_mut()
-                                    .unwrap()
-                                    .init
-                                    .push(wasmparser::ElementItem::Func(*func_index));
-                            }
-                        }
-                    }
+                wasmparser::ParserState::EndDataSectionEntry => {}
+                wasmparser::ParserState::DataSectionEntryBodyChunk(init) => {
+                    last_entry(&mut wasm_module.data_list)?.init = init.to_vec();
                 }
-                wasmparser::ParserState::ImportSectionEntry { module, field, ty } => {
-                    wasm_module.import_list.push(Import {
-                        module: module.to_string(),
-                        field: field.to_string(),
-                        ty: ty,
-                    });
+                wasmparser::ParserState::NameSectionEntry(wasmparser::NameEntry::Function(ref naming_list)) => {
+                    for e in naming_list.iter() {
+                        wasm_module.function_name_map.insert(e.index, e.name.to_string());
+                    }
                 }
-                wasmparser::ParserState::ExportSectionEntry { field, kind, index } => {
-                    wasm_module.export_list.push(Export {
-                        field: field.to_string(),
-                        kind,
-                        index,
-                    });
+                wasmparser::ParserState::Error(ref err) => {
+                    return Err(WascError::malformed(err.message()).at(&Location {
+                        section,
+                        index: None,
+                        offset: err.offset(),
+                    }));
                 }
-                wasmparser::ParserState::Error(ref err) => panic!("{:?}", err),
                 _ => {}
             }
         }
-        wasm_module
+        Ok(wasm_module)
+    }
+
+    // Decode the global section, see read_constant_expression.
+    fn read_global_section(&mut self, data: &[u8], offset: usize) -> Result<(), WascError> {
+        let mut reader = wasmparser::BinaryReader::new_with_offset(data, offset);
+        let count = reader.read_var_u32().map_err(|err| {
+            reader_error(err).at(&Location {
+                section: "global",
+                index: None,
+                offset,
+            })
+        })?;
+        for i in 0..count {
+            let location = Location {
+                section: "global",
+                index: Some(i),
+                offset: reader.original_position(),
+            };
+            let global_type = read_global_type(&mut reader).map_err(|err| err.at(&location))?;
+            let expr = read_constant_expression(&mut reader).map_err(|err| err.at(&location))?;
+            self.global_list.push(Global {
+                global_type,
+                expr,
+                location,
+            });
+        }
+        Ok(())
+    }
+
+    // Decode the element section, see read_constant_expression. The lowest bit of the flags of a segment tells if it
+    // is active, the second one if an active segment gives its table index, or else if the segment is declarative, and
+    // the third one if the elements are expressions instead of function indexes.
+    fn read_element_section(&mut self, data: &[u8], offset: usize) -> Result<(), WascError> {
+        let mut reader = wasmparser::BinaryReader::new_with_offset(data, offset);
+        let count = reader.read_var_u32().map_err(|err| {
+            reader_error(err).at(&Location {
+                section: "element",
+                index: None,
+                offset,
+            })
+        })?;
+        for i in 0..count {
+            let location = Location {
+                section: "element",
+                index: Some(i),
+                offset: reader.original_position(),
+            };
+            let flags = reader.read_var_u32().map_err(|err| reader_error(err).at(&location))?;
+            if flags > 7 {
+                return Err(WascError::malformed(format!("element segment flags {}", flags)).at(&location));
+            }
+            let active = flags & 1 == 0;
+            let table_index = if active && flags & 2 != 0 {
+                reader.read_var_u32().map_err(|err| reader_error(err).at(&location))?
+            } else {
+                0
+            };
+            let offset = if active {
+                Some(read_constant_expression(&mut reader).map_err(|err| err.at(&location))?)
+            } else {
+                None
+            };
+            let exprs = flags & 4 != 0;
+            if flags & 3 != 0 {
+                // The element type, or the element kind, which is always 0 for functions.
+                if exprs {
+                    reader.read_type().map_err(|err| reader_error(err).at(&location))?;
+                } else {
+                    reader.read_u8().map_err(|err| reader_error(err).at(&location))?;
+                }
+            }
+            let item_count = reader.read_var_u32().map_err(|err| reader_error(err).at(&location))?;
+            let mut init = vec![];
+            for _ in 0..item_count {
+                let item = if exprs {
+                    match read_constant_expression(&mut reader).map_err(|err| err.at(&location))?[..] {
+                        [ConstantOperator::RefNull { .. }] => wasmparser::ElementItem::Null,
+                        [ConstantOperator::RefFunc { function_index }] => wasmparser::ElementItem::Func(function_index),
+                        ref e => {
+                            let feature = format!("{:?} in element segment", e);
+                            return Err(WascError::unsupported(feature).at(&location));
+                        }
+                    }
+                } else {
+                    let function_index = reader.read_var_u32().map_err(|err| reader_error(err).at(&location))?;
+                    wasmparser::ElementItem::Func(function_index)
+                };
+                init.push(item);
+            }
+            // Declarative segments only declare the functions which ref.func refers to, they behave like dropped
+            // passive segments.
+            if flags & 3 == 3 {
+                init.clear();
+            }
+            self.element_list.push(Element {
+                table_index,
+                offset,
+                init,
+                location,
+            });
+        }
+        Ok(())
+    }
+
+    // Decode the import section. wasmparser 0.51 fails on tag imports, whose kind is 4.
+    fn read_import_section(&mut self, data: &[u8], offset: usize) -> Result<(), WascError> {
+        let mut reader = wasmparser::BinaryReader::new_with_offset(data, offset);
+        let count = reader.read_var_u32().map_err(|err| {
+            reader_error(err).at(&Location {
+                section: "import",
+                index: None,
+                offset,
+            })
+        })?;
+        for i in 0..count {
+            let location = Location {
+                section: "import",
+                index: Some(i),
+                offset: reader.original_position(),
+            };
+            let module = reader.read_string().map_err(|err| reader_error(err).at(&location))?;
+            let field = reader.read_string().map_err(|err| reader_error(err).at(&location))?;
+            let ty = match reader.read_u8().map_err(|err| reader_error(err).at(&location))? {
+                0 => ImportKind::Function(reader.read_var_u32().map_err(|err| reader_error(err).at(&location))?),
+                1 => ImportKind::Table(read_table_type(&mut reader).map_err(|err| err.at(&location))?),
+                2 => ImportKind::Memory(read_memory_type(&mut reader).map_err(|err| err.at(&location))?),
+                3 => ImportKind::Global(read_global_type(&mut reader).map_err(|err| err.at(&location))?),
+                4 => ImportKind::Tag(read_tag_type(&mut reader).map_err(|err| err.at(&location))?),
+                e => return Err(WascError::malformed(format!("import kind {}", e)).at(&location)),
+            };
+            self.import_list.push(Import {
+                module: module.to_string(),
+                field: field.to_string(),
+                ty,
+                location,
+            });
+        }
+        Ok(())
+    }
+
+    // Decode the export section, see read_import_section.
+    fn read_export_section(&mut self, data: &[u8], offset: usize) -> Result<(), WascError> {
+        let mut reader = wasmparser::BinaryReader::new_with_offset(data, offset);
+        let count = reader.read_var_u32().map_err(|err| {
+            reader_error(err).at(&Location {
+                section: "export",
+                index: None,
+                offset,
+            })
+        })?;
+        for i in 0..count {
+            let location = Location {
+                section: "export",
+                index: Some(i),
+                offset: reader.original_position(),
+            };
+            let field = reader.read_string().map_err(|err| reader_error(err).at(&location))?;
+            let kind = match reader.read_u8().map_err(|err| reader_error(err).at(&location))? {
+                0 => ExternalKind::Function,
+                1 => ExternalKind::Table,
+                2 => ExternalKind::Memory,
+                3 => ExternalKind::Global,
+                4 => ExternalKind::Tag,
+                e => return Err(WascError::malformed(format!("export kind {}", e)).at(&location)),
+            };
+            let index = reader.read_var_u32().map_err(|err| reader_error(err).at(&location))?;
+            self.export_list.push(Export {
+                field: field.to_string(),
+                kind,
+                index,
+            });
+        }
+        Ok(())
+    }
+
+    // Decode the memory section, see read_import_section. wasmparser 0.51 fails on 64-bit memories.
+    fn read_memory_section(&mut self, data: &[u8], offset: usize) -> Result<(), WascError> {
+        let mut reader = wasmparser::BinaryReader::new_with_offset(data, offset);
+        let count = reader.read_var_u32().map_err(|err| {
+            reader_error(err).at(&Location {
+                section: "memory",
+                index: None,
+                offset,
+            })
+        })?;
+        for i in 0..count {
+            let location = Location {
+                section: "memory",
+                index: Some(i),
+                offset: reader.original_position(),
+            };
+            let memory_type = read_memory_type(&mut reader).map_err(|err| err.at(&location))?;
+            self.memory_list.push(Memory { memory_type, location });
+        }
+        Ok(())
+    }
+
+    // Decode the tag section, see Module::from.
+    fn read_tag_section(&mut self, data: &[u8], offset: usize) -> Result<(), WascError> {
+        let mut reader = wasmparser::BinaryReader::new_with_offset(data, offset);
+        let count = reader.read_var_u32().map_err(|err| {
+            reader_error(err).at(&Location {
+                section: "tag",
+                index: None,
+                offset,
+            })
+        })?;
+        for i in 0..count {
+            let location = Location {
+                section: "tag",
+                index: Some(i),
+                offset: reader.original_position(),
+            };
+            let type_index = read_tag_type(&mut reader).map_err(|err| err.at(&location))?;
+            self.tag_list.push(Tag { type_index, location });
+        }
+        Ok(())
+    }
+
+    // Location of a function in the function index space, imported functions come first.
+    fn function_location(&self, function_index: u32) -> Option<&Location> {
+        let import_location: Vec<&Location> = self
+            .import_list
+            .iter()
+            .filter(|e| matches!(e.ty, ImportKind::Function(_)))
+            .map(|e| &e.location)
+            .collect();
+        let index = function_index as usize;
+        if index < import_location.len() {
+            return Some(import_location[index]);
+        }
+        self.function_list
+            .get(index - import_location.len())
+            .map(|e| &e.location)
+    }
+
+    // Location of a global in the global index space, imported globals come first.
+    fn global_location(&self, global_index: u32) -> Option<&Location> {
+        let import_location: Vec<&Location> = self
+            .import_list
+            .iter()
+            .filter(|e| matches!(e.ty, ImportKind::Global(_)))
+            .map(|e| &e.location)
+            .collect();
+        let index = global_index as usize;
+        if index < import_location.len() {
+            return Some(import_location[index]);
+        }
+        self.global_list.get(index - import_location.len()).map(|e| &e.location)
+    }
+
+    // Location of a tag in the tag index space, imported tags come first.
+    fn tag_location(&self, tag_index: u32) -> Option<&Location> {
+        let import_location: Vec<&Location> = self
+            .import_list
+            .iter()
+            .filter(|e| matches!(e.ty, ImportKind::Tag(_)))
+            .map(|e| &e.location)
+            .collect();
+        let index = tag_index as usize;
+        if index < import_location.len() {
+            return Some(import_location[index]);
+        }
+        self.tag_list.get(index - import_location.len()).map(|e| &e.location)
+    }
+
+    // Location of a memory in the memory index space, imported memories come first.
+    fn memory_location(&self, memory_index: u32) -> Option<&Location> {
+        let import_location: Vec<&Location> = self
+            .import_list
+            .iter()
+            .filter(|e| matches!(e.ty, ImportKind::Memory(_)))
+            .map(|e| &e.location)
+            .collect();
+        let index = memory_index as usize;
+        if index < import_location.len() {
+            return Some(import_location[index]);
+        }
+        self.memory_list.get(index - import_location.len()).map(|e| &e.location)
+    }
+}
+
+// Find the section with the given id. Return the offset of its id, and the range of its content.
+fn find_section(wasm: &[u8], id: u32) -> Result<Option<(usize, std::ops::Range<usize>)>, WascError> {
+    if wasm.len() < 8 {
+        return Ok(None);
+    }
+    let mut reader = wasmparser::BinaryReader::new_with_offset(&wasm[8..], 8);
+    while !reader.eof() {
+        let location = Location {
+            section: "module",
+            index: None,
+            offset: reader.original_position(),
+        };
+        let section_id = reader.read_u8().map_err(|err| reader_error(err).at(&location))?;
+        let size = reader.read_var_u32().map_err(|err| reader_error(err).at(&location))? as usize;
+        let start = reader.original_position();
+        if start + size > wasm.len() {
+            return Err(WascError::malformed("section size out of bounds").at(&location));
+        }
+        if section_id == id {
+            if size == 0 {
+                return Err(WascError::malformed("empty section").at(&location));
+            }
+            return Ok(Some((location.offset, start..start + size)));
+        }
+        reader.skip_bytes(size).map_err(|err| reader_error(err).at(&location))?;
+    }
+    Ok(None)
+}
+
+// The flags of limits tell if there is a maximum.
+fn read_limits(reader: &mut wasmparser::BinaryReader) -> Result<(u32, wasmparser::ResizableLimits), WascError> {
+    let flags = reader.read_var_u32().map_err(reader_error)?;
+    let initial = reader.read_var_u32().map_err(reader_error)?;
+    let maximum = if flags & 1 != 0 {
+        Some(reader.read_var_u32().map_err(reader_error)?)
+    } else {
+        None
+    };
+    Ok((flags, wasmparser::ResizableLimits { initial, maximum }))
+}
+
+fn read_table_type(reader: &mut wasmparser::BinaryReader) -> Result<wasmparser::TableType, WascError> {
+    let element_type = reader.read_type().map_err(reader_error)?;
+    let (flags, limits) = read_limits(reader)?;
+    if flags > 1 {
+        return Err(WascError::malformed(format!("table limits flags {}", flags)));
+    }
+    Ok(wasmparser::TableType { element_type, limits })
+}
+
+// The limits of a 64-bit memory, whose flags have the bit 2 set, are u64.
+fn read_memory_type(reader: &mut wasmparser::BinaryReader) -> Result<MemoryType, WascError> {
+    let flags = reader.read_var_u32().map_err(reader_error)?;
+    if flags > 7 {
+        return Err(WascError::malformed(format!("memory limits flags {}", flags)));
+    }
+    let memory64 = flags & 4 != 0;
+    let mut read_limit = || {
+        if memory64 {
+            read_var_u64(reader)
+        } else {
+            reader.read_var_u32().map(u64::from).map_err(reader_error)
+        }
+    };
+    let initial = read_limit()?;
+    let maximum = if flags & 1 != 0 { Some(read_limit()?) } else { None };
+    Ok(MemoryType {
+        initial,
+        maximum,
+        shared: flags & 2 != 0,
+    })
+}
+
+// wasmparser 0.51 has no read_var_u64.
+fn read_var_u64(reader: &mut wasmparser::BinaryReader) -> Result<u64, WascError> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = reader.read_u8().map_err(reader_error)?;
+        if shift == 63 && byte > 1 {
+            return Err(WascError::malformed("integer too large"));
+        }
+        result |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+fn read_global_type(reader: &mut wasmparser::BinaryReader) -> Result<wasmparser::GlobalType, WascError> {
+    let content_type = reader.read_type().map_err(reader_error)?;
+    let mutable = reader.read_u8().map_err(reader_error)? != 0;
+    Ok(wasmparser::GlobalType { content_type, mutable })
+}
+
+// A tag type is an attribute, which is always 0 for exceptions, and the index of a function type.
+fn read_tag_type(reader: &mut wasmparser::BinaryReader) -> Result<u32, WascError> {
+    let attribute = reader.read_u8().map_err(reader_error)?;
+    if attribute != 0 {
+        return Err(WascError::malformed(format!("tag attribute {}", attribute)));
+    }
+    reader.read_var_u32().map_err(reader_error)
+}
+
+// Returns the entry the parser is currently filling, which wasmparser always begins before its content.
+fn last_entry<T>(list: &mut [T]) -> Result<&mut T, WascError> {
+    list.last_mut()
+        .ok_or_else(|| WascError::Internal(String::from("section entry content without a beginning")))
+}
+
+// The name of a section as it appears in the spec.
+fn section_name(code: &wasmparser::SectionCode) -> &'static str {
+    match code {
+        wasmparser::SectionCode::Custom { .. } => "custom",
+        wasmparser::SectionCode::Type => "type",
+        wasmparser::SectionCode::Import => "import",
+        wasmparser::SectionCode::Function => "function",
+        wasmparser::SectionCode::Table => "table",
+        wasmparser::SectionCode::Memory => "memory",
+        wasmparser::SectionCode::Global => "global",
+        wasmparser::SectionCode::Export => "export",
+        wasmparser::SectionCode::Start => "start",
+        wasmparser::SectionCode::Element => "element",
+        wasmparser::SectionCode::Code => "code",
+        wasmparser::SectionCode::Data => "data",
+        wasmparser::SectionCode::DataCount => "data count",
     }
 }
 
 // Values are represented by themselves.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum Value {
     I32(i32),
     I64(i64),
     F32(u32),
     F64(u64),
+    V128(u128),
+    RefNull,
+    RefFunc(u32),
+}
+
+// The value of a constant expression. Expressions which read an imported global are only known at runtime, they are
+// kept as the C expression that computes them, along with their type.
+#[derive(Clone, Debug)]
+enum Constant {
+    Static(Value),
+    Dynamic(wasmparser::Type, String),
 }
 
 // A global instance is the runtime representation of a global variable. It holds an individual value and a flag
@@ -233,7 +836,7 @@ enum Value {
 enum GlobalInstance {
     Wasm {
         global_type: wasmparser::GlobalType,
-        value: Value,
+        value: Constant,
     },
     Host {
         global_type: wasmparser::GlobalType,
@@ -261,11 +864,11 @@ enum FunctionInstance {
 #[derive(Debug)]
 enum MemoryInstance {
     Wasm {
-        memory_type: wasmparser::MemoryType,
+        memory_type: MemoryType,
         data: Vec<Data>,
     },
     Host {
-        memory_type: wasmparser::MemoryType,
+        memory_type: MemoryType,
         data: Vec<Data>,
         import_name: String,
     },
@@ -286,6 +889,19 @@ enum TableInstance {
     },
 }
 
+// A tag instance is the runtime representation of an exception tag. Tags are never modified, they are shared by all
+// instances of the module.
+#[derive(Debug)]
+enum TagInstance {
+    Wasm {
+        tag_type: wasmparser::FuncType,
+    },
+    Host {
+        tag_type: wasmparser::FuncType,
+        import_name: String,
+    },
+}
+
 // The store represents all global state that can be manipulated by WebAssembly programs. It consists of the runtime
 // representation of all instances of functions, tables, memories, and globals that have been allocated during the
 // life time of the abstract machine Syntactically.
@@ -297,6 +913,7 @@ struct Store {
     table_list: Vec<TableInstance>,
     memory_list: Vec<MemoryInstance>,
     global_list: Vec<GlobalInstance>,
+    tag_list: Vec<TagInstance>,
 }
 
 impl Store {
@@ -323,6 +940,12 @@ impl Store {
         self.global_list.push(global_instance);
         global_addr
     }
+
+    fn allocate_tag(&mut self, tag_instance: TagInstance) -> u32 {
+        let tag_addr = self.tag_list.len() as u32;
+        self.tag_list.push(tag_instance);
+        tag_addr
+    }
 }
 
 // A module instance is the runtime representation of a module. It is created by instantiating a module, and
@@ -334,108 +957,110 @@ struct ModuleInstance {
     table_addr_list: Vec<u32>,
     memory_addr_list: Vec<u32>,
     global_addr_list: Vec<u32>,
+    tag_addr_list: Vec<u32>,
     export_list: Vec<Export>,
 }
 
 impl ModuleInstance {
-    fn from(module: &Module, store: &mut Store) -> Self {
-        let mut module_instance = ModuleInstance::default();
-        module_instance.type_list = module.type_list.clone();
-        module_instance.export_list = module.export_list.clone();
+    fn from(module: &Module, store: &mut Store) -> Result<Self, WascError> {
+        let mut module_instance = ModuleInstance {
+            type_list: module.type_list.clone(),
+            export_list: module.export_list.clone(),
+            ..Default::default()
+        };
         // Handle import
         for e in &module.import_list {
+            // Host symbols are C identifiers, so that wasi::thread-spawn is found as wavm_wasi_thread_spawn.
             let import_name = if e.module == "wasi_unstable" || e.module == "wasi_snapshot_preview1" {
-                format!("wasi_{}", e.field)
+                format!("wasi_{}", cnaming(&e.field))
             } else {
-                format!("{}_{}", e.module, e.field)
+                format!("{}_{}", cnaming(&e.module), cnaming(&e.field))
             };
             match e.ty {
-                wasmparser::ImportSectionEntryType::Function(function_type_index) => {
+                ImportKind::Function(function_type_index) => {
                     let function_type = &module_instance.type_list[function_type_index as usize];
                     let function_addr = store.allocate_function(FunctionInstance::HostFunc {
                         function_type: function_type.clone(),
-                        import_name: import_name,
+                        import_name,
                     });
                     module_instance.function_addr_list.push(function_addr);
                 }
-                wasmparser::ImportSectionEntryType::Memory(memory_type) => {
+                ImportKind::Memory(memory_type) => {
                     let memory_addr = store.allocate_memory(MemoryInstance::Host {
-                        memory_type: memory_type,
+                        memory_type,
                         data: vec![],
-                        import_name: import_name,
+                        import_name,
                     });
                     module_instance.memory_addr_list.push(memory_addr);
                 }
-                wasmparser::ImportSectionEntryType::Table(table_type) => {
+                ImportKind::Table(table_type) => {
                     let table_addr = store.allocate_table(TableInstance::Host {
-                        table_type: table_type,
+                        table_type,
                         element_list: vec![],
-                        import_name: import_name,
+                        import_name,
                     });
                     module_instance.table_addr_list.push(table_addr);
                 }
-                wasmparser::ImportSectionEntryType::Global(global_type) => {
+                ImportKind::Global(global_type) => {
                     let global_addr = store.allocate_global(GlobalInstance::Host {
-                        global_type: global_type,
-                        import_name: import_name,
+                        global_type,
+                        import_name,
                     });
                     module_instance.global_addr_list.push(global_addr);
                 }
+                ImportKind::Tag(type_index) => {
+                    let tag_type = &module_instance.type_list[type_index as usize];
+                    let tag_addr = store.allocate_tag(TagInstance::Host {
+                        tag_type: tag_type.clone(),
+                        import_name,
+                    });
+                    module_instance.tag_addr_list.push(tag_addr);
+                }
             }
         }
-        // Let vals be the vector of global initialization values.
+        // Let vals be the vector of global initialization values. Initializers may read the imported globals and the
+        // globals defined before them.
         for e in &module.global_list {
-            match e.global_type.content_type {
-                wasmparser::Type::I32 => {
-                    if let Some(ConstantOperator::I32Const { value }) = e.expr {
-                        let global_addr = store.allocate_global(GlobalInstance::Wasm {
-                            global_type: e.global_type,
-                            value: Value::I32(value),
-                        });
-                        module_instance.global_addr_list.push(global_addr);
-                    } else {
-                        panic!("unreachable");
-                    }
-                }
-                wasmparser::Type::I64 => {
-                    if let Some(ConstantOperator::I64Const { value }) = e.expr {
-                        let global_addr = store.allocate_global(GlobalInstance::Wasm {
-                            global_type: e.global_type,
-                            value: Value::I64(value),
-                        });
-                        module_instance.global_addr_list.push(global_addr);
-                    } else {
-                        panic!("unreachable");
-                    }
-                }
-                wasmparser::Type::F32 => {
-                    if let Some(ConstantOperator::F32Const { value }) = e.expr {
-                        let global_addr = store.allocate_global(GlobalInstance::Wasm {
-                            global_type: e.global_type,
-                            value: Value::F32(value),
-                        });
-                        module_instance.global_addr_list.push(global_addr);
-                    } else {
-                        panic!("unreachable");
-                    }
-                }
-                wasmparser::Type::F64 => {
-                    if let Some(ConstantOperator::F64Const { value }) = e.expr {
-                        let global_addr = store.allocate_global(GlobalInstance::Wasm {
-                            global_type: e.global_type,
-                            value: Value::F64(value),
-                        });
-                        module_instance.global_addr_list.push(global_addr);
-                    } else {
-                        panic!("unreachable");
-                    }
+            let value = module_instance
+                .eval_constant(store, &e.expr)
+                .map_err(|err| err.at(&e.location))?;
+            match (e.global_type.content_type, &value) {
+                (wasmparser::Type::I32, Constant::Static(Value::I32(_)))
+                | (wasmparser::Type::I64, Constant::Static(Value::I64(_)))
+                | (wasmparser::Type::F32, Constant::Static(Value::F32(_)))
+                | (wasmparser::Type::F64, Constant::Static(Value::F64(_)))
+                | (wasmparser::Type::V128, Constant::Static(Value::V128(_)))
+                | (wasmparser::Type::AnyFunc, Constant::Static(Value::RefNull))
+                | (wasmparser::Type::AnyRef, Constant::Static(Value::RefNull))
+                | (wasmparser::Type::AnyFunc, Constant::Static(Value::RefFunc(_))) => {}
+                (wasmparser::Type::I32, Constant::Dynamic(ty, _))
+                | (wasmparser::Type::I64, Constant::Dynamic(ty, _))
+                | (wasmparser::Type::F32, Constant::Dynamic(ty, _))
+                | (wasmparser::Type::F64, Constant::Dynamic(ty, _))
+                | (wasmparser::Type::V128, Constant::Dynamic(ty, _))
+                | (wasmparser::Type::AnyFunc, Constant::Dynamic(ty, _))
+                | (wasmparser::Type::AnyRef, Constant::Dynamic(ty, _))
+                    if *ty == e.global_type.content_type => {}
+                (wasmparser::Type::I32, _)
+                | (wasmparser::Type::I64, _)
+                | (wasmparser::Type::F32, _)
+                | (wasmparser::Type::F64, _)
+                | (wasmparser::Type::V128, _)
+                | (wasmparser::Type::AnyFunc, _)
+                | (wasmparser::Type::AnyRef, _) => {
+                    return Err(WascError::unsupported(format!("global initializer {:?}", e.expr)).at(&e.location));
                 }
-                _ => panic!("unreachable"),
-            }
+                (t, _) => return Err(WascError::unsupported(format!("global of type {:?}", t)).at(&e.location)),
+            };
+            let global_addr = store.allocate_global(GlobalInstance::Wasm {
+                global_type: e.global_type,
+                value,
+            });
+            module_instance.global_addr_list.push(global_addr);
         }
         // Allocate each function in module.function_list
         for e in &module.function_list {
-            let function_type = &module_instance.type_list[*e as usize];
+            let function_type = &module_instance.type_list[e.type_index as usize];
             let function_addr = store.allocate_function(FunctionInstance::WasmFunc {
                 function_type: function_type.clone(),
             });
@@ -452,12 +1077,162 @@ impl ModuleInstance {
         // Allocate each memory in module.memory_list
         for e in &module.memory_list {
             let memory_addr = store.allocate_memory(MemoryInstance::Wasm {
-                memory_type: *e,
+                memory_type: e.memory_type,
                 data: vec![],
             });
             module_instance.memory_addr_list.push(memory_addr);
         }
-        module_instance
+        // Allocate each tag in module.tag_list
+        for e in &module.tag_list {
+            let tag_type = &module_instance.type_list[e.type_index as usize];
+            let tag_addr = store.allocate_tag(TagInstance::Wasm {
+                tag_type: tag_type.clone(),
+            });
+            module_instance.tag_addr_list.push(tag_addr);
+        }
+        Ok(module_instance)
+    }
+
+    // Evaluate a constant expression. Integer arithmetic wraps around like the instructions, it is folded unless it
+    // reads an imported global.
+    fn eval_constant(&self, store: &Store, expr: &[ConstantOperator]) -> Result<Constant, WascError> {
+        let mut stack: Vec<Constant> = vec![];
+        for e in expr {
+            let constant = match e {
+                ConstantOperator::I32Const { value } => Constant::Static(Value::I32(*value)),
+                ConstantOperator::I64Const { value } => Constant::Static(Value::I64(*value)),
+                ConstantOperator::F32Const { value } => Constant::Static(Value::F32(*value)),
+                ConstantOperator::F64Const { value } => Constant::Static(Value::F64(*value)),
+                ConstantOperator::V128Const { value } => Constant::Static(Value::V128(*value)),
+                ConstantOperator::RefNull { .. } => Constant::Static(Value::RefNull),
+                ConstantOperator::RefFunc { function_index } => Constant::Static(Value::RefFunc(*function_index)),
+                ConstantOperator::GlobalGet { global_index } => {
+                    let global_addr = *self
+                        .global_addr_list
+                        .get(*global_index as usize)
+                        .ok_or_else(|| WascError::malformed(format!("unknown global {}", global_index)))?;
+                    match &store.global_list[global_addr as usize] {
+                        GlobalInstance::Wasm { global_type, .. } if global_type.mutable => {
+                            let reason =
+                                format!("global.get of mutable global {} in constant expression", global_index);
+                            return Err(WascError::malformed(reason));
+                        }
+                        // The global is initialized at runtime before the globals that come after it.
+                        GlobalInstance::Wasm {
+                            global_type,
+                            value: Constant::Dynamic(..),
+                        } => Constant::Dynamic(global_type.content_type, get_external_name("global", global_addr)),
+                        GlobalInstance::Wasm { value, .. } => value.clone(),
+                        GlobalInstance::Host {
+                            global_type,
+                            import_name,
+                        } => Constant::Dynamic(global_type.content_type, format!("wavm_{}", import_name)),
+                    }
+                }
+                ConstantOperator::I32Add
+                | ConstantOperator::I32Sub
+                | ConstantOperator::I32Mul
+                | ConstantOperator::I64Add
+                | ConstantOperator::I64Sub
+                | ConstantOperator::I64Mul => {
+                    let b = stack.pop();
+                    let a = stack.pop();
+                    match (a, b) {
+                        (Some(a), Some(b)) => eval_binary(e, a, b)?,
+                        _ => return Err(WascError::malformed(format!("{:?} on an empty stack", e))),
+                    }
+                }
+            };
+            stack.push(constant);
+        }
+        match stack.pop() {
+            Some(constant) if stack.is_empty() => Ok(constant),
+            _ => Err(WascError::malformed(format!("constant expression {:?}", expr))),
+        }
+    }
+
+    // Evaluate the offset expression of a data or element segment. Offsets that read an imported global are only
+    // known at runtime, they are returned as the C expression that computes them.
+    fn eval_offset(&self, store: &Store, expr: &Option<ConstantExpression>) -> Result<Offset, WascError> {
+        let expr = match expr {
+            Some(expr) => expr,
+            None => return Err(WascError::malformed("segment offset of None")),
+        };
+        match self.eval_constant(store, expr)? {
+            Constant::Static(Value::I32(value)) => Ok(Offset::Static(u64::from(value as u32))),
+            Constant::Static(Value::I64(value)) => Ok(Offset::Static(value as u64)),
+            Constant::Dynamic(wasmparser::Type::I32, value) => Ok(Offset::Dynamic(format!("(uint32_t) ({})", value))),
+            Constant::Dynamic(wasmparser::Type::I64, value) => Ok(Offset::Dynamic(format!("(uint64_t) ({})", value))),
+            e => Err(WascError::malformed(format!("segment offset of {:?}", e))),
+        }
+    }
+}
+
+// Evaluate an integer instruction of a constant expression. When an operand is only known at runtime, the result is the
+// C expression that computes it, unsigned so that it wraps around.
+fn eval_binary(operator: &ConstantOperator, a: Constant, b: Constant) -> Result<Constant, WascError> {
+    let (ty, signed, unsigned, symbol) = match operator {
+        ConstantOperator::I32Add => (wasmparser::Type::I32, "int32_t", "uint32_t", "+"),
+        ConstantOperator::I32Sub => (wasmparser::Type::I32, "int32_t", "uint32_t", "-"),
+        ConstantOperator::I32Mul => (wasmparser::Type::I32, "int32_t", "uint32_t", "*"),
+        ConstantOperator::I64Add => (wasmparser::Type::I64, "int64_t", "uint64_t", "+"),
+        ConstantOperator::I64Sub => (wasmparser::Type::I64, "int64_t", "uint64_t", "-"),
+        ConstantOperator::I64Mul => (wasmparser::Type::I64, "int64_t", "uint64_t", "*"),
+        e => return Err(WascError::Internal(format!("{:?} is not a binary instruction", e))),
+    };
+    let operand = |e: Constant| match e {
+        Constant::Static(Value::I32(value)) if ty == wasmparser::Type::I32 => Ok(format!("{}u", value as u32)),
+        Constant::Static(Value::I64(value)) if ty == wasmparser::Type::I64 => Ok(format!("{}ull", value as u64)),
+        Constant::Dynamic(t, value) if t == ty => Ok(value),
+        e => Err(WascError::malformed(format!("{:?} of {:?}", operator, e))),
+    };
+    match (operator, a, b) {
+        (ConstantOperator::I32Add, Constant::Static(Value::I32(a)), Constant::Static(Value::I32(b))) => {
+            Ok(Constant::Static(Value::I32(a.wrapping_add(b))))
+        }
+        (ConstantOperator::I32Sub, Constant::Static(Value::I32(a)), Constant::Static(Value::I32(b))) => {
+            Ok(Constant::Static(Value::I32(a.wrapping_sub(b))))
+        }
+        (ConstantOperator::I32Mul, Constant::Static(Value::I32(a)), Constant::Static(Value::I32(b))) => {
+            Ok(Constant::Static(Value::I32(a.wrapping_mul(b))))
+        }
+        (ConstantOperator::I64Add, Constant::Static(Value::I64(a)), Constant::Static(Value::I64(b))) => {
+            Ok(Constant::Static(Value::I64(a.wrapping_add(b))))
+        }
+        (ConstantOperator::I64Sub, Constant::Static(Value::I64(a)), Constant::Static(Value::I64(b))) => {
+            Ok(Constant::Static(Value::I64(a.wrapping_sub(b))))
+        }
+        (ConstantOperator::I64Mul, Constant::Static(Value::I64(a)), Constant::Static(Value::I64(b))) => {
+            Ok(Constant::Static(Value::I64(a.wrapping_mul(b))))
+        }
+        (_, a, b) => Ok(Constant::Dynamic(
+            ty,
+            format!(
+                "({}) (({}) ({}) {} ({}) ({}))",
+                signed,
+                unsigned,
+                operand(a)?,
+                symbol,
+                unsigned,
+                operand(b)?
+            ),
+        )),
+    }
+}
+
+// The offset of a data or element segment.
+#[derive(Debug)]
+enum Offset {
+    Static(u64),
+    Dynamic(String),
+}
+
+impl Offset {
+    fn emit(&self) -> String {
+        match self {
+            Offset::Static(value) => value.to_string(),
+            Offset::Dynamic(name) => name.clone(),
+        }
     }
 }
 
@@ -472,60 +1247,468 @@ pub fn cnaming(name: &str) -> String {
     for e in name.chars() {
         if e.is_ascii_alphanumeric() {
             new_name += &e.to_string();
-        } else if e == '_' {
-            new_name += "_";
-        } else if e == '-' {
+        } else if e == '_' || e == '-' {
             new_name += "_";
         } else {
-            new_name += &hex::encode(&e.to_string());
+            new_name += &hex::encode(e.to_string());
         }
     }
     new_name
 }
 
+// Quote a string as a C string literal. Octal escapes are used since they never take more than three digits.
+fn cstring(s: &str) -> String {
+    let mut r = String::from("\"");
+    for e in s.bytes() {
+        if e.is_ascii_graphic() && e != b'"' && e != b'\\' && e != b'?' || e == b' ' {
+            r.push(e as char);
+        } else {
+            r += &format!("\\{:03o}", e);
+        }
+    }
+    r.push('"');
+    r
+}
+
+// Reserve the names of the public functions of an export. Exports may clash with each other or with the fixed
+// functions of the public header, for example an export named "new", in which case "_" is appended to the name.
+fn reserve_api_name(api_name_list: &mut Vec<String>, mut name: String, suffix_list: &[&str]) -> String {
+    while suffix_list
+        .iter()
+        .any(|suffix| api_name_list.contains(&format!("{}{}", name, suffix)))
+    {
+        name.push('_');
+    }
+    for suffix in suffix_list {
+        api_name_list.push(format!("{}{}", name, suffix));
+    }
+    name
+}
+
 // Emit wasm type to c code.
-fn emit_type(t: wasmparser::Type) -> String {
+fn emit_type(t: wasmparser::Type) -> Result<String, WascError> {
     match t {
-        wasmparser::Type::I32 => "int32_t".to_string(),
-        wasmparser::Type::I64 => "int64_t".to_string(),
-        wasmparser::Type::F32 => "float".to_string(),
-        wasmparser::Type::F64 => "double".to_string(),
-        _ => panic!("unreachable"),
+        wasmparser::Type::I32 => Ok("int32_t".to_string()),
+        wasmparser::Type::I64 => Ok("int64_t".to_string()),
+        wasmparser::Type::F32 => Ok("float".to_string()),
+        wasmparser::Type::F64 => Ok("double".to_string()),
+        wasmparser::Type::AnyFunc => Ok("wasc_funcref".to_string()),
+        wasmparser::Type::AnyRef => Ok("wasc_externref".to_string()),
+        wasmparser::Type::V128 => Ok("wasc_v128".to_string()),
+        t => Err(WascError::unsupported(format!("value type {:?}", t))),
     }
 }
 
-// Emit wasm function type to c function signature.
-fn emit_function_signature(func_type: &wasmparser::FuncType, name: String) -> String {
-    if func_type.form != wasmparser::Type::Func || func_type.returns.len() > 1 {
-        panic!("unreachable");
+// Size of a value of a wasm type in the instance or in the context. References are pointers.
+fn type_size(t: wasmparser::Type) -> usize {
+    match t {
+        wasmparser::Type::I32 | wasmparser::Type::F32 => 4,
+        wasmparser::Type::V128 => 16,
+        _ => 8,
     }
-    let mut fields: Vec<String> = func_type.params.iter().map(|t| emit_type(*t)).collect();
-    fields.insert(0, "void*".to_string());
-    let return_type = if func_type.returns.len() > 0 {
-        format!("wavm_ret_{}", emit_type(func_type.returns[0]))
-    } else {
-        "void*".to_string()
-    };
-    format!("{} ({}) ({})", return_type, name, fields.join(", ")).to_string()
 }
 
-// Emit memory data with static/dynamic offset.
-fn emit_memory_data(mi: u32, di: u32, offset: &str, len: u32) -> String {
-    format!("memcpy(memory{} + {}, memory{}_data{}, {});", mi, offset, mi, di, len)
+// The initializer of a wasc_v128, its two 64 bits halves. The low half comes first on little endian machines.
+fn emit_v128(value: u128) -> String {
+    format!(
+        "{{(int64_t)0x{:016x}ull, (int64_t)0x{:016x}ull}}",
+        value as u64,
+        (value >> 64) as u64
+    )
 }
 
-pub fn generate(middle: &mut context::Middle) -> Result<(), Box<dyn std::error::Error>> {
-    let wasm_data: Vec<u8> = std::fs::read(middle.path_precompiled.to_str().unwrap())?;
-    let wasm_module = Module::from(wasm_data.clone());
-    let mut store = Store::default();
-    let wasm_instance = ModuleInstance::from(&wasm_module, &mut store);
+// The reference to a function, see wasc_funcref.
+fn emit_function_reference(element_name: &str) -> String {
+    format!("((wasc_funcref) ((uintptr_t) ({}) - 0x20))", element_name)
+}
 
-    let file_stem = middle.file_stem.clone();
-    // Save precompiled object.
-    let mut object_data: Vec<u8> = vec![];
-    for e in wasm_module.custom_list {
-        if e.name == "wavm.precompiled_object" {
-            object_data.extend_from_slice(&e.data);
+// The canonical IDs of the function types of a module. call_indirect compares the IDs of the expected type and of the
+// type of the function, so all the types with the same signature share an ID. IDs are dense and start at 1, zero is left
+// for the null element.
+struct TypeIdList {
+    // One function type of every signature, in the order of the type section.
+    signature_list: Vec<wasmparser::FuncType>,
+}
+
+impl TypeIdList {
+    fn new(type_list: &[wasmparser::FuncType]) -> Self {
+        let mut signature_list: Vec<wasmparser::FuncType> = vec![];
+        for e in type_list {
+            if !signature_list
+                .iter()
+                .any(|x| x.params == e.params && x.returns == e.returns)
+            {
+                signature_list.push(e.clone());
+            }
+        }
+        Self { signature_list }
+    }
+
+    // Every function type comes from the type section.
+    fn get(&self, function_type: &wasmparser::FuncType) -> usize {
+        let position = self
+            .signature_list
+            .iter()
+            .position(|x| x.params == function_type.params && x.returns == function_type.returns)
+            .unwrap();
+        position + 1
+    }
+}
+
+// Emit wasm function type to c function signature.
+fn emit_function_signature(func_type: &wasmparser::FuncType, name: String) -> Result<String, WascError> {
+    if func_type.form != wasmparser::Type::Func {
+        return Err(WascError::malformed(format!(
+            "function type of form {:?}",
+            func_type.form
+        )));
+    }
+    let mut fields: Vec<String> = func_type
+        .params
+        .iter()
+        .map(|t| emit_type(*t))
+        .collect::<Result<Vec<String>, WascError>>()?;
+    fields.insert(0, "void*".to_string());
+    let return_type = if !func_type.returns.is_empty() {
+        emit_results_type(&func_type.returns)?
+    } else {
+        "void*".to_string()
+    };
+    Ok(format!("{} ({}) ({})", return_type, name, fields.join(", ")))
+}
+
+// Emit the struct which holds the results of a function, for example wavm_ret_int32_t_int64_t.
+fn emit_results_type(returns: &[wasmparser::Type]) -> Result<String, WascError> {
+    let mut name = String::from("wavm_ret");
+    for t in returns {
+        name.push('_');
+        name.push_str(&emit_type(*t)?);
+    }
+    Ok(name)
+}
+
+// Multi-value functions. In C, they return all their results in a struct, like the functions with a single result.
+// WAVM compiled code returns up to two results in registers next to the context pointer, which no C struct matches,
+// and more results in the first bytes of the context: glue functions move the results between the two. A single v128
+// result goes the same way, C returns a struct which holds it in memory while WAVM compiled code returns it in xmm0.
+struct MultiValue<'a> {
+    // Name of the function in WAVM compiled code, functionDefN or functionImportN.
+    name: String,
+    func_type: &'a wasmparser::FuncType,
+    params: Vec<String>,
+    results: String,
+    // Offset and size of every result in the context.
+    layout: Vec<(usize, usize)>,
+}
+
+impl<'a> MultiValue<'a> {
+    fn needed(func_type: &wasmparser::FuncType) -> bool {
+        func_type.returns.len() > 1 || func_type.returns.contains(&wasmparser::Type::V128)
+    }
+
+    fn new(name: String, func_type: &'a wasmparser::FuncType) -> Result<Self, WascError> {
+        let mut params = vec![String::from("void *p0")];
+        for (i, t) in func_type.params.iter().enumerate() {
+            params.push(format!("{} p{}", emit_type(*t)?, i + 1));
+        }
+        let mut layout = vec![];
+        let mut offset: usize = 0;
+        for t in func_type.returns.iter() {
+            let size = type_size(*t);
+            offset = offset.div_ceil(size) * size;
+            layout.push((offset, size));
+            offset += size;
+        }
+        Ok(MultiValue {
+            name,
+            func_type,
+            params,
+            results: emit_results_type(&func_type.returns)?,
+            layout,
+        })
+    }
+
+    // A single result is named value, like in the structs of wavm.h.
+    fn field(&self, i: usize) -> String {
+        if self.layout.len() == 1 {
+            String::from("value")
+        } else {
+            format!("value{}", i)
+        }
+    }
+
+    fn args(&self) -> String {
+        (0..self.params.len())
+            .map(|i| format!("p{}", i))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    // The C struct of the results, and on riscv the struct which WAVM compiled code returns two results in. It is
+    // returned through a pointer because it does not fit in two registers, the padding makes C do the same.
+    fn emit_types(&self, glue_file: &mut code_builder::CodeBuilder, emitted: &mut std::collections::HashSet<String>) {
+        if !emitted.insert(self.results.clone()) {
+            return;
+        }
+        let mut fields = vec![String::from("void *dummy;")];
+        for (i, t) in self.func_type.returns.iter().enumerate() {
+            fields.push(format!("{} {};", emit_type(*t).unwrap(), self.field(i)));
+        }
+        glue_file.write(format!("typedef struct {{ {} }} {};", fields.join(" "), self.results));
+        if self.func_type.returns.len() == 2 {
+            glue_file.write("#ifdef __riscv");
+            glue_file.write(format!(
+                "typedef struct {{ {} uint64_t padding[2]; }} wasc_sret_{};",
+                fields.join(" "),
+                &self.results["wavm_ret_".len()..]
+            ));
+            glue_file.write("#endif");
+        }
+    }
+
+    fn sret(&self) -> String {
+        format!("wasc_sret_{}", &self.results["wavm_ret_".len()..])
+    }
+
+    // Emit wasc_multi_functionDefN, which calls functionDefN from C.
+    fn emit_def(&self, glue_file: &mut code_builder::CodeBuilder) {
+        let multi = format!("wasc_multi_{}", self.name);
+        let signature = format!("({}) ({})", self.name, self.params.join(", "));
+        if self.layout.len() <= 2 {
+            if self.layout.len() == 2 {
+                glue_file.write("#ifdef __riscv");
+                glue_file.write(format!("extern {} {};", self.sret(), signature));
+                glue_file.write(format!("{} {}({}) {{", self.results, multi, self.params.join(", ")));
+                glue_file.write(format!("{} s = {}({});", self.sret(), self.name, self.args()));
+                glue_file.write(format!("{} r = {{s.dummy, s.value0, s.value1}};", self.results));
+                glue_file.write("return r;");
+                glue_file.write("}");
+                glue_file.write("#else");
+            }
+            glue_file.write(format!("extern void* {};", signature));
+            let thunk = format!("wasc_thunk_{}", self.name);
+            glue_file.write(format!("extern void* ({}) ({});", thunk, self.params.join(", ")));
+            self.emit_thunk(glue_file, &thunk, &self.name, true);
+            self.emit_from_context(glue_file, &multi, &thunk);
+            if self.layout.len() == 2 {
+                glue_file.write("#endif");
+            }
+        } else {
+            glue_file.write(format!("extern void* {};", signature));
+            self.emit_from_context(glue_file, &multi, &self.name);
+        }
+    }
+
+    // Emit the function which WAVM compiled code calls, on top of wasc_multi_functionImportN implemented in C.
+    fn emit_import(&self, glue_file: &mut code_builder::CodeBuilder, name: &str) {
+        let multi = format!("wasc_multi_{}", self.name);
+        glue_file.write(format!(
+            "extern {} ({}) ({});",
+            self.results,
+            multi,
+            self.params.join(", ")
+        ));
+        if self.layout.len() <= 2 {
+            if self.layout.len() == 2 {
+                glue_file.write("#ifdef __riscv");
+                glue_file.write(format!("{} {}({}) {{", self.sret(), name, self.params.join(", ")));
+                glue_file.write(format!("{} r = {}({});", self.results, multi, self.args()));
+                glue_file.write(format!("{} s = {{r.dummy, r.value0, r.value1}};", self.sret()));
+                glue_file.write("return s;");
+                glue_file.write("}");
+                glue_file.write("#else");
+            }
+            let thunk = format!("wasc_thunk_{}", self.name);
+            self.emit_to_context(glue_file, &thunk, &multi);
+            glue_file.write(format!("extern void* ({}) ({});", name, self.params.join(", ")));
+            self.emit_thunk(glue_file, name, &thunk, false);
+            if self.layout.len() == 2 {
+                glue_file.write("#endif");
+            }
+        } else {
+            self.emit_to_context(glue_file, name, &multi);
+        }
+    }
+
+    // A C function which calls a function that leaves its results in the context, and returns them in a struct.
+    fn emit_from_context(&self, glue_file: &mut code_builder::CodeBuilder, name: &str, callee: &str) {
+        glue_file.write(format!("{} {}({}) {{", self.results, name, self.params.join(", ")));
+        glue_file.write(format!("uint8_t *context = {}({});", callee, self.args()));
+        glue_file.write(format!("{} r;", self.results));
+        glue_file.write("r.dummy = context;");
+        for (i, (offset, size)) in self.layout.iter().enumerate() {
+            glue_file.write(format!("memcpy(&r.{}, context + {}, {});", self.field(i), offset, size));
+        }
+        glue_file.write("return r;");
+        glue_file.write("}");
+    }
+
+    // A C function which calls a function that returns its results in a struct, and leaves them in the context.
+    fn emit_to_context(&self, glue_file: &mut code_builder::CodeBuilder, name: &str, callee: &str) {
+        glue_file.write(format!("void *{}({}) {{", name, self.params.join(", ")));
+        glue_file.write(format!("{} r = {}({});", self.results, callee, self.args()));
+        glue_file.write("uint8_t *context = r.dummy;");
+        for (i, (offset, size)) in self.layout.iter().enumerate() {
+            glue_file.write(format!("memcpy(context + {}, &r.{}, {});", offset, self.field(i), size));
+        }
+        glue_file.write("return context;");
+        glue_file.write("}");
+    }
+
+    // Emit an x86_64 function which calls another one with the same arguments, and moves the results between the
+    // registers of WAVM compiled code and the context. Arguments on the stack are copied to the new frame.
+    fn emit_thunk(&self, glue_file: &mut code_builder::CodeBuilder, name: &str, callee: &str, store: bool) {
+        // The context takes the first integer register. Arguments which do not fit in registers take 8 bytes on the
+        // stack, a v128 takes 16 aligned bytes.
+        let mut int_count = 1;
+        let mut float_count = 0;
+        let mut stack_size: usize = 0;
+        for t in self.func_type.params.iter() {
+            let in_register = match t {
+                wasmparser::Type::F32 | wasmparser::Type::F64 | wasmparser::Type::V128 => {
+                    float_count += 1;
+                    float_count <= 8
+                }
+                _ => {
+                    int_count += 1;
+                    int_count <= 6
+                }
+            };
+            if !in_register {
+                let size = if *t == wasmparser::Type::V128 { 16 } else { 8 };
+                stack_size = stack_size.div_ceil(size) * size + size;
+            }
+        }
+        let stack_count = stack_size / 8;
+        let mut code = vec![
+            String::from("WASC_SECTION_PUSH"),
+            String::from("\".balign 16\\n\""),
+            format!("\".globl \" WASC_SYMBOL({}) \"\\n\"", name),
+            format!("WASC_SYMBOL({}) \":\\n\"", name),
+            String::from("\"push %rbp\\n\""),
+            String::from("\"mov %rsp, %rbp\\n\""),
+            format!("\"sub ${}, %rsp\\n\"", stack_size.div_ceil(16) * 16),
+        ];
+        for i in 0..stack_count {
+            code.push(format!("\"mov {}(%rbp), %r11\\n\"", 16 + i * 8));
+            code.push(format!("\"mov %r11, {}(%rsp)\\n\"", i * 8));
+        }
+        code.push(format!("WASC_CALL({}) \"\\n\"", callee));
+        let mut int_register = ["rdx", "rcx"].iter();
+        let mut float_register = ["xmm0", "xmm1"].iter();
+        for (t, (offset, _)) in self.func_type.returns.iter().zip(self.layout.iter()) {
+            let (instruction, register) = match t {
+                wasmparser::Type::I32 => ("movl", int_register.next().map(|e| format!("%e{}", &e[1..]))),
+                wasmparser::Type::F32 => ("movss", float_register.next().map(|e| format!("%{}", e))),
+                wasmparser::Type::F64 => ("movsd", float_register.next().map(|e| format!("%{}", e))),
+                wasmparser::Type::V128 => ("movups", float_register.next().map(|e| format!("%{}", e))),
+                _ => ("movq", int_register.next().map(|e| format!("%{}", e))),
+            };
+            let register = register.unwrap();
+            if store {
+                code.push(format!("\"{} {}, {}(%rax)\\n\"", instruction, register, offset));
+            } else {
+                code.push(format!("\"{} {}(%rax), {}\\n\"", instruction, offset, register));
+            }
+        }
+        code.push(String::from("\"leave\\n\""));
+        code.push(String::from("\"ret\\n\""));
+        code.push(String::from("WASC_SECTION_POP"));
+        glue_file.write("__asm__(");
+        for e in code {
+            glue_file.write(format!("  {}", e));
+        }
+        glue_file.write(");");
+    }
+}
+
+// Attach the location of the entry which caused the error, if it is known.
+fn locate(err: WascError, location: Option<&Location>) -> WascError {
+    match location {
+        Some(location) => err.at(location),
+        None => err,
+    }
+}
+
+// Emit the content of a data segment, it is copied into memory when an instance is initialized or reset.
+fn emit_memory_data_array(glue_file: &mut code_builder::CodeBuilder, mi: u32, di: u32, init: &[u8]) {
+    glue_file.write(format!("const uint8_t memory{}_data{}[{}] = {{", mi, di, init.len()));
+    glue_file.write_array(init.iter().map(|x| format!("0x{:02x}", x)).collect(), 16);
+    glue_file.write("};");
+}
+
+// Emit memory data with static/dynamic offset.
+fn emit_memory_data(mi: u32, di: u32, offset: &str, len: u32) -> String {
+    format!(
+        "memcpy(instance->memory{}.base + {}, memory{}_data{}, {});",
+        mi, offset, mi, di, len
+    )
+}
+
+// An active element segment which can not be checked at compile time, at a dynamic offset or in an imported table,
+// must fit its table, or instantiating the module traps.
+fn emit_table_check(ti: u32, offset: &str, len: usize) -> String {
+    format!("wasc_table_check(&instance->table{}, {}, {});", ti, offset, len)
+}
+
+// Size of the area at the start of the instance which WAVM uses to pass arguments and results that do not fit in
+// registers.
+const INSTANCE_THUNK_SIZE: u32 = 256;
+// The largest memory, 64-bit memories included.
+const MEMORY_MAX_PAGES: u64 = 65536;
+// The largest table on ckb-vm, unless its initial length is larger.
+const CKB_TABLE_CAPACITY: u32 = 1024;
+
+// A field of the instance struct, and the symbol WAVM compiled code uses to find it.
+#[derive(Debug)]
+struct InstanceField {
+    declaration: String,
+    name: String,
+    symbol: String,
+    offset: u32,
+}
+
+// The layout of the instance struct, fields are naturally aligned like the C compiler does.
+#[derive(Debug)]
+struct InstanceLayout {
+    field_list: Vec<InstanceField>,
+    size: u32,
+}
+
+impl InstanceLayout {
+    fn new() -> Self {
+        InstanceLayout {
+            field_list: vec![],
+            size: INSTANCE_THUNK_SIZE,
+        }
+    }
+
+    // Globals are naturally aligned, WAVM compiled code loads a v128 global with aligned instructions. Memories and
+    // tables are structs of 8 bytes fields.
+    fn push(&mut self, declaration: String, name: String, symbol: String, size: u32, align: u32) {
+        let offset = self.size.div_ceil(align) * align;
+        self.size = offset + size;
+        self.field_list.push(InstanceField {
+            declaration,
+            name,
+            symbol,
+            offset,
+        });
+    }
+}
+
+pub fn generate(middle: &mut context::Middle) -> Result<(), WascError> {
+    let wasm_data: Vec<u8> = std::fs::read(&middle.path_precompiled)?;
+    let wasm_module = Module::from(wasm_data)?;
+    let mut store = Store::default();
+    let wasm_instance = ModuleInstance::from(&wasm_module, &mut store)?;
+
+    let file_stem = middle.file_stem.clone();
+    // Save precompiled object.
+    let mut object_data: Vec<u8> = vec![];
+    for e in &wasm_module.custom_list {
+        if e.name == "wavm.precompiled_object" {
+            object_data.extend_from_slice(&e.data);
         }
     }
     std::fs::write(&middle.path_object, &object_data)?;
@@ -536,386 +1719,896 @@ pub fn generate(middle: &mut context::Middle) -> Result<(), Box<dyn std::error::
     glue_file.write(format!(include_str!("glue.template"), header_id, header_id));
 
     // Emit type.
+    let type_id_list = TypeIdList::new(&wasm_instance.type_list);
     for i in 0..wasm_instance.type_list.len() {
-        glue_file.write(format!("const uint64_t {} = 0;", get_external_name("typeId", i as u32)));
+        glue_file.write(format!(
+            "WASC_SET_SYMBOL({}, {});",
+            get_external_name("typeId", i as u32),
+            type_id_list.get(&wasm_instance.type_list[i])
+        ));
+    }
+    let prefix = cnaming(&file_stem);
+    let instance_type = format!("struct {}_instance", prefix);
+    let is_ckb = match middle.config.platform {
+        context::Platform::CKBVMAssemblyScript | context::Platform::CKBVMSpectest => true,
+        context::Platform::PosixX8664 | context::Platform::PosixX8664Spectest | context::Platform::PosixX8664Wasi => {
+            false
+        }
+        context::Platform::Unknown => return Err(WascError::UnknownPlatform),
+    };
+    // On posix, the state shared by all instances is initialized with pthread_once.
+    if !is_ckb {
+        glue_file.write("#include <pthread.h>");
+    }
+    // The glue code moves v128 arguments and results through the vector registers of x86_64.
+    if is_ckb {
+        for i in &wasm_instance.function_addr_list {
+            let function_type = match &store.function_list[*i as usize] {
+                FunctionInstance::WasmFunc { function_type } => function_type,
+                FunctionInstance::HostFunc { function_type, .. } => function_type,
+            };
+            if function_type.params.contains(&wasmparser::Type::V128)
+                || function_type.returns.contains(&wasmparser::Type::V128)
+            {
+                let err = WascError::unsupported("v128 argument or result on ckb-vm");
+                return Err(locate(err, wasm_module.function_location(*i)));
+            }
+        }
     }
-    // Emit global.
-    for i in &wasm_instance.global_addr_list {
-        let global_instance = &store.global_list[*i as usize];
-        let extern_name = get_external_name("global", *i);
 
-        match global_instance {
-            GlobalInstance::Wasm { global_type, value } => {
-                let type_string = emit_type(global_type.content_type.clone());
-                let mutable_string = if global_type.mutable { "" } else { "const " };
-                match value {
-                    Value::I32(v) => {
-                        glue_file.write(format!("{}{} {} = {};", mutable_string, type_string, extern_name, v));
-                    }
-                    Value::I64(v) => {
-                        glue_file.write(format!("{}{} {} = {};", mutable_string, type_string, extern_name, v));
-                    }
-                    Value::F32(v) => {
-                        let f = unsafe { std::mem::transmute::<u32, f32>(*v).to_string() };
-                        glue_file.write(format!("{}{} {} = {};", mutable_string, type_string, extern_name, f));
-                    }
-                    Value::F64(v) => {
-                        let f = unsafe { std::mem::transmute::<u64, f64>(*v).to_string() };
-                        glue_file.write(format!("{}{} {} = {};", mutable_string, type_string, extern_name, f));
-                    }
+    for i in wasm_instance.tag_addr_list.iter().copied() {
+        let tag_type = match &store.tag_list[i as usize] {
+            TagInstance::Wasm { tag_type } => tag_type,
+            TagInstance::Host { tag_type, .. } => tag_type,
+        };
+        if !tag_type.returns.is_empty() {
+            let err = WascError::malformed("tag type with results");
+            return Err(locate(err, wasm_module.tag_location(i)));
+        }
+        // The exception intrinsics rely on the unwinder of the host.
+        if is_ckb {
+            let err = WascError::unsupported("exception tag on ckb-vm");
+            return Err(locate(err, wasm_module.tag_location(i)));
+        }
+    }
+
+    // Memories are reserved with room for 4GiB on posix, and are static arrays in the .wasm_memory section on ckb-vm,
+    // which only has room for one of them.
+    // An imported memory the host does not define is provided by the glue: its size and maximum come from the config,
+    // or else from the limits of the import.
+    for (k, i) in wasm_instance.memory_addr_list.iter().copied().enumerate() {
+        if let MemoryInstance::Host { memory_type, .. } = &mut store.memory_list[i as usize] {
+            let pages = middle.config.imported_memory_pages.unwrap_or(memory_type.initial);
+            let max_pages = middle.config.imported_memory_max_pages.or(memory_type.maximum);
+            if pages < memory_type.initial {
+                let err = WascError::uninstantiable("imported memory smaller than its import");
+                return Err(locate(err, wasm_module.memory_location(k as u32)));
+            }
+            if let Some(max_pages) = max_pages {
+                if max_pages < pages {
+                    let err = WascError::uninstantiable("imported memory larger than its maximum");
+                    return Err(locate(err, wasm_module.memory_location(k as u32)));
+                }
+                if memory_type.maximum.is_some_and(|maximum| max_pages > maximum) {
+                    let err = WascError::uninstantiable("imported memory maximum larger than its import");
+                    return Err(locate(err, wasm_module.memory_location(k as u32)));
                 }
             }
-            GlobalInstance::Host {
-                global_type,
-                import_name,
-            } => {
-                let type_string = emit_type(global_type.content_type.clone());
-                let wavm_name = format!("wavm_{}", import_name);
-                glue_file.write(format!("#define {} {}", wavm_name, extern_name));
-                glue_file.write(format!("extern {} {};", type_string, extern_name));
+            memory_type.initial = pages;
+            memory_type.maximum = max_pages;
+        }
+    }
+    for (k, i) in wasm_instance.memory_addr_list.iter().copied().enumerate() {
+        let memory_type = match &store.memory_list[i as usize] {
+            MemoryInstance::Wasm { memory_type, .. } => memory_type,
+            MemoryInstance::Host { memory_type, .. } => memory_type,
+        };
+        if memory_type.initial > MEMORY_MAX_PAGES {
+            let err = WascError::unsupported("memory larger than 4GiB");
+            return Err(locate(err, wasm_module.memory_location(k as u32)));
+        }
+        if is_ckb && k > 0 {
+            let err = WascError::unsupported("several memories on ckb-vm");
+            return Err(locate(err, wasm_module.memory_location(k as u32)));
+        }
+    }
+
+    // Emit instance. Everything that an instance may modify lives in the instance struct: mutable globals, memories
+    // and tables. WAVM compiled code finds them by adding the address of the globalN, memoryOffsetN and tableOffsetN
+    // symbols to the context pointer, so these symbols are defined as the offsets of the fields.
+    let mut layout = InstanceLayout::new();
+    for i in wasm_instance.global_addr_list.iter().copied() {
+        let global_type = match &store.global_list[i as usize] {
+            GlobalInstance::Wasm { global_type, .. } => global_type,
+            GlobalInstance::Host { global_type, .. } => global_type,
+        };
+        if global_type.mutable {
+            let type_string =
+                emit_type(global_type.content_type).map_err(|err| locate(err, wasm_module.global_location(i)))?;
+            let size = type_size(global_type.content_type) as u32;
+            let name = get_external_name("global", i);
+            layout.push(format!("{} {}", type_string, name), name.clone(), name, size, size);
+        }
+    }
+    let mut shared_memory_symbol_list: Vec<String> = vec![];
+    for i in wasm_instance.memory_addr_list.iter().copied() {
+        let memory_type = match &store.memory_list[i as usize] {
+            MemoryInstance::Wasm { memory_type, .. } => memory_type,
+            MemoryInstance::Host { memory_type, .. } => memory_type,
+        };
+        let name = get_external_name("memory", i);
+        let symbol = get_external_name("memoryOffset", i);
+        if memory_type.shared {
+            shared_memory_symbol_list.push(symbol.clone());
+        }
+        layout.push(format!("memory_instance {}", name), name, symbol, 16, 8);
+    }
+    for i in wasm_instance.table_addr_list.iter().copied() {
+        let name = get_external_name("table", i);
+        let symbol = get_external_name("tableOffset", i);
+        layout.push(format!("table_instance {}", name), name, symbol, 16, 8);
+    }
+    glue_file.write(format!("{} {{", instance_type));
+    glue_file.write(format!("uint8_t thunk[{}];", INSTANCE_THUNK_SIZE));
+    for e in &layout.field_list {
+        glue_file.write(format!("{};", e.declaration));
+    }
+    // The segments memory.init and table.init copy from, WAVM compiled code does not know about them.
+    if !wasm_module.data_list.is_empty() {
+        glue_file.write(format!("wasc_segment data_segment[{}];", wasm_module.data_list.len()));
+    }
+    if !wasm_module.element_list.is_empty() {
+        glue_file.write(format!(
+            "wasc_segment element_segment[{}];",
+            wasm_module.element_list.len()
+        ));
+    }
+    glue_file.write("};");
+    if is_ckb {
+        // There is only one instance on CKB, the symbols are the absolute addresses of its fields and the context
+        // pointer is always NULL.
+        glue_file.write(format!("{} wasc_instance_storage;", instance_type));
+        glue_file.write("#define WASC_INSTANCE(context) (&wasc_instance_storage)");
+        glue_file.write("#define WASC_CONTEXT(instance) NULL");
+        for e in &layout.field_list {
+            glue_file.write(format!(
+                "WASC_SET_SYMBOL({}, wasc_instance_storage + {});",
+                e.symbol, e.offset
+            ));
+        }
+    } else {
+        // Instances are aligned to 4GiB, WAVM compiled code gets the instance back by rounding the context pointer
+        // down, see wasc_instance_alloc.
+        glue_file.write(format!(
+            "#define WASC_INSTANCE(context) (({} *)((uintptr_t)(context) & ~(uintptr_t)0xffffffff))",
+            instance_type
+        ));
+        glue_file.write("#define WASC_CONTEXT(instance) ((void *)(instance))");
+        glue_file.write("void *wasc_instance_alloc(size_t size);");
+        glue_file.write("void wasc_instance_free(void *instance, size_t size);");
+        glue_file.write("void wasc_memory_init(memory_instance *memory, uint64_t num_pages);");
+        glue_file.write("void wasc_memory_free(memory_instance *memory);");
+        glue_file.write("void wasc_memory_detach(memory_instance *memory);");
+        glue_file.write("void wasc_table_init(table_instance *table, uint32_t length, uint32_t maximum);");
+        glue_file.write("void wasc_table_free(table_instance *table);");
+        glue_file.write("void wasc_table_import(table_instance *table, table_instance *source, uint32_t length);");
+        for e in &layout.field_list {
+            glue_file.write(format!("WASC_SET_SYMBOL({}, {});", e.symbol, e.offset));
+        }
+    }
+    for e in &layout.field_list {
+        glue_file.write(format!(
+            "_Static_assert(offsetof({}, {}) == {}, \"{}\");",
+            instance_type, e.name, e.offset, e.symbol
+        ));
+    }
+    // Memory and table intrinsics find the memory or the table by the ID WAVM compiled code computes from its symbol.
+    let symbol_value = |offset: u32| {
+        if is_ckb {
+            format!("(uintptr_t) &wasc_instance_storage + {}", offset)
+        } else {
+            offset.to_string()
+        }
+    };
+    for (kind, symbol, id) in &[
+        ("memory_instance", "memoryOffset", "WASC_MEMORY_ID"),
+        ("table_instance", "tableOffset", "WASC_TABLE_ID"),
+    ] {
+        let function = &kind[..kind.len() - "_instance".len()];
+        glue_file.write(format!("{} *wasc_{}(void *context, uintptr_t id) {{", kind, function));
+        for e in layout.field_list.iter().filter(|e| e.symbol.starts_with(symbol)) {
+            glue_file.write(format!("if (id == {}({})) {{", id, symbol_value(e.offset)));
+            glue_file.write(format!("return &WASC_INSTANCE(context)->{};", e.name));
+            glue_file.write("}");
+        }
+        glue_file.write("return NULL;");
+        glue_file.write("}");
+    }
+    // The host may size an imported memory by defining these constants, they override the ones given at compile time.
+    for i in wasm_instance.memory_addr_list.iter().copied() {
+        if let MemoryInstance::Host {
+            memory_type,
+            import_name,
+            ..
+        } = &store.memory_list[i as usize]
+        {
+            let maximum = std::cmp::min(memory_type.maximum.unwrap_or(MEMORY_MAX_PAGES), MEMORY_MAX_PAGES);
+            glue_file.write(format!(
+                "const uint64_t __attribute__((weak)) wavm_{}_pages = {};",
+                import_name, memory_type.initial
+            ));
+            glue_file.write(format!(
+                "const uint64_t __attribute__((weak)) wavm_{}_max_pages = {};",
+                import_name, maximum
+            ));
+        }
+    }
+    // memory.grow fails past the maximum of the memory.
+    glue_file.write("uint64_t wasc_memory_max_pages(uintptr_t id) {");
+    for i in wasm_instance.memory_addr_list.iter().copied() {
+        let maximum = match &store.memory_list[i as usize] {
+            MemoryInstance::Wasm { memory_type, .. } => {
+                std::cmp::min(memory_type.maximum.unwrap_or(MEMORY_MAX_PAGES), MEMORY_MAX_PAGES).to_string()
             }
+            MemoryInstance::Host { import_name, .. } => format!("wavm_{}_max_pages", import_name),
+        };
+        let symbol = get_external_name("memoryOffset", i);
+        let e = layout.field_list.iter().find(|e| e.symbol == symbol).unwrap();
+        glue_file.write(format!("if (id == WASC_MEMORY_ID({})) {{", symbol_value(e.offset)));
+        glue_file.write(format!("return {};", maximum));
+        glue_file.write("}");
+    }
+    glue_file.write("return 0;");
+    glue_file.write("}");
+    // WASI and the syscalls of ckb-vm work on the memory the module exports as "memory".
+    let exported_memory = wasm_module
+        .export_list
+        .iter()
+        .filter(|e| matches!(e.kind, ExternalKind::Memory) && e.field == "memory")
+        .map(|e| wasm_instance.memory_addr_list[e.index as usize])
+        .next();
+    if exported_memory.is_some() {
+        glue_file.write("#define WASC_MEMORY_EXPORTED 1");
+    }
+    glue_file.write("memory_instance *wasc_memory_exported(void *context) {");
+    match exported_memory {
+        Some(i) => glue_file.write(format!("return &WASC_INSTANCE(context)->memory{};", i)),
+        None => {
+            glue_file.write("static memory_instance none = {NULL, 0};");
+            glue_file.write("return &none;");
         }
     }
+    glue_file.write("}");
+    // Waiting on a memory which is not shared traps.
+    glue_file.write("int wasc_memory_shared(uintptr_t id) {");
+    for e in layout
+        .field_list
+        .iter()
+        .filter(|e| shared_memory_symbol_list.contains(&e.symbol))
+    {
+        glue_file.write(format!("if (id == WASC_MEMORY_ID({})) {{", symbol_value(e.offset)));
+        glue_file.write("return 1;");
+        glue_file.write("}");
+    }
+    glue_file.write("return 0;");
+    glue_file.write("}");
+
+    let mut init_function_list: Vec<String> = vec![];
     // Emit function.
     let mut wasm_function_counter = 0;
     let mut host_function_counter = 0;
     let mut function_name_list: Vec<String> = vec![];
+    // What references to the functions point to, which differs from the function itself for imported functions.
+    let mut element_name_list: Vec<String> = vec![];
+    let mut results_type_set: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut trap_name_list: Vec<(String, String)> = vec![];
     for i in &wasm_instance.function_addr_list {
         let function_instance = &store.function_list[*i as usize];
         match function_instance {
             FunctionInstance::WasmFunc { function_type } => {
                 let name = get_external_name("functionDef", wasm_function_counter);
-                glue_file.write(format!(
-                    "extern {};",
-                    emit_function_signature(&function_type, name.clone())
-                ));
+                let call_name = if MultiValue::needed(function_type) {
+                    let multi_value = MultiValue::new(name.clone(), function_type)
+                        .map_err(|err| locate(err, wasm_module.function_location(*i)))?;
+                    multi_value.emit_types(&mut glue_file, &mut results_type_set);
+                    multi_value.emit_def(&mut glue_file);
+                    format!("wasc_multi_{}", name)
+                } else {
+                    let signature = emit_function_signature(function_type, name.clone())
+                        .map_err(|err| locate(err, wasm_module.function_location(*i)))?;
+                    glue_file.write(format!("extern {};", signature));
+                    name.clone()
+                };
                 let a = get_external_name("functionDefMutableDatas", wasm_function_counter);
                 glue_file.write(format!("const uint64_t {} = 0;", a));
                 wasm_function_counter += 1;
-                function_name_list.push(name);
+                // Traps name the function after the name section, or an export.
+                let trap_name = match wasm_module.function_name_map.get(i) {
+                    Some(e) => e.clone(),
+                    None => match wasm_instance
+                        .export_list
+                        .iter()
+                        .find(|e| matches!(e.kind, ExternalKind::Function) && e.index == *i)
+                    {
+                        Some(e) => e.field.clone(),
+                        None => format!("function {}", i),
+                    },
+                };
+                trap_name_list.push((name.clone(), trap_name));
+                element_name_list.push(name);
+                function_name_list.push(call_name);
             }
             FunctionInstance::HostFunc {
                 function_type,
                 import_name,
             } => {
+                // WAVM compiled code calls functionImportN, and takes references to it. It is a stub which jumps to
+                // wasc_import_functionImportN, with a type ID like the functions WAVM compiles.
                 let extern_name = get_external_name("functionImport", host_function_counter);
-                let signature = emit_function_signature(&function_type, extern_name.clone());
-                glue_file.write(format!("#define wavm_{} {}", import_name, extern_name));
-                glue_file.write(format!("extern {};", signature));
+                let import_name_c = format!("wasc_import_{}", extern_name);
+                if MultiValue::needed(function_type) {
+                    let multi_value = MultiValue::new(extern_name.clone(), function_type)
+                        .map_err(|err| locate(err, wasm_module.function_location(*i)))?;
+                    glue_file.write(format!("#define wavm_{} wasc_multi_{}", import_name, extern_name));
+                    multi_value.emit_types(&mut glue_file, &mut results_type_set);
+                    multi_value.emit_import(&mut glue_file, &import_name_c);
+                } else {
+                    let signature = emit_function_signature(function_type, import_name_c.clone())
+                        .map_err(|err| locate(err, wasm_module.function_location(*i)))?;
+                    glue_file.write(format!("#define wavm_{} {}", import_name, import_name_c));
+                    glue_file.write(format!("extern {};", signature));
+                }
+                glue_file.write(format!(
+                    "WASC_FUNCTION_ELEMENT({}, {}, {});",
+                    extern_name,
+                    import_name_c,
+                    type_id_list.get(function_type)
+                ));
+                element_name_list.push(extern_name);
                 function_name_list.push(format!("wavm_{}", import_name));
                 host_function_counter += 1
             }
         }
     }
-    // Get the correspondence between data and memory.
-    for e in wasm_module.data_list {
+    // Emit global. Immutable globals are shared by all instances, mutable globals are initialized per instance. They
+    // come after the functions, which reference globals may point to.
+    let mut global_init_list: Vec<String> = vec![];
+    let mut const_global_init_list: Vec<String> = vec![];
+    for i in wasm_instance.global_addr_list.iter().copied() {
+        let global_instance = &store.global_list[i as usize];
+        let extern_name = get_external_name("global", i);
+
+        match global_instance {
+            GlobalInstance::Wasm {
+                global_type,
+                value: Constant::Static(value),
+            } => {
+                let type_string =
+                    emit_type(global_type.content_type).map_err(|err| locate(err, wasm_module.global_location(i)))?;
+                let value_string = match value {
+                    Value::I32(v) => v.to_string(),
+                    // -9223372036854775808 is the negation of a literal too large for int64_t.
+                    Value::I64(i64::MIN) => String::from("INT64_MIN"),
+                    Value::I64(v) => v.to_string(),
+                    Value::F32(v) => f32::from_bits(*v).to_string(),
+                    Value::F64(v) => f64::from_bits(*v).to_string(),
+                    Value::V128(v) => emit_v128(*v),
+                    Value::RefNull => String::from("NULL"),
+                    Value::RefFunc(function_index) => {
+                        emit_function_reference(&element_name_list[*function_index as usize])
+                    }
+                };
+                if global_type.mutable {
+                    let value_string = match value {
+                        Value::V128(_) => format!("({}) {}", type_string, value_string),
+                        _ => value_string,
+                    };
+                    global_init_list.push(format!("instance->{} = {};", extern_name, value_string));
+                } else {
+                    glue_file.write(format!("const {} {} = {};", type_string, extern_name, value_string));
+                }
+            }
+            // The initializer reads an imported global, init_global computes it after the globals before it. An immutable
+            // global is shared by all instances, it is computed once for all of them by init_const_global.
+            GlobalInstance::Wasm {
+                global_type,
+                value: Constant::Dynamic(_, value_string),
+            } => {
+                if global_type.mutable {
+                    global_init_list.push(format!("instance->{} = {};", extern_name, value_string));
+                } else {
+                    let type_string = emit_type(global_type.content_type)
+                        .map_err(|err| locate(err, wasm_module.global_location(i)))?;
+                    glue_file.write(format!("{} {};", type_string, extern_name));
+                    const_global_init_list.push(format!("{} = {};", extern_name, value_string));
+                }
+            }
+            GlobalInstance::Host {
+                global_type,
+                import_name,
+            } => {
+                let type_string =
+                    emit_type(global_type.content_type).map_err(|err| locate(err, wasm_module.global_location(i)))?;
+                let wavm_name = format!("wavm_{}", import_name);
+                if global_type.mutable {
+                    // Each instance starts with the value the host gives, later changes are not shared.
+                    glue_file.write(format!("extern {} {};", type_string, wavm_name));
+                    global_init_list.push(format!("instance->{} = {};", extern_name, wavm_name));
+                } else {
+                    glue_file.write(format!("#define {} {}", wavm_name, extern_name));
+                    glue_file.write(format!("extern {} {};", type_string, extern_name));
+                }
+            }
+        }
+    }
+    // Emit tag. WAVM compiled code identifies the tag of an exception by the address of its biasedExceptionTypeIdN
+    // symbol, a wasc_tag which tells how many values an exception of the tag carries.
+    for i in wasm_instance.tag_addr_list.iter().copied() {
+        let extern_name = get_external_name("biasedExceptionTypeId", i);
+        match &store.tag_list[i as usize] {
+            TagInstance::Wasm { tag_type } => {
+                glue_file.write(format!(
+                    "const wasc_tag {} = {{{}}};",
+                    extern_name,
+                    tag_type.params.len()
+                ));
+            }
+            TagInstance::Host { import_name, .. } => {
+                glue_file.write(format!("#define wavm_{} {}", import_name, extern_name));
+                glue_file.write(format!("extern const wasc_tag {};", extern_name));
+            }
+        }
+    }
+    glue_file.write("void init_const_global(void) {");
+    for e in const_global_init_list {
+        glue_file.write(e);
+    }
+    glue_file.write("}");
+    glue_file.write(format!("void init_global({} *instance) {{", instance_type));
+    // Instances may be created by several threads at once on posix. ckb-vm runs a single thread, which computes the
+    // same values again.
+    if is_ckb {
+        glue_file.write("init_const_global();");
+    } else {
+        glue_file.write("static pthread_once_t const_global_once = PTHREAD_ONCE_INIT;");
+        glue_file.write("pthread_once(&const_global_once, init_const_global);");
+    }
+    for e in global_init_list {
+        glue_file.write(e);
+    }
+    glue_file.write("}");
+    init_function_list.push(String::from("init_global"));
+    // Emit function names for trap messages, ckb-vm does not print them.
+    if !is_ckb {
+        glue_file.write("const void *wasc_function_address[] = {");
+        for (e, _) in &trap_name_list {
+            glue_file.write(format!("(const void *) {},", e));
+        }
+        glue_file.write("NULL,");
+        glue_file.write("};");
+        glue_file.write("const char *wasc_function_name[] = {");
+        for (_, e) in &trap_name_list {
+            glue_file.write(format!("{},", cstring(e)));
+        }
+        glue_file.write("NULL,");
+        glue_file.write("};");
+        glue_file.write(format!(
+            "const uint32_t wasc_function_length = {};",
+            trap_name_list.len()
+        ));
+    }
+    // Get the correspondence between data and memory. Passive segments are emitted with the element segments below.
+    let data_segment_count = wasm_module.data_list.len();
+    let mut data_segment_list: Vec<(usize, Data)> = vec![];
+    for (k, e) in wasm_module.data_list.into_iter().enumerate() {
+        if e.offset.is_none() {
+            data_segment_list.push((k, e));
+            continue;
+        }
         let memory_instance = &mut store.memory_list[wasm_instance.memory_addr_list[e.memory_index as usize] as usize];
         match memory_instance {
-            MemoryInstance::Wasm { memory_type: _, data } => {
+            MemoryInstance::Wasm { data, .. } => {
                 data.push(e);
             }
-            MemoryInstance::Host {
-                memory_type: _,
-                data,
-                import_name: _,
-            } => {
+            MemoryInstance::Host { data, .. } => {
                 data.push(e);
             }
         }
     }
 
     // Emit memory.
-    let mut init_function_list: Vec<String> = vec![];
-    for i in wasm_instance.memory_addr_list {
-        let memory_instance = &store.memory_list[i as usize];
-        match memory_instance {
-            MemoryInstance::Wasm { memory_type, data } => {
-                glue_file.write(format!("uint32_t memory{}_length = {};", i, memory_type.limits.initial));
-                match middle.config.platform {
-                    // On Posix
-                    context::Platform::PosixX8664
-                    | context::Platform::PosixX8664Spectest
-                    | context::Platform::PosixX8664Wasi => {
-                        glue_file.write(format!("uint8_t* memory{};", i));
-                        let extern_name = get_external_name("memoryOffset", i as u32);
-                        glue_file.write(format!("struct memory_instance {};", extern_name));
-                        if let Some(x) = memory_type.limits.maximum {
-                            glue_file.write(format!("#define MEMORY{}_MAX_PAGE {}", i, x));
-                        }
-                        for (j, e) in data.iter().enumerate() {
-                            glue_file.write(format!("uint8_t memory{}_data{}[{}] = {{", i, j, e.init.len()));
-                            let array: Vec<String> = e.init.iter().map(|x| format!("0x{:02x}", x)).collect();
-                            glue_file.write_array(array, 16);
-                            glue_file.write("};");
-                        }
-                        glue_file.write(format!("#define MEMORY{}_DEFINED 1", i));
-                        glue_file.write(format!("void init_memory{}() {{", i));
-                        let memory_size = memory_type.limits.initial * 65536;
-                        glue_file.write(format!("memory{} = calloc({}, 1);", i, memory_size));
-                        for (j, e) in data.iter().enumerate() {
-                            match e.offset {
-                                Some(ConstantOperator::I32Const { value }) => {
-                                    let a =
-                                        emit_memory_data(i, j as u32, value.to_string().as_str(), e.init.len() as u32);
-                                    glue_file.write(a);
-                                }
-                                Some(ConstantOperator::GlobalGet { global_index }) => {
-                                    let global_addr = wasm_instance.global_addr_list[global_index as usize];
-                                    let global_instance = &store.global_list[global_addr as usize];
-                                    match global_instance {
-                                        GlobalInstance::Wasm { global_type: _, value } => match value {
-                                            Value::I32(value) => {
-                                                let offset = value.to_string();
-                                                let len = e.init.len() as u32;
-                                                let a = emit_memory_data(i, j as u32, offset.as_str(), len);
-                                                glue_file.write(a);
-                                            }
-                                            _ => panic!("unreachable"),
-                                        },
-                                        GlobalInstance::Host {
-                                            global_type: _,
-                                            import_name,
-                                        } => {
-                                            let offset = format!("wavm_{}", import_name);
-                                            let len = e.init.len() as u32;
-                                            let a = emit_memory_data(i, j as u32, offset.as_str(), len);
-                                            glue_file.write(a);
-                                        }
-                                    }
-                                }
-                                _ => panic!("unreachable"),
-                            }
+    let mut release_list: Vec<String> = vec![];
+    let mut shared_memory_list: Vec<u32> = vec![];
+    let mut imported_memory_list: Vec<(u32, String)> = vec![];
+    for i in wasm_instance.memory_addr_list.iter().copied() {
+        let (memory_type, data, import_name) = match &store.memory_list[i as usize] {
+            MemoryInstance::Wasm { memory_type, data } => (memory_type, data, None),
+            MemoryInstance::Host {
+                memory_type,
+                data,
+                import_name,
+            } => (memory_type, data, Some(import_name)),
+        };
+        // The pages of an imported memory belong to no instance, threads share them through the import.
+        if memory_type.shared && import_name.is_none() {
+            shared_memory_list.push(i);
+        }
+        if let Some(import_name) = import_name {
+            imported_memory_list.push((i, import_name.clone()));
+        }
+        if is_ckb && import_name.is_none() {
+            // On CKB, the initial memory is a part of the binary.
+            let memory_size = memory_type.initial as usize * 65536;
+            let mut memory_data: Vec<u8> = vec![0x00; memory_size];
+            for e in data.iter() {
+                match wasm_instance
+                    .eval_offset(&store, &e.offset)
+                    .map_err(|err| err.at(&e.location))?
+                {
+                    Offset::Static(value) => {
+                        if value.saturating_add(e.init.len() as u64) > memory_size as u64 {
+                            let reason = format!("data segment does not fit memory {}", i);
+                            return Err(WascError::uninstantiable(reason).at(&e.location));
                         }
-                        glue_file.write(format!("{}.base = memory{};", extern_name, i));
-                        glue_file.write(format!("{}.num_pages = {};", extern_name, memory_type.limits.initial));
-                        glue_file.write("}");
-                        init_function_list.push(format!("init_memory{}", i));
+                        let lo = value as usize;
+                        memory_data[lo..lo + e.init.len()].copy_from_slice(&e.init);
                     }
-                    // On CKB
-                    context::Platform::CKBVMSpectest | context::Platform::CKBVMAssemblyScript => {
-                        let memory_size = memory_type.limits.initial as usize * 65536;
-                        let mut memory_data: Vec<u8> = vec![0x00; memory_size];
-                        for (_, e) in data.iter().enumerate() {
-                            match e.offset {
-                                Some(ConstantOperator::I32Const { value }) => {
-                                    memory_data[value as usize..value as usize + e.init.len()].copy_from_slice(&e.init);
-                                }
-                                Some(ConstantOperator::GlobalGet { global_index }) => {
-                                    let global_addr = wasm_instance.global_addr_list[global_index as usize];
-                                    let global_instance = &store.global_list[global_addr as usize];
-                                    match global_instance {
-                                        GlobalInstance::Wasm { global_type: _, value } => match value {
-                                            Value::I32(value) => {
-                                                memory_data[*value as usize..*value as usize + e.init.len()]
-                                                    .copy_from_slice(&e.init);
-                                            }
-                                            _ => {}
-                                        },
-                                        _ => rog::println!(
-                                            "Dynamic offset is not supported in memory. {:?}",
-                                            global_instance
-                                        ),
-                                    }
-                                }
-                                _ => panic!("unreachable"),
-                            }
-                        }
-                        glue_file.write(format!(
-                            "uint8_t __attribute__((section (\".wasm_memory\"))) memory{}[{}] = {{",
-                            i, memory_size
-                        ));
-                        glue_file.write_array(memory_data.iter().map(|x| format!("0x{:02x}", x)).collect(), 16);
-                        glue_file.write("};");
-                        glue_file.write(format!(
-                            "struct memory_instance memoryOffset{} = {{ memory{}, {} }};",
-                            i, i, memory_type.limits.initial
-                        ));
-                        glue_file.write(format!("#define MEMORY{}_DEFINED 1", i));
-                        if let Some(x) = memory_type.limits.maximum {
-                            glue_file.write(format!("#define MEMORY{}_MAX_PAGE {}", i, x));
-                        }
+                    Offset::Dynamic(name) => {
+                        let feature = format!("data segment with dynamic offset {} on ckb-vm", name);
+                        return Err(WascError::unsupported(feature).at(&e.location));
                     }
-                    _ => panic!("unreachable"),
                 }
             }
-            MemoryInstance::Host {
-                memory_type: _,
-                data: _,
-                import_name: _,
-            } => {
-                // Does it make sense to support it?
+            glue_file.write(format!(
+                "uint8_t __attribute__((section (\".wasm_memory\"))) memory{}[{}] = {{",
+                i, memory_size
+            ));
+            glue_file.write_array(memory_data.iter().map(|x| format!("0x{:02x}", x)).collect(), 16);
+            glue_file.write("};");
+            for (j, e) in data.iter().enumerate() {
+                emit_memory_data_array(&mut glue_file, i, j as u32, &e.init);
             }
+            glue_file.write(format!("#define MEMORY{}_DEFINED 1", i));
+            glue_file.write(format!("void init_memory{}({} *instance) {{", i, instance_type));
+            glue_file.write(format!("instance->memory{}.base = memory{};", i, i));
+            // The memory has been used when the instance is reset, restore its initial content.
+            glue_file.write(format!("if (instance->memory{}.num_pages != 0) {{", i));
+            glue_file.write(format!(
+                "memset(memory{}, 0, instance->memory{}.num_pages * 65536);",
+                i, i
+            ));
+            for (j, e) in data.iter().enumerate() {
+                let offset = wasm_instance
+                    .eval_offset(&store, &e.offset)
+                    .map_err(|err| err.at(&e.location))?;
+                glue_file.write(emit_memory_data(
+                    i,
+                    j as u32,
+                    offset.emit().as_str(),
+                    e.init.len() as u32,
+                ));
+            }
+            glue_file.write("}");
+        } else {
+            for (j, e) in data.iter().enumerate() {
+                emit_memory_data_array(&mut glue_file, i, j as u32, &e.init);
+            }
+            // The host may define an imported memory. The weak definition stands in when it does not: the first
+            // instance reserves it on posix, it is a static array on ckb-vm.
+            let memory_size = memory_type.initial * 65536;
+            if let Some(import_name) = import_name {
+                glue_file.write(format!("extern memory_instance wavm_{};", import_name));
+                if is_ckb {
+                    glue_file.write(format!(
+                        "uint8_t __attribute__((section (\".wasm_memory\"))) memory{}[{}];",
+                        i, memory_size
+                    ));
+                    glue_file.write(format!(
+                        "memory_instance __attribute__((weak)) wavm_{} = {{memory{}, 0}};",
+                        import_name, i
+                    ));
+                } else {
+                    glue_file.write(format!("memory_instance __attribute__((weak)) wavm_{};", import_name));
+                }
+            }
+            glue_file.write(format!("#define MEMORY{}_DEFINED 1", i));
+            glue_file.write(format!("void init_memory{}({} *instance) {{", i, instance_type));
+            match import_name {
+                Some(import_name) => {
+                    // Data segments are written into the imported memory each time an instance is initialized.
+                    glue_file.write(format!(
+                        "wasc_memory_import(&instance->memory{}, &wavm_{}, wavm_{}_pages);",
+                        i, import_name, import_name
+                    ));
+                }
+                None => {
+                    // The memory is reserved with guard pages, see wasc_memory_init.
+                    glue_file.write(format!(
+                        "wasc_memory_init(&instance->memory{}, {});",
+                        i, memory_type.initial
+                    ));
+                }
+            }
+            for (j, e) in data.iter().enumerate() {
+                let offset = wasm_instance
+                    .eval_offset(&store, &e.offset)
+                    .map_err(|err| err.at(&e.location))?;
+                // The host may give an imported memory more pages, a segment past them traps at run time.
+                if let (Offset::Static(value), None) = (&offset, import_name) {
+                    if value.saturating_add(e.init.len() as u64) > memory_size {
+                        let reason = format!("data segment does not fit memory {}", i);
+                        return Err(WascError::uninstantiable(reason).at(&e.location));
+                    }
+                }
+                let a = emit_memory_data(i, j as u32, offset.emit().as_str(), e.init.len() as u32);
+                glue_file.write(a);
+            }
+            match import_name {
+                _ if is_ckb => {}
+                Some(_) => release_list.push(format!("wasc_memory_detach(&instance->memory{});", i)),
+                None => release_list.push(format!("wasc_memory_free(&instance->memory{});", i)),
+            }
+        }
+        if import_name.is_none() {
+            glue_file.write(format!("instance->memory{}.num_pages = {};", i, memory_type.initial));
         }
+        glue_file.write("}");
+        init_function_list.push(format!("init_memory{}", i));
     }
     // Get the correspondence between elem and table.
-    for e in wasm_module.element_list {
+    let element_segment_count = wasm_module.element_list.len();
+    let mut element_segment_list: Vec<(usize, Element)> = vec![];
+    for (k, e) in wasm_module.element_list.into_iter().enumerate() {
+        if e.offset.is_none() {
+            element_segment_list.push((k, e));
+            continue;
+        }
         let table_instance = &mut store.table_list[wasm_instance.table_addr_list[e.table_index as usize] as usize];
         match table_instance {
-            TableInstance::Wasm {
-                table_type: _,
-                element_list,
-            } => {
+            TableInstance::Wasm { element_list, .. } => {
                 element_list.push(e);
             }
-            TableInstance::Host {
-                table_type: _,
-                element_list,
-                import_name: _,
-            } => {
+            TableInstance::Host { element_list, .. } => {
                 element_list.push(e);
             }
         }
     }
     // Emit table.
-    for i in wasm_instance.table_addr_list {
+    for i in wasm_instance.table_addr_list.iter().copied() {
         let table_instance = &store.table_list[i as usize];
         match table_instance {
             TableInstance::Wasm {
                 table_type,
                 element_list,
             } => {
-                glue_file.write(format!("uint32_t table{}_length = {};", i, table_type.limits.initial));
-                let mut table: Vec<String> = vec!["0".into(); table_type.limits.initial as usize];
+                let initial = table_type.limits.initial;
+                let maximum = table_type.limits.maximum.unwrap_or(u32::MAX);
+                // On ckb-vm a table is a static array, it grows up to its maximum but not past CKB_TABLE_CAPACITY.
+                let maximum = if is_ckb {
+                    std::cmp::max(initial, std::cmp::min(maximum, CKB_TABLE_CAPACITY))
+                } else {
+                    maximum
+                };
+                let mut table: Vec<String> = vec!["0".into(); initial as usize];
                 let mut space: Vec<String> = vec![];
-                for e in element_list {
-                    match e.offset {
-                        Some(ConstantOperator::I32Const { value }) => {
-                            for (j, item) in e.init.iter().enumerate() {
-                                match item {
-                                    wasmparser::ElementItem::Func(func_index) => {
-                                        let extern_name = &function_name_list[*func_index as usize];
-                                        let table_item = format!("((uintptr_t) ({}))", extern_name);
-                                        table[value as usize + j] = table_item;
-                                    }
-                                    wasmparser::ElementItem::Null => panic!("unreachable"),
-                                }
+                for e in element_list.iter() {
+                    let offset = wasm_instance
+                        .eval_offset(&store, &e.offset)
+                        .map_err(|err| err.at(&e.location))?;
+                    match offset {
+                        Offset::Static(value) => {
+                            if value.saturating_add(e.init.len() as u64) > table.len() as u64 {
+                                let reason = format!("element segment does not fit table {}", i);
+                                return Err(WascError::uninstantiable(reason).at(&e.location));
                             }
                         }
-                        Some(ConstantOperator::GlobalGet { global_index }) => {
-                            let global_addr = wasm_instance.global_addr_list[global_index as usize];
-                            let global_instance = &store.global_list[global_addr as usize];
-                            match global_instance {
-                                GlobalInstance::Wasm { global_type: _, value } => match value {
-                                    Value::I32(value) => {
-                                        for (j, item) in e.init.iter().enumerate() {
-                                            match item {
-                                                wasmparser::ElementItem::Func(func_index) => {
-                                                    let extern_name = &function_name_list[*func_index as usize];
-                                                    let table_item = format!("((uintptr_t) ({}))", extern_name);
-                                                    table[*value as usize + j] = table_item;
-                                                }
-                                                wasmparser::ElementItem::Null => panic!("unreachable"),
-                                            }
-                                        }
-                                    }
-                                    _ => panic!("unreachable"),
-                                },
-                                GlobalInstance::Host {
-                                    global_type: _,
-                                    import_name,
-                                } => {
-                                    for (j, item) in e.init.iter().enumerate() {
-                                        match item {
-                                            wasmparser::ElementItem::Func(func_index) => {
-                                                space.push(format!(
-                                                    "table{}[{} + {}] = ((uintptr_t) ({}));",
-                                                    i,
-                                                    format!("wavm_{}", import_name),
-                                                    j,
-                                                    function_name_list[*func_index as usize],
-                                                ));
-                                            }
-                                            wasmparser::ElementItem::Null => panic!("unreachable"),
-                                        }
-                                    }
-                                }
+                        Offset::Dynamic(ref name) => space.push(emit_table_check(i, name, e.init.len())),
+                    }
+                    for (j, item) in e.init.iter().enumerate() {
+                        let entry = match item {
+                            wasmparser::ElementItem::Func(func_index) => {
+                                format!("((uintptr_t) ({}))", element_name_list[*func_index as usize])
+                            }
+                            wasmparser::ElementItem::Null => String::from("0"),
+                        };
+                        match offset {
+                            Offset::Static(value) => {
+                                table[value as usize + j] = entry;
+                            }
+                            Offset::Dynamic(ref name) => {
+                                space.push(format!("instance->table{}.base[{} + {}] = {};", i, name, j, entry));
                             }
                         }
-                        _ => panic!("unreachable"),
                     }
                 }
 
-                glue_file.write(format!("uintptr_t table{}[{}] = {{", i, table_type.limits.initial));
+                glue_file.write(format!("const uintptr_t table{}_elem[{}] = {{", i, initial));
                 glue_file.write_array(table, 4);
                 glue_file.write("};");
-                glue_file.write(format!("uintptr_t* tableOffset{} = table{};", i, i));
+                // A table has one more slot than its maximum, which stays null: the static array on ckb-vm, the region
+                // reserved by wasc_table_init on posix. call_indirect clamps its index to the length of the table, see
+                // instrument.
+                if is_ckb {
+                    glue_file.write(format!("uintptr_t table{}[{}];", i, u64::from(maximum) + 1));
+                }
                 glue_file.write(format!("#define TABLE{}_DEFINED 1", i));
-                glue_file.write(format!("void init_table{}() {{", i));
+                glue_file.write(format!("void init_table{}({} *instance) {{", i, instance_type));
+                if is_ckb {
+                    glue_file.write(format!("memset(table{}, 0, sizeof(table{}));", i, i));
+                    glue_file.write(format!("instance->table{}.base = table{};", i, i));
+                    glue_file.write(format!("instance->table{}.length = {};", i, initial));
+                } else {
+                    // The table of a reset instance is reused and shrunk back to its initial length.
+                    glue_file.write(format!(
+                        "wasc_table_init(&instance->table{}, {}, {}u);",
+                        i, initial, maximum
+                    ));
+                    release_list.push(format!("wasc_table_free(&instance->table{});", i));
+                }
+                glue_file.write(format!("instance->table{}.maximum = {}u;", i, maximum));
+                glue_file.write(format!(
+                    "memcpy(instance->table{}.base, table{}_elem, sizeof(table{}_elem));",
+                    i, i, i
+                ));
                 for e in space {
                     glue_file.write(&e);
                 }
-                glue_file.write(format!("for (int i = 0; i < table{}_length; i++) {{", i));
+                // Entries left to zero are null references.
+                glue_file.write(format!("for (uint32_t i = 0; i < {}; i++) {{", initial));
+                glue_file.write(format!("if (instance->table{}.base[i] != 0) {{", i));
                 glue_file.write(format!(
-                    "table{}[i] = table{}[i] - ((uintptr_t) &tableReferenceBias) - 0x20;",
+                    "instance->table{}.base[i] = instance->table{}.base[i] - ((uintptr_t) &tableReferenceBias) - 0x20;",
                     i, i
                 ));
                 glue_file.write("}");
                 glue_file.write("}");
+                glue_file.write("}");
                 init_function_list.push(format!("init_table{}", i));
             }
             TableInstance::Host {
-                table_type: _,
+                table_type,
                 element_list,
                 import_name,
             } => {
-                let extern_name = get_external_name("table", i);
-                glue_file.write(format!("#define wavm_{}_length {}_length", import_name, extern_name));
-                glue_file.write(format!("extern uint32_t table{}_length;", i));
-                glue_file.write(format!("#define wavm_{} {}", import_name, extern_name));
-                glue_file.write(format!("extern uintptr_t table{}[];", i));
-                glue_file.write(format!("uintptr_t* tableOffset{} = table{};", i, i));
+                // An imported table is shared by all instances and never grows. Entries are biased one by one, so
+                // that instantiating the module again does not bias the entries twice.
+                if is_ckb {
+                    // The host defines the table as a static array, with one more null slot than its length, see
+                    // instrument.
+                    let extern_name = get_external_name("table", i);
+                    glue_file.write(format!("#define wavm_{}_length {}_length", import_name, extern_name));
+                    glue_file.write(format!("extern uint32_t table{}_length;", i));
+                    glue_file.write(format!("#define wavm_{} {}", import_name, extern_name));
+                    glue_file.write(format!("extern uintptr_t table{}[];", i));
+                } else {
+                    glue_file.write(format!("extern uint32_t wavm_{}_length;", import_name));
+                    // The host defines the length of the table, and may define the table itself once it has reserved
+                    // it with wasc_table_init. Otherwise the first instance reserves it, see wasc_table_import.
+                    glue_file.write(format!("table_instance __attribute__((weak)) wavm_{};", import_name));
+                }
                 glue_file.write(format!("#define TABLE{}_DEFINED 1", i));
-                glue_file.write(format!("void init_table{}() {{", i));
+                glue_file.write(format!(
+                    "// Imported table, at least {} elements.",
+                    table_type.limits.initial
+                ));
+                glue_file.write(format!("void init_table{}({} *instance) {{", i, instance_type));
+                if is_ckb {
+                    glue_file.write(format!("instance->table{}.base = table{};", i, i));
+                    glue_file.write(format!("instance->table{}.length = table{}_length;", i, i));
+                    glue_file.write(format!("instance->table{}.maximum = table{}_length;", i, i));
+                } else {
+                    glue_file.write(format!(
+                        "wasc_table_import(&instance->table{}, &wavm_{}, wavm_{}_length);",
+                        i, import_name, import_name
+                    ));
+                }
                 for e in element_list {
+                    let offset = wasm_instance
+                        .eval_offset(&store, &e.offset)
+                        .map_err(|err| err.at(&e.location))?;
+                    glue_file.write(emit_table_check(i, offset.emit().as_str(), e.init.len()));
                     for (j, item) in e.init.iter().enumerate() {
-                        let offset: String = match e.offset {
-                            Some(ConstantOperator::I32Const { value }) => value.to_string(),
-                            Some(ConstantOperator::GlobalGet { global_index }) => {
-                                let global_addr = wasm_instance.global_addr_list[global_index as usize];
-                                let global_instance = &store.global_list[global_addr as usize];
-                                match global_instance {
-                                    GlobalInstance::Wasm { global_type: _, value } => match value {
-                                        Value::I32(value) => value.to_string(),
-                                        _ => panic!("unreachable"),
-                                    },
-                                    GlobalInstance::Host {
-                                        global_type: _,
-                                        import_name,
-                                    } => format!("wavm_{}", import_name),
-                                }
-                            }
-                            _ => panic!("unreachable"),
+                        let entry = match item {
+                            wasmparser::ElementItem::Func(func_index) => format!(
+                                "((uintptr_t) ({})) - ((uintptr_t) &tableReferenceBias) - 0x20",
+                                element_name_list[*func_index as usize]
+                            ),
+                            wasmparser::ElementItem::Null => String::from("0"),
                         };
-                        match item {
-                            wasmparser::ElementItem::Func(func_index) => {
-                                glue_file.write(format!(
-                                    "table{}[{} + {}] = ((uintptr_t) ({}));",
-                                    i,
-                                    offset,
-                                    j,
-                                    get_external_name("functionDef", *func_index)
-                                ));
-                            }
-                            wasmparser::ElementItem::Null => panic!("unreachable"),
-                        }
+                        glue_file.write(format!(
+                            "instance->table{}.base[{} + {}] = {};",
+                            i,
+                            offset.emit(),
+                            j,
+                            entry
+                        ));
                     }
                 }
-                glue_file.write(format!("for (int i = 0; i < table{}_length; i++) {{", i));
-                glue_file.write(format!(
-                    "table{}[i] = table{}[i] - ((uintptr_t) &tableReferenceBias) - 0x20;",
-                    i, i
-                ));
-                glue_file.write("}");
                 glue_file.write("}");
                 init_function_list.push(format!("init_table{}", i));
             }
         }
     }
-    // Emit export.
+    // Emit segments. Every segment of the module has an entry in the instance, but only passive segments have content:
+    // active and declarative segments are dropped once the instance is initialized.
+    for (k, e) in data_segment_list.iter() {
+        glue_file.write(format!("const uint8_t data_segment{}[{}] = {{", k, e.init.len()));
+        glue_file.write_array(e.init.iter().map(|x| format!("0x{:02x}", x)).collect(), 16);
+        glue_file.write("};");
+    }
+    for (k, e) in element_segment_list.iter() {
+        glue_file.write(format!(
+            "const wasc_funcref element_segment{}[{}] = {{",
+            k,
+            e.init.len()
+        ));
+        let item_list = e
+            .init
+            .iter()
+            .map(|item| match item {
+                wasmparser::ElementItem::Func(func_index) => {
+                    emit_function_reference(&element_name_list[*func_index as usize])
+                }
+                wasmparser::ElementItem::Null => String::from("NULL"),
+            })
+            .collect();
+        glue_file.write_array(item_list, 4);
+        glue_file.write("};");
+    }
+    for (kind, count) in &[("data", data_segment_count), ("element", element_segment_count)] {
+        glue_file.write(format!(
+            "wasc_segment *wasc_{}_segment(void *context, uintptr_t index) {{",
+            kind
+        ));
+        if *count == 0 {
+            glue_file.write("return NULL;");
+        } else {
+            glue_file.write(format!("return &WASC_INSTANCE(context)->{}_segment[index];", kind));
+        }
+        glue_file.write("}");
+    }
+    glue_file.write(format!("void init_segment({} *instance) {{", instance_type));
+    for (kind, count) in &[("data", data_segment_count), ("element", element_segment_count)] {
+        if *count != 0 {
+            glue_file.write(format!(
+                "memset(instance->{}_segment, 0, sizeof(instance->{}_segment));",
+                kind, kind
+            ));
+        }
+    }
+    for (k, _) in data_segment_list.iter() {
+        glue_file.write(format!("instance->data_segment[{}].base = data_segment{};", k, k));
+        glue_file.write(format!(
+            "instance->data_segment[{}].length = sizeof(data_segment{});",
+            k, k
+        ));
+    }
+    for (k, e) in element_segment_list.iter() {
+        glue_file.write(format!("instance->element_segment[{}].base = element_segment{};", k, k));
+        glue_file.write(format!("instance->element_segment[{}].length = {};", k, e.init.len()));
+    }
+    glue_file.write("}");
+    init_function_list.push(String::from("init_segment"));
+    // Emit export. Besides the internal names used by the platform code, every export gets a plain C function in the
+    // public header, so that a host program can use the module like an ordinary C library.
+    let mut api_file = code_builder::CodeBuilder::create(&middle.path_header);
+    let api_id = format!("{}_H", prefix.to_uppercase());
+    api_file.write(format!(
+        include_str!("api.template"),
+        api_id = api_id,
+        prefix = prefix,
+        trap_code = middle.config.platform_common_trap_code_h
+    ));
+    let mut api_name_list: Vec<String> = vec![
+        format!("{}_new", prefix),
+        format!("{}_reset", prefix),
+        format!("{}_delete", prefix),
+        format!("{}_set_trap_hook", prefix),
+    ];
     let mut has_main = false;
     for e in wasm_instance.export_list {
         match e.kind {
-            wasmparser::ExternalKind::Function => {
+            ExternalKind::Function => {
                 glue_file.write(format!(
                     "#define wavm_exported_function_{} {}",
                     cnaming(&e.field),
@@ -924,43 +2617,307 @@ pub fn generate(middle: &mut context::Middle) -> Result<(), Box<dyn std::error::
                 if &e.field == "_start" {
                     has_main = true;
                 }
+                let function_type =
+                    match &store.function_list[wasm_instance.function_addr_list[e.index as usize] as usize] {
+                        FunctionInstance::WasmFunc { function_type } => function_type,
+                        FunctionInstance::HostFunc { function_type, .. } => function_type,
+                    };
+                let mut params: Vec<String> = vec![format!("{}_instance *instance", prefix)];
+                let mut args: Vec<String> = vec![String::from("WASC_CONTEXT(instance)")];
+                for (i, t) in function_type.params.iter().enumerate() {
+                    params.push(format!("{} p{}", emit_type(*t)?, i));
+                    args.push(format!("p{}", i));
+                }
+                let name = format!("{}_{}", prefix, cnaming(&e.field));
+                let call = format!("{}({})", function_name_list[e.index as usize], args.join(", "));
+                if function_type.returns.len() > 1 {
+                    // Multiple results are returned in a struct of their own, value0 is the first one.
+                    let name = reserve_api_name(&mut api_name_list, name, &["", "_results"]);
+                    let mut fields: Vec<String> = vec![];
+                    let mut values: Vec<String> = vec![];
+                    for (i, t) in function_type.returns.iter().enumerate() {
+                        fields.push(format!("{} value{};", emit_type(*t)?, i));
+                        values.push(format!("r.value{}", i));
+                    }
+                    api_file.write(format!("typedef struct {{ {} }} {}_results;", fields.join(" "), name));
+                    let prototype = format!("{}_results {}({})", name, name, params.join(", "));
+                    api_file.write(format!("{};", prototype));
+                    glue_file.write(format!("{} {{", prototype));
+                    glue_file.write(format!("{} r = {};", emit_results_type(&function_type.returns)?, call));
+                    glue_file.write(format!("{}_results results = {{{}}};", name, values.join(", ")));
+                    glue_file.write("return results;");
+                    glue_file.write("}");
+                } else {
+                    let return_type = match function_type.returns.first() {
+                        Some(t) => emit_type(*t)?,
+                        None => String::from("void"),
+                    };
+                    let name = reserve_api_name(&mut api_name_list, name, &[""]);
+                    let prototype = format!("{} {}({})", return_type, name, params.join(", "));
+                    api_file.write(format!("{};", prototype));
+                    glue_file.write(format!("{} {{", prototype));
+                    if function_type.returns.is_empty() {
+                        glue_file.write(format!("{};", call));
+                    } else {
+                        glue_file.write(format!("return {}.value;", call));
+                    }
+                    glue_file.write("}");
+                }
+            }
+            ExternalKind::Memory => {
+                let i = wasm_instance.memory_addr_list[e.index as usize];
+                let name = format!("{}_memory_{}", prefix, cnaming(&e.field));
+                let name = reserve_api_name(&mut api_name_list, name, &["", "_pages"]);
+                let param = format!("{}_instance *instance", prefix);
+                api_file.write(format!("uint8_t *{}({});", name, param));
+                api_file.write(format!("uint64_t {}_pages({});", name, param));
+                glue_file.write(format!("uint8_t *{}({}) {{", name, param));
+                glue_file.write(format!("return instance->memory{}.base;", i));
+                glue_file.write("}");
+                glue_file.write(format!("uint64_t {}_pages({}) {{", name, param));
+                glue_file.write(format!("return instance->memory{}.num_pages;", i));
+                glue_file.write("}");
+            }
+            ExternalKind::Global => {
+                let i = wasm_instance.global_addr_list[e.index as usize];
+                let global_type = match &store.global_list[i as usize] {
+                    GlobalInstance::Wasm { global_type, .. } => global_type,
+                    GlobalInstance::Host { global_type, .. } => global_type,
+                };
+                let type_string = emit_type(global_type.content_type)?;
+                let global_name = if global_type.mutable {
+                    format!("instance->{}", get_external_name("global", i))
+                } else {
+                    get_external_name("global", i)
+                };
+                let name = format!("{}_global_{}", prefix, cnaming(&e.field));
+                let name = reserve_api_name(&mut api_name_list, name, &["_get", "_set"]);
+                let getter = format!("{} {}_get({}_instance *instance)", type_string, name, prefix);
+                api_file.write(format!("{};", getter));
+                glue_file.write(format!("{} {{", getter));
+                glue_file.write(format!("return {};", global_name));
+                glue_file.write("}");
+                if global_type.mutable {
+                    let setter = format!(
+                        "void {}_set({}_instance *instance, {} value)",
+                        name, prefix, type_string
+                    );
+                    api_file.write(format!("{};", setter));
+                    glue_file.write(format!("{} {{", setter));
+                    glue_file.write(format!("{} = value;", global_name));
+                    glue_file.write("}");
+                }
+            }
+            ExternalKind::Table => {
+                let i = wasm_instance.table_addr_list[e.index as usize];
+                let table_type = match &store.table_list[i as usize] {
+                    TableInstance::Wasm { table_type, .. } => table_type,
+                    TableInstance::Host { table_type, .. } => table_type,
+                };
+                let type_string = emit_type(table_type.element_type)?;
+                let table = format!("instance->table{}", i);
+                let name = format!("{}_table_{}", prefix, cnaming(&e.field));
+                let name = reserve_api_name(&mut api_name_list, name, &["_get", "_set", "_size"]);
+                // Elements out of the table read as the null reference, and can not be set.
+                let getter = format!(
+                    "{} {}_get({}_instance *instance, uint32_t index)",
+                    type_string, name, prefix
+                );
+                api_file.write(format!("{};", getter));
+                glue_file.write(format!("{} {{", getter));
+                glue_file.write(format!("if (index >= {}.length) {{", table));
+                glue_file.write("return NULL;");
+                glue_file.write("}");
+                glue_file.write(format!("return wasc_table_reference({}.base[index]);", table));
+                glue_file.write("}");
+                let setter = format!(
+                    "int {}_set({}_instance *instance, uint32_t index, {} value)",
+                    name, prefix, type_string
+                );
+                api_file.write(format!("{};", setter));
+                glue_file.write(format!("{} {{", setter));
+                glue_file.write(format!("if (index >= {}.length) {{", table));
+                glue_file.write("return -1;");
+                glue_file.write("}");
+                glue_file.write(format!("{}.base[index] = wasc_table_entry(value);", table));
+                glue_file.write("return 0;");
+                glue_file.write("}");
+                let size = format!("uint32_t {}_size({}_instance *instance)", name, prefix);
+                api_file.write(format!("{};", size));
+                glue_file.write(format!("{} {{", size));
+                glue_file.write(format!("return {}.length;", table));
+                glue_file.write("}");
+            }
+            ExternalKind::Tag => {
+                let name = format!("{}_tag_{}", prefix, cnaming(&e.field));
+                let name = reserve_api_name(&mut api_name_list, name, &[""]);
+                let prototype = format!("const wasc_tag *{}(void)", name);
+                api_file.write(format!("{};", prototype));
+                glue_file.write(format!("{} {{", prototype));
+                glue_file.write(format!(
+                    "return &{};",
+                    get_external_name("biasedExceptionTypeId", wasm_instance.tag_addr_list[e.index as usize])
+                ));
+                glue_file.write("}");
             }
-            _ => {}
         }
     }
+    api_file.write(format!("#endif /* {} */", api_id));
+    api_file.close()?;
     // Emit init function.
-    glue_file.write("void init() {");
-    for e in init_function_list {
-        glue_file.write(format!("{}();", e));
+    glue_file.write(format!("void init({} *instance) {{", instance_type));
+    for e in &init_function_list {
+        glue_file.write(format!("{}(instance);", e));
     }
     if let Some(function_index) = wasm_module.start {
-        glue_file.write(format!("{}(NULL);", function_name_list[function_index as usize]));
+        glue_file.write(format!(
+            "{}(WASC_CONTEXT(instance));",
+            function_name_list[function_index as usize]
+        ));
+    }
+    glue_file.write("}");
+    // Emit reset function. Every init function also works on an instance which has been used, so resetting an
+    // instance is initializing it again.
+    glue_file.write(format!("void reset({} *instance) {{", instance_type));
+    glue_file.write("init(instance);");
+    glue_file.write("}");
+    // Emit instantiate and release functions.
+    glue_file.write(format!("{} *wasc_instantiate(void) {{", instance_type));
+    if is_ckb {
+        glue_file.write(format!("{} *instance = &wasc_instance_storage;", instance_type));
+    } else {
+        glue_file.write(format!(
+            "{} *instance = wasc_instance_alloc(sizeof({}));",
+            instance_type, instance_type
+        ));
+        glue_file.write("if (instance == NULL) {");
+        glue_file.write("return NULL;");
+        glue_file.write("}");
     }
+    glue_file.write("init(instance);");
+    glue_file.write("return instance;");
+    glue_file.write("}");
+    glue_file.write(format!("void wasc_release({} *instance) {{", instance_type));
+    for e in &release_list {
+        glue_file.write(e);
+    }
+    if !is_ckb {
+        glue_file.write(format!("wasc_instance_free(instance, sizeof({}));", instance_type));
+    }
+    glue_file.write("}");
+    // Emit thread instantiate and release functions. A thread spawned by wasi::thread-spawn runs in an instance of its
+    // own, which shares the shared memories of the instance that spawns it instead of initializing them. Imported
+    // memories are attached as they are: their data segments have been written by the first instance, and the other
+    // threads are using them. The start function has run in the first instance too, threads do not run it again.
+    if let context::Platform::PosixX8664Wasi = middle.config.platform {
+        let mut attach_list: Vec<(u32, String)> = shared_memory_list
+            .iter()
+            .map(|i| (*i, format!("&parent->memory{}", i)))
+            .collect();
+        attach_list.extend(imported_memory_list.iter().map(|(i, e)| (*i, format!("&wavm_{}", e))));
+        let attach_init_list: Vec<String> = attach_list.iter().map(|(i, _)| format!("init_memory{}", i)).collect();
+        let attach_release_list: Vec<String> = attach_list
+            .iter()
+            .flat_map(|(i, _)| {
+                vec![
+                    format!("wasc_memory_free(&instance->memory{});", i),
+                    format!("wasc_memory_detach(&instance->memory{});", i),
+                ]
+            })
+            .collect();
+        glue_file.write("int wasc_memory_attach(memory_instance *memory, memory_instance *source);");
+        glue_file.write(format!(
+            "{} *wasc_instantiate_thread({} *parent) {{",
+            instance_type, instance_type
+        ));
+        glue_file.write(format!(
+            "{} *instance = wasc_instance_alloc(sizeof({}));",
+            instance_type, instance_type
+        ));
+        glue_file.write("if (instance == NULL) {");
+        glue_file.write("return NULL;");
+        glue_file.write("}");
+        // A failed attach leaves no view of the instance behind, growing the memory would write into it once freed.
+        for (k, (i, source)) in attach_list.iter().enumerate() {
+            glue_file.write(format!(
+                "if (wasc_memory_attach(&instance->memory{}, {}) != 0) {{",
+                i, source
+            ));
+            for (j, _) in &attach_list[..=k] {
+                glue_file.write(format!("wasc_memory_detach(&instance->memory{});", j));
+            }
+            glue_file.write(format!("wasc_instance_free(instance, sizeof({}));", instance_type));
+            glue_file.write("return NULL;");
+            glue_file.write("}");
+        }
+        // Other threads may be spawning or releasing instances meanwhile, wasc_memory_init keeps the region list of
+        // the platform consistent.
+        for e in init_function_list.iter().filter(|e| !attach_init_list.contains(e)) {
+            glue_file.write(format!("{}(instance);", e));
+        }
+        glue_file.write("return instance;");
+        glue_file.write("}");
+        glue_file.write(format!("void wasc_release_thread({} *instance) {{", instance_type));
+        for (i, _) in &attach_list {
+            glue_file.write(format!("wasc_memory_detach(&instance->memory{});", i));
+        }
+        for e in release_list.iter().filter(|e| !attach_release_list.contains(e)) {
+            glue_file.write(e);
+        }
+        glue_file.write(format!("wasc_instance_free(instance, sizeof({}));", instance_type));
+        glue_file.write("}");
+    }
+    // Emit public instance functions.
+    if let context::Platform::PosixX8664Wasi = middle.config.platform {
+        glue_file.write("void init_wasi(void);");
+    }
+    glue_file.write(format!("{}_instance *{}_new(void) {{", prefix, prefix));
+    if let context::Platform::PosixX8664Wasi = middle.config.platform {
+        glue_file.write("static pthread_once_t wasi_once = PTHREAD_ONCE_INIT;");
+        glue_file.write("pthread_once(&wasi_once, init_wasi);");
+    }
+    glue_file.write("return wasc_instantiate();");
+    glue_file.write("}");
+    glue_file.write(format!("void {}_reset({}_instance *instance) {{", prefix, prefix));
+    glue_file.write("reset(instance);");
+    glue_file.write("}");
+    glue_file.write(format!("void {}_delete({}_instance *instance) {{", prefix, prefix));
+    glue_file.write("wasc_release(instance);");
+    glue_file.write("}");
+    glue_file.write("extern void (*wasc_trap_hook)(int32_t trap, const char *function);");
+    glue_file.write(format!(
+        "void {}_set_trap_hook(void (*hook)(int32_t trap, const char *function)) {{",
+        prefix
+    ));
+    glue_file.write("wasc_trap_hook = hook;");
     glue_file.write("}");
     // Emit main function.
+    // Libraries are linked into a host program which has its own main function.
     if has_main {
         glue_file.write("int32_t g_argc;");
         glue_file.write("char **g_argv;");
+        glue_file.write("#ifndef WASC_NO_MAIN");
         glue_file.write("int main(int argc, char *argv[]) {");
         glue_file.write("g_argc = argc;");
         glue_file.write("g_argv = argv;");
-        match middle.config.platform {
-            context::Platform::PosixX8664Wasi => {
-                glue_file.write("init_wasi();");
-            }
-            _ => {}
+        if let context::Platform::PosixX8664Wasi = middle.config.platform {
+            glue_file.write("init_wasi();");
         }
-        glue_file.write("init();");
+        glue_file.write(format!("{} *instance = wasc_instantiate();", instance_type));
+        glue_file.write("if (instance == NULL) {");
+        glue_file.write("return 1;");
+        glue_file.write("}");
         match middle.config.platform {
             context::Platform::CKBVMAssemblyScript => {
-                glue_file.write("return wavm_exported_function__start(NULL).value;");
+                glue_file.write("return wavm_exported_function__start(WASC_CONTEXT(instance)).value;");
             }
             _ => {
-                glue_file.write("wavm_exported_function__start(NULL);");
+                glue_file.write("wavm_exported_function__start(WASC_CONTEXT(instance));");
                 glue_file.write("return 0;");
             }
         }
         glue_file.write("}");
+        glue_file.write("#endif /* WASC_NO_MAIN */");
     }
     glue_file.write(format!("#endif /* {} */", header_id));
     glue_file.close()?;
@@ -15,15 +15,14 @@ impl CodeBuilder {
         }
     }
 
-    pub fn append<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn append<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
         let mut c = CodeBuilder::create(&path);
-        c.data = std::str::from_utf8(&std::fs::read(&path)?)?.to_string();
-        return Ok(c);
+        c.data = std::fs::read_to_string(&path)?;
+        Ok(c)
     }
 
-    pub fn close(&self) -> Result<(), Box<dyn std::error::Error>> {
-        std::fs::write(&self.path, &self.data)?;
-        Ok(())
+    pub fn close(&self) -> std::io::Result<()> {
+        std::fs::write(&self.path, &self.data)
     }
 
     // Function write will add indent and "\n" automatically.
@@ -36,13 +35,22 @@ impl CodeBuilder {
             self.data += "\n";
             return;
         }
+        // Lines like "} else {" close a block and open another one.
+        if line.starts_with('}') && line.ends_with('{') {
+            self.head_whitespace -= 2;
+            self.data += &" ".repeat(self.head_whitespace);
+            self.data += line;
+            self.data += "\n";
+            self.head_whitespace += 2;
+            return;
+        }
         self.data += &" ".repeat(self.head_whitespace);
         self.data += line;
         self.data += "\n";
-        if line.ends_with("{") {
+        if line.ends_with('{') {
             self.head_whitespace += 2;
         }
-        if line.ends_with("}") {
+        if line.ends_with('}') {
             self.head_whitespace -= 2;
         }
     }
@@ -1,37 +1,239 @@
 use super::aot_generator;
 use super::code_builder;
 use super::context;
+use super::error::WascError;
+use super::gcc;
+use super::instrument;
 
-// The main entry function for wasc compiler. It is expected that it will be a complete set of compilation work.
-// TODO: only a small part is realized.
-pub fn compile<P: AsRef<std::path::Path>>(
-    path: P,
+// Compiler is the library entry of wasc. It drives a complete build from a wasm/wa(s)t source file to a native
+// executable or library, and reports every failure as a WascError instead of terminating the process.
+//
+// Example:
+//
+//   let artifacts = wasc::Compiler::new(wasc::context::Platform::PosixX8664Wasi)
+//       .wavm("/usr/local/bin/wavm")
+//       .emit(wasc::context::Emit::StaticLib)
+//       .compile("helloworld.wasm")?;
+#[derive(Clone, Debug)]
+pub struct Compiler {
     config: context::Config,
-) -> Result<context::Middle, Box<dyn std::error::Error>> {
+    emit: Vec<context::Emit>,
+    save_temps: bool,
+}
+
+// Artifacts are the files left by a successful build.
+#[derive(Clone, Debug)]
+pub struct Artifacts {
+    // Every emitted file, in the order they were requested. They are placed next to the source file, except the glue
+    // header and the C source which stay in the build folder with the platform headers.
+    pub outputs: Vec<std::path::PathBuf>,
+    // The folder holding the intermediate files, if they were saved or some outputs are in it.
+    pub build_dir: Option<std::path::PathBuf>,
+}
+
+impl Compiler {
+    pub fn new(platform: context::Platform) -> Self {
+        let config = context::Config {
+            binary_cc: platform.default_cc().to_string(),
+            binary_ar: platform.default_ar().to_string(),
+            platform,
+            ..Default::default()
+        };
+        Compiler::with_config(config)
+    }
+
+    // Start from a complete config, for callers which need to replace the platform files.
+    pub fn with_config(config: context::Config) -> Self {
+        Compiler {
+            config,
+            emit: vec![],
+            save_temps: false,
+        }
+    }
+
+    // Path of the WAVM binary.
+    pub fn wavm<S: Into<String>>(mut self, binary: S) -> Self {
+        self.config.binary_wavm = binary.into();
+        self
+    }
+
+    // Path of the C compiler.
+    pub fn cc<S: Into<String>>(mut self, binary: S) -> Self {
+        self.config.binary_cc = binary.into();
+        self
+    }
+
+    // Path of the archiver.
+    pub fn ar<S: Into<String>>(mut self, binary: S) -> Self {
+        self.config.binary_ar = binary.into();
+        self
+    }
+
+    // Number of pages of imported memories. The host may still change it by defining wavm_<import>_pages.
+    pub fn imported_memory_pages(mut self, pages: u64) -> Self {
+        self.config.imported_memory_pages = Some(pages);
+        self
+    }
+
+    // Maximum number of pages of imported memories. The host may still change it by defining wavm_<import>_max_pages.
+    pub fn imported_memory_max_pages(mut self, pages: u64) -> Self {
+        self.config.imported_memory_max_pages = Some(pages);
+        self
+    }
+
+    // Add a kind of output. An executable is emitted if none is given.
+    pub fn emit(mut self, emit: context::Emit) -> Self {
+        if !self.emit.contains(&emit) {
+            self.emit.push(emit);
+        }
+        self
+    }
+
+    // Keep the xx_build folder after a successful build.
+    pub fn save_temps(mut self, save_temps: bool) -> Self {
+        self.save_temps = save_temps;
+        self
+    }
+
+    pub fn config(&self) -> &context::Config {
+        &self.config
+    }
+
+    pub fn compile<P: AsRef<std::path::Path>>(&self, path: P) -> Result<Artifacts, WascError> {
+        let middle = compile(path, self.config.clone())?;
+        let emit = if self.emit.is_empty() {
+            vec![context::Emit::Exe]
+        } else {
+            self.emit.clone()
+        };
+        let mut outputs: Vec<std::path::PathBuf> = vec![];
+        for e in emit {
+            match e {
+                // The glue header and the C source include the platform headers by relative path, they stay in the
+                // build folder next to them.
+                context::Emit::Glue => {
+                    outputs.push(middle.path_platform_code_folder.clone());
+                    outputs.push(middle.path_header.clone());
+                    outputs.push(middle.path_glue.clone());
+                }
+                context::Emit::C => {
+                    outputs.push(middle.path_platform_code_folder.clone());
+                    outputs.push(middle.path_header.clone());
+                    outputs.push(middle.path_glue.clone());
+                    outputs.push(middle.path_c.clone());
+                }
+                context::Emit::Object => {
+                    outputs.push(copy_output(&middle, &middle.path_object)?);
+                }
+                context::Emit::StaticLib => {
+                    gcc::build_static_lib(&middle)?;
+                    outputs.push(copy_output(&middle, &middle.path_header)?);
+                    outputs.push(copy_output(&middle, &middle.path_static_lib)?);
+                }
+                context::Emit::SharedLib => {
+                    gcc::build_shared_lib(&middle)?;
+                    outputs.push(copy_output(&middle, &middle.path_header)?);
+                    outputs.push(copy_output(&middle, &middle.path_shared_lib)?);
+                }
+                context::Emit::Exe => {
+                    gcc::build(&middle)?;
+                    outputs.push(copy_output(&middle, &middle.path_output)?);
+                }
+            }
+        }
+        // Several kinds share the same headers.
+        let mut unique: Vec<std::path::PathBuf> = vec![];
+        for e in outputs {
+            if !unique.contains(&e) {
+                unique.push(e);
+            }
+        }
+        let outputs = unique;
+
+        if self.save_temps || outputs.iter().any(|e| e.starts_with(&middle.path_prog)) {
+            return Ok(Artifacts {
+                outputs,
+                build_dir: Some(middle.path_prog),
+            });
+        }
+        rog::debugln!("remove {}", middle.path_prog.to_str().unwrap());
+        std::fs::remove_dir_all(&middle.path_prog)?;
+        Ok(Artifacts {
+            outputs,
+            build_dir: None,
+        })
+    }
+}
+
+// Copy a file of the build folder next to the source file.
+fn copy_output(middle: &context::Middle, src: &std::path::Path) -> Result<std::path::PathBuf, WascError> {
+    let dst = middle.file.with_file_name(src.file_name().unwrap());
+    rog::debugln!("copy {} {}", src.to_str().unwrap(), dst.to_str().unwrap());
+    std::fs::copy(src, &dst)?;
+    Ok(dst)
+}
+
+// Run "wavm compile". WAVM compiles the module with its call_indirect clamped, see instrument::clamp_call_indirect. A
+// text module is assembled first.
+fn wavm_compile(middle: &context::Middle) -> Result<(), WascError> {
+    let mut args = vec!["compile", "--enable", "all"];
+    if let context::Platform::CKBVMAssemblyScript | context::Platform::CKBVMSpectest = middle.config.platform {
+        args.extend(&["--target-triple", "riscv64"]);
+    }
+    let mut source = middle.file.clone();
+    let mut wasm = std::fs::read(&source)?;
+    if !wasm.starts_with(b"\0asm") {
+        wavm(middle, &["assemble"], &[&source, &middle.path_instrumented])?;
+        source = middle.path_instrumented.clone();
+        wasm = std::fs::read(&source)?;
+    }
+    match instrument::clamp_call_indirect(&wasm) {
+        Ok(wasm) => {
+            rog::debugln!("create {}", middle.path_instrumented.to_str().unwrap());
+            std::fs::write(&middle.path_instrumented, wasm)?;
+            wavm(middle, &args, &[&middle.path_instrumented, &middle.path_precompiled])
+        }
+        // An invalid module is reported by WAVM, which tells why it is invalid.
+        Err(err) => {
+            wavm(middle, &args, &[&source, &middle.path_precompiled])?;
+            Err(err)
+        }
+    }
+}
+
+// Run a WAVM command and capture its stderr.
+fn wavm(middle: &context::Middle, args: &[&str], paths: &[&std::path::Path]) -> Result<(), WascError> {
+    let mut cmd_wavm = std::process::Command::new(&middle.config.binary_wavm);
+    cmd_wavm.args(args).args(paths);
+    rog::debugln!("$ {:?}", cmd_wavm);
+    let output = match cmd_wavm.output() {
+        Ok(output) => output,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(WascError::WavmNotFound(middle.config.binary_wavm.clone()));
+        }
+        Err(err) => return Err(WascError::Io(err)),
+    };
+    if !output.status.success() {
+        return Err(WascError::WavmFailed {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    Ok(())
+}
+
+// The main entry function for wasc compiler. It runs WAVM, writes the platform files and generates the glue code,
+// leaving the final build to gcc::build.
+pub fn compile<P: AsRef<std::path::Path>>(path: P, config: context::Config) -> Result<context::Middle, WascError> {
     let mut middle = context::Middle::default();
     middle.init_config(config);
-    middle.init_file(path);
+    middle.init_file(path)?;
 
     rog::debugln!("create {}", middle.path_prog.to_str().unwrap());
     if let Ok(()) = std::fs::create_dir(&middle.path_prog) {}
 
     // Get wavm precompiled module.
-    let mut cmd_wavm = std::process::Command::new(&middle.config.binary_wavm);
-    cmd_wavm.arg("compile").arg("--enable").arg("all");
-    match middle.config.platform {
-        context::Platform::CKBVMAssemblyScript | context::Platform::CKBVMSpectest => {
-            cmd_wavm.arg("--target-triple").arg("riscv64");
-        }
-        _ => {}
-    }
-    cmd_wavm
-        .arg(middle.file.clone())
-        .arg(middle.path_precompiled.to_str().unwrap());
-    rog::debugln!("$ {:?}", cmd_wavm);
-    let exit_status = cmd_wavm.spawn()?.wait()?;
-    if !exit_status.success() {
-        std::process::exit(exit_status.code().unwrap());
-    }
+    wavm_compile(&middle)?;
     // Init platform based code.
     rog::debugln!("create {}", middle.path_platform_code_folder.to_str().unwrap());
     if let Ok(()) = std::fs::create_dir(&middle.path_platform_code_folder) {}
@@ -40,85 +242,116 @@ pub fn compile<P: AsRef<std::path::Path>>(
     rog::debugln!("create {}", &middle.path_platform_common_wavm_h.to_str().unwrap());
     std::fs::write(
         &middle.path_platform_common_wavm_h,
-        &middle.config.platform_common_wavm_h,
+        middle.config.platform_common_wavm_h,
+    )?;
+    rog::debugln!("create {}", &middle.path_platform_common_trap_h.to_str().unwrap());
+    std::fs::write(
+        &middle.path_platform_common_trap_h,
+        middle.config.platform_common_trap_h,
+    )?;
+    rog::debugln!("create {}", &middle.path_platform_common_trap_code_h.to_str().unwrap());
+    std::fs::write(
+        &middle.path_platform_common_trap_code_h,
+        middle.config.platform_common_trap_code_h,
+    )?;
+    rog::debugln!("create {}", &middle.path_platform_common_table_h.to_str().unwrap());
+    std::fs::write(
+        &middle.path_platform_common_table_h,
+        middle.config.platform_common_table_h,
+    )?;
+    rog::debugln!("create {}", &middle.path_platform_common_memory_h.to_str().unwrap());
+    std::fs::write(
+        &middle.path_platform_common_memory_h,
+        middle.config.platform_common_memory_h,
     )?;
     match middle.config.platform {
         context::Platform::CKBVMAssemblyScript => {
             rog::debugln!("create {}", middle.path_platform_header.to_str().unwrap());
             std::fs::write(
                 &middle.path_platform_header,
-                &middle.config.platform_ckb_vm_assemblyscript_h,
+                middle.config.platform_ckb_vm_assemblyscript_h,
             )?;
             rog::debugln!(
                 "create {}",
                 middle.path_platform_lds.to_owned().unwrap().to_str().unwrap()
             );
             std::fs::write(
-                &middle.path_platform_lds.to_owned().unwrap(),
-                &middle.config.platform_ckb_vm_assemblyscript_lds,
+                middle.path_platform_lds.to_owned().unwrap(),
+                middle.config.platform_ckb_vm_assemblyscript_lds,
             )?;
             rog::debugln!("create {}", middle.path_platform_s.to_str().unwrap());
             std::fs::write(
                 &middle.path_platform_s,
-                &middle.config.platform_ckb_vm_assemblyscript_runtime_s,
+                middle.config.platform_ckb_vm_assemblyscript_runtime_s,
             )?;
         }
         context::Platform::CKBVMSpectest => {
             rog::debugln!("create {}", middle.path_platform_header.to_str().unwrap());
-            std::fs::write(&middle.path_platform_header, &middle.config.platform_ckb_vm_spectest_h)?;
+            std::fs::write(&middle.path_platform_header, middle.config.platform_ckb_vm_spectest_h)?;
             rog::debugln!(
                 "create {}",
                 middle.path_platform_lds.to_owned().unwrap().to_str().unwrap()
             );
             std::fs::write(
-                &middle.path_platform_lds.to_owned().unwrap(),
-                &middle.config.platform_ckb_vm_spectest_lds,
+                middle.path_platform_lds.to_owned().unwrap(),
+                middle.config.platform_ckb_vm_spectest_lds,
             )?;
             rog::debugln!("create {}", middle.path_platform_s.to_str().unwrap());
             std::fs::write(
                 &middle.path_platform_s,
-                &middle.config.platform_ckb_vm_spectest_runtime_s,
+                middle.config.platform_ckb_vm_spectest_runtime_s,
             )?;
         }
         context::Platform::PosixX8664 => {
             rog::debugln!("create {}", middle.path_platform_header.to_str().unwrap());
-            std::fs::write(&middle.path_platform_header, &middle.config.platform_posix_x86_64_h)?;
+            std::fs::write(&middle.path_platform_header, middle.config.platform_posix_x86_64_h)?;
             rog::debugln!("create {}", middle.path_platform_s.to_str().unwrap());
-            std::fs::write(&middle.path_platform_s, &middle.config.platform_posix_x86_64_runtime_s)?;
+            std::fs::write(&middle.path_platform_s, middle.config.platform_posix_x86_64_runtime_s)?;
         }
         context::Platform::PosixX8664Spectest => {
             rog::debugln!("create {}", middle.path_platform_header.to_str().unwrap());
             std::fs::write(
                 &middle.path_platform_header,
-                &middle.config.platform_posix_x86_64_spectest_h,
+                middle.config.platform_posix_x86_64_spectest_h,
             )?;
             rog::debugln!("create {}", middle.path_platform_s.to_str().unwrap());
             std::fs::write(
                 &middle.path_platform_s,
-                &middle.config.platform_posix_x86_64_spectest_runtime_s,
+                middle.config.platform_posix_x86_64_spectest_runtime_s,
             )?;
         }
         context::Platform::PosixX8664Wasi => {
             rog::debugln!("create {}", middle.path_platform_header.to_str().unwrap());
-            std::fs::write(
-                &middle.path_platform_header,
-                &middle.config.platform_posix_x86_64_wasi_h,
-            )?;
+            std::fs::write(&middle.path_platform_header, middle.config.platform_posix_x86_64_wasi_h)?;
             rog::debugln!("create {}", middle.path_platform_s.to_str().unwrap());
             std::fs::write(
                 &middle.path_platform_s,
-                &middle.config.platform_posix_x86_64_wasi_runtime_s,
+                middle.config.platform_posix_x86_64_wasi_runtime_s,
             )?;
             rog::debugln!("create {}", &middle.path_platform_common_wasi_h.to_str().unwrap());
             std::fs::write(
                 &middle.path_platform_common_wasi_h,
-                &middle.config.platform_common_wasi_h,
+                middle.config.platform_common_wasi_h,
             )?;
         }
         context::Platform::Unknown => {
-            panic!("unreachable");
+            return Err(WascError::UnknownPlatform);
         }
     }
+    if let context::Platform::PosixX8664 | context::Platform::PosixX8664Spectest | context::Platform::PosixX8664Wasi =
+        middle.config.platform
+    {
+        rog::debugln!("create {}", &middle.path_platform_common_posix_h.to_str().unwrap());
+        std::fs::write(
+            &middle.path_platform_common_posix_h,
+            middle.config.platform_common_posix_h,
+        )?;
+        rog::debugln!("create {}", &middle.path_platform_common_exception_h.to_str().unwrap());
+        std::fs::write(
+            &middle.path_platform_common_exception_h,
+            middle.config.platform_common_exception_h,
+        )?;
+    }
 
     // AOT generator
     aot_generator::generate(&mut middle)?;
@@ -130,8 +363,9 @@ pub fn compile<P: AsRef<std::path::Path>>(
         context::Platform::PosixX8664 => "platform/posix_x86_64.h",
         context::Platform::PosixX8664Spectest => "platform/posix_x86_64_spectest.h",
         context::Platform::PosixX8664Wasi => "platform/posix_x86_64_wasi.h",
-        context::Platform::Unknown => panic!("unreachable"),
+        context::Platform::Unknown => return Err(WascError::UnknownPlatform),
     };
+    main_file.write(format!("#include \"{}.h\"", middle.file_stem).as_str());
     main_file.write(format!("#include \"{}_glue.h\"", middle.file_stem).as_str());
     main_file.write(format!("#include \"{}\"", platform_header));
     main_file.close()?;
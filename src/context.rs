@@ -1,3 +1,5 @@
+use super::error::WascError;
+
 #[derive(Clone, Debug)]
 pub enum Platform {
     PosixX8664,
@@ -8,12 +10,94 @@ pub enum Platform {
     Unknown,
 }
 
+impl Platform {
+    // The C compiler used when the caller does not specify one.
+    pub fn default_cc(&self) -> &'static str {
+        match self {
+            Platform::CKBVMAssemblyScript | Platform::CKBVMSpectest => "riscv64-unknown-elf-gcc",
+            _ => "gcc",
+        }
+    }
+
+    // The archiver used to create static libraries.
+    pub fn default_ar(&self) -> &'static str {
+        match self {
+            Platform::CKBVMAssemblyScript | Platform::CKBVMSpectest => "riscv64-unknown-elf-ar",
+            _ => "ar",
+        }
+    }
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        if cfg!(unix) {
+            Platform::PosixX8664Wasi
+        } else {
+            Platform::Unknown
+        }
+    }
+}
+
+impl std::str::FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ckb_vm_assemblyscript" => Ok(Platform::CKBVMAssemblyScript),
+            "ckb_vm_spectest" => Ok(Platform::CKBVMSpectest),
+            "posix_x86_64" => Ok(Platform::PosixX8664),
+            "posix_x86_64_spectest" => Ok(Platform::PosixX8664Spectest),
+            "posix_x86_64_wasi" => Ok(Platform::PosixX8664Wasi),
+            x => Err(format!("unknown platform {}", x)),
+        }
+    }
+}
+
+// Emit is a kind of output of a build. Several kinds can be emitted by one build, each one is placed next to the
+// source file. The glue header and the C source are left in the xx_build folder, together with the platform headers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emit {
+    // The glue header xx_glue.h, together with the public header xx.h and the platform headers it includes.
+    Glue,
+    // The C source xx.c, together with the glue header, the public header and the platform headers.
+    C,
+    // The object xx.o precompiled by WAVM.
+    Object,
+    // A static archive libxx.a with the WAVM object, the glue and the platform runtime, and the public header xx.h.
+    StaticLib,
+    // A shared object libxx.so with the WAVM object, the glue and the platform runtime, and the public header xx.h.
+    SharedLib,
+    // A native executable.
+    Exe,
+}
+
+impl std::str::FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "glue" => Ok(Emit::Glue),
+            "c" => Ok(Emit::C),
+            "obj" => Ok(Emit::Object),
+            "staticlib" => Ok(Emit::StaticLib),
+            "sharedlib" => Ok(Emit::SharedLib),
+            "exe" => Ok(Emit::Exe),
+            x => Err(format!("unknown emit kind {}", x)),
+        }
+    }
+}
+
 // A Config specifies the global config for a build.
 #[derive(Clone, Debug)]
 pub struct Config {
     // Path of cc, usually the result of "$ which gcc".
     pub binary_cc: String,
+    // Path of ar, used to create static libraries.
+    pub binary_ar: String,
     pub binary_wavm: String,
+    // Number of pages and maximum number of pages of imported memories, the limits of the import by default.
+    pub imported_memory_pages: Option<u64>,
+    pub imported_memory_max_pages: Option<u64>,
     // Platform flag and their files.
     pub platform: Platform,
     pub platform_ckb_vm_assemblyscript_h: &'static str,
@@ -30,13 +114,22 @@ pub struct Config {
     pub platform_posix_x86_64_wasi_runtime_s: &'static str,
     pub platform_common_wavm_h: &'static str,
     pub platform_common_wasi_h: &'static str,
+    pub platform_common_posix_h: &'static str,
+    pub platform_common_trap_h: &'static str,
+    pub platform_common_trap_code_h: &'static str,
+    pub platform_common_table_h: &'static str,
+    pub platform_common_memory_h: &'static str,
+    pub platform_common_exception_h: &'static str,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             binary_cc: String::from("gcc"),
+            binary_ar: String::from("ar"),
             binary_wavm: String::from("wavm"),
+            imported_memory_pages: None,
+            imported_memory_max_pages: None,
             platform: Platform::Unknown,
             platform_ckb_vm_assemblyscript_h: include_str!("./platform/ckb_vm_assemblyscript.h"),
             platform_ckb_vm_assemblyscript_lds: include_str!("./platform/ckb_vm_assemblyscript.lds"),
@@ -52,6 +145,12 @@ impl Default for Config {
             platform_posix_x86_64_wasi_runtime_s: include_str!("./platform/posix_x86_64_wasi_runtime.S"),
             platform_common_wavm_h: include_str!("./platform/common/wavm.h"),
             platform_common_wasi_h: include_str!("./platform/common/wasi.h"),
+            platform_common_posix_h: include_str!("./platform/common/posix.h"),
+            platform_common_trap_h: include_str!("./platform/common/trap.h"),
+            platform_common_trap_code_h: include_str!("./platform/common/trap_code.h"),
+            platform_common_table_h: include_str!("./platform/common/table.h"),
+            platform_common_memory_h: include_str!("./platform/common/memory.h"),
+            platform_common_exception_h: include_str!("./platform/common/exception.h"),
         }
     }
 }
@@ -78,14 +177,26 @@ pub struct Middle {
     pub path_platform_common_code_folder: std::path::PathBuf, // xx_build/platform/common
     pub path_platform_common_wavm_h: std::path::PathBuf,      // xx_build/platform/common/wavm.h
     pub path_platform_common_wasi_h: std::path::PathBuf,      // xx_build/platform/common/wasi.h
+    pub path_platform_common_posix_h: std::path::PathBuf,     // xx_build/platform/common/posix.h
+    pub path_platform_common_trap_h: std::path::PathBuf,      // xx_build/platform/common/trap.h
+    pub path_platform_common_trap_code_h: std::path::PathBuf, // xx_build/platform/common/trap_code.h
+    pub path_platform_common_table_h: std::path::PathBuf,     // xx_build/platform/common/table.h
+    pub path_platform_common_memory_h: std::path::PathBuf,    // xx_build/platform/common/memory.h
+    pub path_platform_common_exception_h: std::path::PathBuf, // xx_build/platform/common/exception.h
     pub path_platform_header: std::path::PathBuf,             // xx_build/platform/xx.h
     pub path_platform_lds: Option<std::path::PathBuf>,        // xx_build/platform/xx.lds
     pub path_platform_s: std::path::PathBuf,                  // xx_build/platform/xx_runtime.s
     pub path_object: std::path::PathBuf,                      // xx_build/xx.o
     pub path_glue: std::path::PathBuf,                        // xx_build/xx_glue.h
+    pub path_header: std::path::PathBuf,                      // xx_build/xx.h
     pub path_c: std::path::PathBuf,                           // xx_build/xx.c
     pub path_precompiled: std::path::PathBuf,                 // xx_build/xx_precompiled.wasm
+    pub path_instrumented: std::path::PathBuf,                // xx_build/xx_instrumented.wasm
     pub path_output: std::path::PathBuf,                      // xx
+    pub path_c_object: std::path::PathBuf,                    // xx_build/xx_c.o
+    pub path_platform_s_object: std::path::PathBuf,           // xx_build/xx_runtime.o
+    pub path_static_lib: std::path::PathBuf,                  // xx_build/libxx.a
+    pub path_shared_lib: std::path::PathBuf,                  // xx_build/libxx.so
 }
 
 impl Middle {
@@ -95,10 +206,18 @@ impl Middle {
     }
 
     // Initialize the compilation environment.
-    pub fn init_file<P: AsRef<std::path::Path>>(&mut self, p: P) {
-        self.current_dir = std::env::current_dir().unwrap();
+    pub fn init_file<P: AsRef<std::path::Path>>(&mut self, p: P) -> Result<(), WascError> {
+        self.current_dir = std::env::current_dir()?;
         self.file = p.as_ref().to_path_buf();
-        self.file_stem = self.file.file_stem().unwrap().to_str().unwrap().to_string();
+        self.file_stem = match self.file.file_stem().and_then(|e| e.to_str()) {
+            Some(file_stem) => file_stem.to_string(),
+            None => {
+                return Err(WascError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid source file name {:?}", self.file),
+                )))
+            }
+        };
         self.path_prog = self.file.with_file_name(format!("{}_build", self.file_stem));
         self.path_platform_code_folder = self.path_prog.join("platform");
         self.path_platform_common_code_folder = self.path_platform_code_folder.join("common");
@@ -126,15 +245,28 @@ impl Middle {
                 self.path_platform_s = self.path_platform_code_folder.join("posix_x86_64_wasi_runtime.S");
             }
             Platform::Unknown => {
-                panic!("unreachable");
+                return Err(WascError::UnknownPlatform);
             }
         }
         self.path_object = self.path_prog.join(self.file_stem.clone() + ".o");
         self.path_glue = self.path_prog.join(self.file_stem.clone() + "_glue.h");
+        self.path_header = self.path_prog.join(self.file_stem.clone() + ".h");
         self.path_c = self.path_prog.join(self.file_stem.clone() + ".c");
         self.path_precompiled = self.path_prog.join(self.file_stem.clone() + "_precompiled.wasm");
+        self.path_instrumented = self.path_prog.join(self.file_stem.clone() + "_instrumented.wasm");
         self.path_platform_common_wavm_h = self.path_platform_common_code_folder.join("wavm.h");
         self.path_platform_common_wasi_h = self.path_platform_common_code_folder.join("wasi.h");
+        self.path_platform_common_posix_h = self.path_platform_common_code_folder.join("posix.h");
+        self.path_platform_common_trap_h = self.path_platform_common_code_folder.join("trap.h");
+        self.path_platform_common_trap_code_h = self.path_platform_common_code_folder.join("trap_code.h");
+        self.path_platform_common_table_h = self.path_platform_common_code_folder.join("table.h");
+        self.path_platform_common_memory_h = self.path_platform_common_code_folder.join("memory.h");
+        self.path_platform_common_exception_h = self.path_platform_common_code_folder.join("exception.h");
         self.path_output = self.path_prog.join(self.file_stem.clone());
+        self.path_c_object = self.path_prog.join(self.file_stem.clone() + "_c.o");
+        self.path_platform_s_object = self.path_prog.join(self.file_stem.clone() + "_runtime.o");
+        self.path_static_lib = self.path_prog.join(format!("lib{}.a", self.file_stem));
+        self.path_shared_lib = self.path_prog.join(format!("lib{}.so", self.file_stem));
+        Ok(())
     }
 }
@@ -0,0 +1,176 @@
+// WascError is the error type of every fallible operation in wasc. The library never terminates the process on its
+// own, it is up to the caller to decide how to report an error.
+#[derive(Debug)]
+pub enum WascError {
+    // An I/O error occurred while reading the source or writing intermediate files.
+    Io(std::io::Error),
+    // The platform is not set or not supported on this host.
+    UnknownPlatform,
+    // The WAVM binary could not be executed.
+    WavmNotFound(String),
+    // WAVM rejected the module. The captured stderr usually tells why.
+    WavmFailed {
+        code: Option<i32>,
+        stderr: String,
+    },
+    // The C compiler or the archiver could not be executed.
+    CcNotFound(String),
+    // The C compiler or the archiver failed to build the generated code. Both stdout and stderr are captured in output.
+    CcFailed {
+        code: Option<i32>,
+        output: String,
+    },
+    // The linker could not resolve some symbols, usually imports which neither the platform nor the host provides. The
+    // output of the C compiler is captured in output.
+    LinkFailed {
+        code: Option<i32>,
+        output: String,
+    },
+    // The precompiled module could not be decoded.
+    Malformed {
+        reason: String,
+        location: Option<Location>,
+    },
+    // The module uses a wasm feature that wasc does not support yet.
+    Unsupported {
+        feature: String,
+        location: Option<Location>,
+    },
+    // The module is valid, but instantiating it is known to fail, for example a data segment does not fit its memory.
+    Uninstantiable {
+        reason: String,
+        location: Option<Location>,
+    },
+    // An invariant of wasc itself does not hold. This is always a bug in wasc.
+    Internal(String),
+}
+
+// Location points at the entry of the wasm module which caused an error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    // Name of the section, for example "global" or "element".
+    pub section: &'static str,
+    // Index of the entry within its section. Imported entries are counted in the import section.
+    pub index: Option<u32>,
+    // Byte offset of the entry in the module.
+    pub offset: usize,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.index {
+            Some(index) => write!(
+                f,
+                "{} section, entry {}, offset {:#x}",
+                self.section, index, self.offset
+            ),
+            None => write!(f, "{} section, offset {:#x}", self.section, self.offset),
+        }
+    }
+}
+
+impl WascError {
+    pub fn malformed<S: Into<String>>(reason: S) -> Self {
+        WascError::Malformed {
+            reason: reason.into(),
+            location: None,
+        }
+    }
+
+    pub fn unsupported<S: Into<String>>(feature: S) -> Self {
+        WascError::Unsupported {
+            feature: feature.into(),
+            location: None,
+        }
+    }
+
+    pub fn uninstantiable<S: Into<String>>(reason: S) -> Self {
+        WascError::Uninstantiable {
+            reason: reason.into(),
+            location: None,
+        }
+    }
+
+    // Attach a location to the error. The innermost location wins, since it is the most precise one.
+    pub fn at(mut self, at: &Location) -> Self {
+        match &mut self {
+            WascError::Malformed { location, .. }
+            | WascError::Unsupported { location, .. }
+            | WascError::Uninstantiable { location, .. }
+                if location.is_none() =>
+            {
+                *location = Some(at.clone());
+            }
+            _ => {}
+        }
+        self
+    }
+
+    // Where in the wasm module the error was found, if the error is caused by the module.
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            WascError::Malformed { location, .. }
+            | WascError::Unsupported { location, .. }
+            | WascError::Uninstantiable { location, .. } => location.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for WascError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WascError::Io(err) => write!(f, "{}", err),
+            WascError::UnknownPlatform => write!(f, "unknown platform"),
+            WascError::WavmNotFound(binary) => write!(f, "failed to execute wavm binary {}", binary),
+            WascError::WavmFailed { code, stderr } => {
+                write!(f, "wavm exited with {}", emit_exit_code(*code))?;
+                if !stderr.trim().is_empty() {
+                    write!(f, "\n{}", stderr.trim_end())?;
+                }
+                Ok(())
+            }
+            WascError::CcNotFound(binary) => write!(f, "failed to execute {}", binary),
+            WascError::CcFailed { code, output } => {
+                write!(f, "c toolchain exited with {}", emit_exit_code(*code))?;
+                if !output.trim().is_empty() {
+                    write!(f, "\n{}", output.trim_end())?;
+                }
+                Ok(())
+            }
+            WascError::LinkFailed { code, output } => {
+                write!(f, "linker exited with {}", emit_exit_code(*code))?;
+                if !output.trim().is_empty() {
+                    write!(f, "\n{}", output.trim_end())?;
+                }
+                Ok(())
+            }
+            WascError::Malformed { reason, .. } => write!(f, "malformed module: {}", reason),
+            WascError::Unsupported { feature, .. } => write!(f, "unsupported wasm feature: {}", feature),
+            WascError::Uninstantiable { reason, .. } => write!(f, "module is uninstantiable: {}", reason),
+            WascError::Internal(reason) => write!(f, "internal error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for WascError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WascError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WascError {
+    fn from(err: std::io::Error) -> Self {
+        WascError::Io(err)
+    }
+}
+
+fn emit_exit_code(code: Option<i32>) -> String {
+    match code {
+        Some(code) => format!("code {}", code),
+        None => String::from("signal"),
+    }
+}
@@ -1,39 +1,115 @@
 use super::context;
+use super::error::WascError;
 
-pub fn build(middle: &context::Middle) -> Result<(), Box<dyn std::error::Error>> {
+// Link the WAVM object, the C code and the platform runtime into an executable.
+pub fn build(middle: &context::Middle) -> Result<(), WascError> {
     let mut cmd = std::process::Command::new(&middle.config.binary_cc);
     cmd.arg("-g")
         .arg("-w") // Disable all gcc warnings.
         .arg("-o")
-        .arg(middle.path_output.to_str().unwrap())
-        .arg(middle.path_object.to_str().unwrap())
-        .arg(middle.path_c.to_str().unwrap());
+        .arg(&middle.path_output)
+        .arg(&middle.path_object)
+        .arg(&middle.path_c)
+        .arg(&middle.path_platform_s);
     match middle.config.platform {
-        context::Platform::CKBVMAssemblyScript => {
-            cmd.arg(middle.path_prog.join("platform/ckb_vm_assemblyscript_runtime.S"));
+        context::Platform::CKBVMAssemblyScript | context::Platform::CKBVMSpectest => {
             cmd.arg("-Wl,-T");
-            cmd.arg(middle.path_prog.join("platform/ckb_vm_assemblyscript.lds"));
+            cmd.arg(middle.path_platform_lds.as_ref().unwrap());
         }
-        context::Platform::CKBVMSpectest => {
-            cmd.arg(middle.path_prog.join("platform/ckb_vm_spectest_runtime.S"));
-            cmd.arg("-Wl,-T");
-            cmd.arg(middle.path_prog.join("platform/ckb_vm_spectest.lds"));
-        }
-        context::Platform::PosixX8664 => {
-            cmd.arg(middle.path_prog.join("platform/posix_x86_64_runtime.S"));
-        }
-        context::Platform::PosixX8664Spectest => {
-            cmd.arg(middle.path_prog.join("platform/posix_x86_64_spectest_runtime.S"));
+        // The platform runtime initializes shared state with pthread_once, and wasi::thread-spawn runs threads with
+        // pthreads.
+        context::Platform::PosixX8664 | context::Platform::PosixX8664Spectest | context::Platform::PosixX8664Wasi => {
+            cmd.arg("-pthread");
         }
-        context::Platform::PosixX8664Wasi => {
-            cmd.arg(middle.path_prog.join("platform/posix_x86_64_wasi_runtime.S"));
-        }
-        _ => panic!("unreachable"),
+        context::Platform::Unknown => return Err(WascError::UnknownPlatform),
+    }
+    run(cmd, &middle.config.binary_cc)
+}
+
+// Archive the WAVM object, the C code and the platform runtime into a static library. The main function of the glue
+// is left out, the library is meant to be linked into a host program.
+pub fn build_static_lib(middle: &context::Middle) -> Result<(), WascError> {
+    if let context::Platform::Unknown = middle.config.platform {
+        return Err(WascError::UnknownPlatform);
+    }
+    for (src, dst) in [
+        (&middle.path_c, &middle.path_c_object),
+        (&middle.path_platform_s, &middle.path_platform_s_object),
+    ] {
+        let mut cmd = std::process::Command::new(&middle.config.binary_cc);
+        cmd.arg("-g")
+            .arg("-w")
+            .arg("-DWASC_NO_MAIN")
+            .arg("-c")
+            .arg("-o")
+            .arg(dst)
+            .arg(src);
+        run(cmd, &middle.config.binary_cc)?;
+    }
+    if middle.path_static_lib.exists() {
+        std::fs::remove_file(&middle.path_static_lib)?;
+    }
+    let mut cmd = std::process::Command::new(&middle.config.binary_ar);
+    cmd.arg("rcs")
+        .arg(&middle.path_static_lib)
+        .arg(&middle.path_object)
+        .arg(&middle.path_c_object)
+        .arg(&middle.path_platform_s_object);
+    run(cmd, &middle.config.binary_ar)
+}
+
+// Link the WAVM object, the C code and the platform runtime into a shared library. Like the static library, the main
+// function of the glue is left out.
+pub fn build_shared_lib(middle: &context::Middle) -> Result<(), WascError> {
+    if let context::Platform::Unknown = middle.config.platform {
+        return Err(WascError::UnknownPlatform);
+    }
+    let mut cmd = std::process::Command::new(&middle.config.binary_cc);
+    cmd.arg("-g")
+        .arg("-w")
+        .arg("-DWASC_NO_MAIN")
+        .arg("-shared")
+        .arg("-fPIC")
+        .arg("-o")
+        .arg(&middle.path_shared_lib)
+        .arg(&middle.path_object)
+        .arg(&middle.path_c)
+        .arg(&middle.path_platform_s);
+    if let context::Platform::PosixX8664 | context::Platform::PosixX8664Spectest | context::Platform::PosixX8664Wasi =
+        middle.config.platform
+    {
+        cmd.arg("-pthread");
     }
+    run(cmd, &middle.config.binary_cc)
+}
+
+// Run the c compiler and capture everything it prints, so that the caller can decide how to report a failure.
+fn run(mut cmd: std::process::Command, binary: &str) -> Result<(), WascError> {
     rog::debugln!("$ {:?}", cmd);
-    let exit_code = cmd.spawn()?.wait()?;
-    if exit_code.code().unwrap() != 0 {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Interrupted, "")));
+    let output = match cmd.output() {
+        Ok(output) => output,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(WascError::CcNotFound(binary.to_string()));
+        }
+        Err(err) => return Err(WascError::Io(err)),
+    };
+    if !output.status.success() {
+        let mut captured = String::from_utf8_lossy(&output.stdout).to_string();
+        captured += &String::from_utf8_lossy(&output.stderr);
+        // GNU ld, lld and the linker of macOS tell about missing symbols in their own words.
+        if ["undefined reference", "undefined symbol", "Undefined symbols"]
+            .iter()
+            .any(|e| captured.contains(e))
+        {
+            return Err(WascError::LinkFailed {
+                code: output.status.code(),
+                output: captured,
+            });
+        }
+        return Err(WascError::CcFailed {
+            code: output.status.code(),
+            output: captured,
+        });
     }
     Ok(())
 }
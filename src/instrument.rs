@@ -0,0 +1,362 @@
+use super::error::{Location, WascError};
+
+// WAVM compiled code does not check the index of call_indirect: a large index reads past the end of the table, the
+// static array on ckb-vm or the region reserved on posix, and takes whatever it finds there as a function. Before the
+// module is compiled by WAVM, the index of every call_indirect and return_call_indirect is clamped to the length of the
+// table. The platform keeps the slot right past the end of a table null, so the call goes through a null element and
+// traps in callIndirectFail, like a call through an empty slot. Two i32 locals are appended to the functions which need
+// them:
+//
+//   local.set $index
+//   local.get $index
+//   table.size $table
+//   local.tee $length
+//   local.get $index
+//   local.get $length
+//   i32.lt_u
+//   select
+//   call_indirect $type $table
+pub fn clamp_call_indirect(wasm: &[u8]) -> Result<Vec<u8>, WascError> {
+    if wasm.len() < 8 || &wasm[0..4] != b"\0asm" {
+        return Err(WascError::malformed("magic header not detected"));
+    }
+    let mut output = wasm[0..8].to_vec();
+    let mut reader = Reader::new(wasm, 8, "module");
+    // The number of parameters of every type, and the type of every function defined by the module.
+    let mut type_list: Vec<u64> = vec![];
+    let mut function_list: Vec<u32> = vec![];
+    while !reader.eof() {
+        let id = reader.read_u8()?;
+        let size = reader.read_u32()? as usize;
+        let offset = reader.position;
+        let payload = reader.read_bytes(size)?;
+        let payload = match id {
+            1 => {
+                type_list = read_type_section(&mut Reader::new(wasm, offset, "type"), offset + size)?;
+                payload.to_vec()
+            }
+            3 => {
+                function_list = read_function_section(&mut Reader::new(wasm, offset, "function"))?;
+                payload.to_vec()
+            }
+            10 => rewrite_code_section(wasm, offset, offset + size, &type_list, &function_list)?,
+            _ => payload.to_vec(),
+        };
+        output.push(id);
+        write_var_u64(&mut output, payload.len() as u64);
+        output.extend_from_slice(&payload);
+    }
+    Ok(output)
+}
+
+// A reader of LEB128 integers and raw bytes, which knows its position in the module for error locations.
+struct Reader<'a> {
+    wasm: &'a [u8],
+    position: usize,
+    section: &'static str,
+    index: Option<u32>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(wasm: &'a [u8], position: usize, section: &'static str) -> Self {
+        Reader {
+            wasm,
+            position,
+            section,
+            index: None,
+        }
+    }
+
+    fn eof(&self) -> bool {
+        self.position >= self.wasm.len()
+    }
+
+    fn location(&self) -> Location {
+        Location {
+            section: self.section,
+            index: self.index,
+            offset: self.position,
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8, WascError> {
+        match self.wasm.get(self.position) {
+            Some(e) => {
+                self.position += 1;
+                Ok(*e)
+            }
+            None => Err(WascError::malformed("unexpected end").at(&self.location())),
+        }
+    }
+
+    fn read_bytes(&mut self, size: usize) -> Result<&'a [u8], WascError> {
+        if self.wasm.len() - self.position < size {
+            return Err(WascError::malformed("unexpected end").at(&self.location()));
+        }
+        self.position += size;
+        Ok(&self.wasm[self.position - size..self.position])
+    }
+
+    // Signed and unsigned integers are skipped the same way, only the unsigned ones are decoded.
+    fn read_var_u64(&mut self) -> Result<u64, WascError> {
+        let mut value = 0u64;
+        for shift in (0..70).step_by(7) {
+            let byte = self.read_u8()?;
+            if shift < 64 {
+                value |= u64::from(byte & 0x7f) << shift;
+            }
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(WascError::malformed("integer representation too long").at(&self.location()))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, WascError> {
+        let value = self.read_var_u64()?;
+        if value > u64::from(u32::MAX) {
+            return Err(WascError::malformed("integer too large").at(&self.location()));
+        }
+        Ok(value as u32)
+    }
+
+    fn read_value_type(&mut self) -> Result<u8, WascError> {
+        match self.read_u8()? {
+            e @ (0x7f | 0x7e | 0x7d | 0x7c | 0x7b | 0x70 | 0x6f) => Ok(e),
+            e => Err(WascError::unsupported(format!("value type 0x{:02x}", e)).at(&self.location())),
+        }
+    }
+
+    // The alignment of a memory argument tells whether a memory index follows. The offset is 64 bits on memory64.
+    fn read_memarg(&mut self) -> Result<(), WascError> {
+        let align = self.read_u32()?;
+        if align & 0x40 != 0 {
+            self.read_u32()?;
+        }
+        self.read_var_u64()?;
+        Ok(())
+    }
+}
+
+fn write_var_u64(output: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn read_type_section(reader: &mut Reader, end: usize) -> Result<Vec<u64>, WascError> {
+    let count = reader.read_u32()?;
+    let mut type_list = vec![];
+    for i in 0..count {
+        reader.index = Some(i);
+        let form = reader.read_u8()?;
+        if form != 0x60 {
+            return Err(WascError::unsupported(format!("type of form 0x{:02x}", form)).at(&reader.location()));
+        }
+        let params = reader.read_u32()?;
+        for _ in 0..params {
+            reader.read_value_type()?;
+        }
+        let results = reader.read_u32()?;
+        for _ in 0..results {
+            reader.read_value_type()?;
+        }
+        type_list.push(u64::from(params));
+    }
+    if reader.position != end {
+        return Err(WascError::malformed("section size mismatch").at(&reader.location()));
+    }
+    Ok(type_list)
+}
+
+fn read_function_section(reader: &mut Reader) -> Result<Vec<u32>, WascError> {
+    let count = reader.read_u32()?;
+    let mut function_list = vec![];
+    for _ in 0..count {
+        function_list.push(reader.read_u32()?);
+    }
+    Ok(function_list)
+}
+
+fn rewrite_code_section(
+    wasm: &[u8],
+    offset: usize,
+    end: usize,
+    type_list: &[u64],
+    function_list: &[u32],
+) -> Result<Vec<u8>, WascError> {
+    let mut reader = Reader::new(&wasm[..end], offset, "code");
+    let count = reader.read_u32()?;
+    let mut output = vec![];
+    write_var_u64(&mut output, u64::from(count));
+    for i in 0..count {
+        reader.index = Some(i);
+        let params = function_list
+            .get(i as usize)
+            .and_then(|e| type_list.get(*e as usize))
+            .ok_or_else(|| WascError::malformed("function and code section have inconsistent lengths"))
+            .map_err(|err| err.at(&reader.location()))?;
+        let size = reader.read_u32()? as usize;
+        let body_offset = reader.position;
+        reader.read_bytes(size)?;
+        let mut body_reader = Reader::new(&wasm[..body_offset + size], body_offset, "code");
+        body_reader.index = Some(i);
+        let body = rewrite_function_body(&mut body_reader, *params)?;
+        write_var_u64(&mut output, body.len() as u64);
+        output.extend_from_slice(&body);
+    }
+    if !reader.eof() {
+        return Err(WascError::malformed("section size mismatch").at(&reader.location()));
+    }
+    Ok(output)
+}
+
+fn rewrite_function_body(reader: &mut Reader, params: u64) -> Result<Vec<u8>, WascError> {
+    let body_offset = reader.position;
+    let group_count = reader.read_u32()?;
+    let mut local_count = params;
+    for _ in 0..group_count {
+        local_count += u64::from(reader.read_u32()?);
+        reader.read_value_type()?;
+    }
+    let code_offset = reader.position;
+    // The offset of every call_indirect and return_call_indirect, with its table.
+    let mut site_list: Vec<(usize, u32)> = vec![];
+    while !reader.eof() {
+        let position = reader.position;
+        if let Some(table_index) = read_instruction(reader)? {
+            site_list.push((position, table_index));
+        }
+    }
+    let body = &reader.wasm[body_offset..];
+    if site_list.is_empty() {
+        return Ok(body.to_vec());
+    }
+    if local_count + 2 > u64::from(u32::MAX) {
+        return Err(WascError::unsupported("too many locals to clamp call_indirect").at(&reader.location()));
+    }
+    let index_local = local_count;
+    let length_local = local_count + 1;
+
+    let mut output = vec![];
+    write_var_u64(&mut output, u64::from(group_count) + 1);
+    output.extend_from_slice(&reader.wasm[body_offset..code_offset][leb_length(body)..]);
+    output.extend_from_slice(&[0x02, 0x7f]);
+    let mut copied = code_offset;
+    for (position, table_index) in site_list {
+        output.extend_from_slice(&reader.wasm[copied..position]);
+        copied = position;
+        output.push(0x21);
+        write_var_u64(&mut output, index_local);
+        output.push(0x20);
+        write_var_u64(&mut output, index_local);
+        output.extend_from_slice(&[0xfc, 0x10]);
+        write_var_u64(&mut output, u64::from(table_index));
+        output.push(0x22);
+        write_var_u64(&mut output, length_local);
+        output.push(0x20);
+        write_var_u64(&mut output, index_local);
+        output.push(0x20);
+        write_var_u64(&mut output, length_local);
+        output.extend_from_slice(&[0x49, 0x1b]);
+    }
+    output.extend_from_slice(&reader.wasm[copied..]);
+    Ok(output)
+}
+
+// The length of the LEB128 integer at the start of data.
+fn leb_length(data: &[u8]) -> usize {
+    data.iter().position(|e| e & 0x80 == 0).map_or(data.len(), |e| e + 1)
+}
+
+// Skip an instruction. Return the table of a call_indirect or return_call_indirect.
+fn read_instruction(reader: &mut Reader) -> Result<Option<u32>, WascError> {
+    let opcode = reader.read_u8()?;
+    match opcode {
+        0x00 | 0x01 | 0x05 | 0x0b | 0x0f | 0x19 | 0x1a | 0x1b | 0x45..=0xc4 | 0xd1 => {}
+        // Block types are either a value type, 0x40 or a type index, all of them a single signed LEB128 integer.
+        0x02 | 0x03 | 0x04 | 0x06 => {
+            reader.read_var_u64()?;
+        }
+        0x07 | 0x08 | 0x09 | 0x0c | 0x0d | 0x10 | 0x12 | 0x18 | 0x20..=0x26 | 0x3f | 0x40 | 0xd2 => {
+            reader.read_u32()?;
+        }
+        0x0e => {
+            let count = reader.read_u32()?;
+            for _ in 0..=count {
+                reader.read_u32()?;
+            }
+        }
+        0x11 | 0x13 => {
+            reader.read_u32()?;
+            return Ok(Some(reader.read_u32()?));
+        }
+        0x1c => {
+            let count = reader.read_u32()?;
+            for _ in 0..count {
+                reader.read_value_type()?;
+            }
+        }
+        0x28..=0x3e => reader.read_memarg()?,
+        0x41 | 0x42 => {
+            reader.read_var_u64()?;
+        }
+        0x43 => {
+            reader.read_bytes(4)?;
+        }
+        0x44 => {
+            reader.read_bytes(8)?;
+        }
+        0xd0 => {
+            reader.read_value_type()?;
+        }
+        0xfc => match reader.read_u32()? {
+            0..=7 => {}
+            9 | 11 | 13 | 15 | 16 | 17 => {
+                reader.read_u32()?;
+            }
+            8 | 10 | 12 | 14 => {
+                reader.read_u32()?;
+                reader.read_u32()?;
+            }
+            e => return Err(unsupported_opcode(reader, opcode, e)),
+        },
+        0xfd => match reader.read_u32()? {
+            0..=11 | 92 | 93 => reader.read_memarg()?,
+            12 | 13 => {
+                reader.read_bytes(16)?;
+            }
+            21..=34 => {
+                reader.read_u8()?;
+            }
+            84..=91 => {
+                reader.read_memarg()?;
+                reader.read_u8()?;
+            }
+            // The other SIMD instructions have no immediate.
+            _ => {}
+        },
+        0xfe => match reader.read_u32()? {
+            0x03 => {
+                reader.read_u8()?;
+            }
+            0x00..=0x02 | 0x10..=0x4e => reader.read_memarg()?,
+            e => return Err(unsupported_opcode(reader, opcode, e)),
+        },
+        _ => {
+            let err = WascError::unsupported(format!("opcode 0x{:02x}", opcode));
+            return Err(err.at(&reader.location()));
+        }
+    }
+    Ok(None)
+}
+
+fn unsupported_opcode(reader: &Reader, prefix: u8, opcode: u32) -> WascError {
+    WascError::unsupported(format!("opcode 0x{:02x} 0x{:02x}", prefix, opcode)).at(&reader.location())
+}
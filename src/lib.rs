@@ -2,4 +2,10 @@ pub mod aot_generator;
 pub mod code_builder;
 pub mod compile;
 pub mod context;
+pub mod error;
 pub mod gcc;
+pub mod instrument;
+pub mod spectest;
+
+pub use compile::Compiler;
+pub use error::{Location, WascError};
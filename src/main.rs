@@ -1,12 +1,14 @@
-use wasc::compile;
 use wasc::context;
-use wasc::gcc;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() {
     // Usage of wasc:
     //
     // wasc
+    //     --ar [AR binary]
+    //     --emit [EMIT,...]
     //     --gcc [GCC binary]
+    //     --imported-memory-pages [PAGES]
+    //     --imported-memory-max-pages [PAGES]
     //     -p --platform [PLATFORM]
     //     -s --save
     //     -v --verbose
@@ -19,17 +21,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     //   posix_x86_64
     //   posix_x86_64_spectest
     //   posix_x86_64_wasi
+    //
+    // EMIT:
+    //   glue       xx_build/xx_glue.h and the platform headers
+    //   c          xx_build/xx.c, xx_build/xx_glue.h and the platform headers
+    //   obj        xx.o precompiled by WAVM
+    //   staticlib  libxx.a
+    //   sharedlib  libxx.so
+    //   exe        xx, the default
+    //
+    // Run the spec tests converted by wast2json, see spectest_main:
+    //
+    // wasc spectest [OPTIONS] path
+    if std::env::args().nth(1).as_deref() == Some("spectest") {
+        spectest_main();
+        return;
+    }
     let mut fl_source = String::from("");
     let mut fl_platform = String::from("");
-    let mut fl_wavm = String::from(
-        std::env::current_exe()?
-            .parent()
-            .unwrap()
-            .join("wavm")
-            .to_str()
-            .unwrap(),
-    );
+    let mut fl_wavm = std::env::current_exe()
+        .ok()
+        .and_then(|e| e.parent().map(|p| p.join("wavm")))
+        .and_then(|e| e.to_str().map(String::from))
+        .unwrap_or_else(|| String::from("wavm"));
     let mut fl_gcc = String::from("");
+    let mut fl_ar = String::from("");
+    let mut fl_emit = String::from("");
+    let mut fl_imported_memory_pages: Option<u64> = None;
+    let mut fl_imported_memory_max_pages: Option<u64> = None;
     let mut fl_verbose = false;
     let mut fl_save = false;
     {
@@ -45,6 +64,22 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         ap.refer(&mut fl_wavm)
             .add_option(&["--wavm"], argparse::Store, "WAVM binary");
         ap.refer(&mut fl_gcc).add_option(&["--gcc"], argparse::Store, "GCC");
+        ap.refer(&mut fl_ar).add_option(&["--ar"], argparse::Store, "AR");
+        ap.refer(&mut fl_emit).add_option(
+            &["--emit"],
+            argparse::Store,
+            "comma separated list of glue c obj staticlib sharedlib exe",
+        );
+        ap.refer(&mut fl_imported_memory_pages).add_option(
+            &["--imported-memory-pages"],
+            argparse::StoreOption,
+            "pages of imported memories",
+        );
+        ap.refer(&mut fl_imported_memory_max_pages).add_option(
+            &["--imported-memory-max-pages"],
+            argparse::StoreOption,
+            "maximum pages of imported memories",
+        );
         ap.refer(&mut fl_verbose)
             .add_option(&["-v", "--verbose"], argparse::StoreTrue, "");
         ap.refer(&mut fl_save)
@@ -63,53 +98,181 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         rog::reg("wasc::gcc");
     }
 
-    let mut config = context::Config::default();
-    config.platform = match fl_platform.as_str() {
-        "ckb_vm_assemblyscript" => context::Platform::CKBVMAssemblyScript,
-        "ckb_vm_spectest" => context::Platform::CKBVMSpectest,
-        "posix_x86_64" => context::Platform::PosixX8664,
-        "posix_x86_64_spectest" => context::Platform::PosixX8664Spectest,
-        "posix_x86_64_wasi" => context::Platform::PosixX8664Wasi,
-        "" => {
-            if cfg!(unix) {
-                context::Platform::PosixX8664Wasi
-            } else {
-                context::Platform::Unknown
+    let platform = if fl_platform.is_empty() {
+        context::Platform::default()
+    } else {
+        match fl_platform.parse::<context::Platform>() {
+            Ok(platform) => platform,
+            Err(err) => {
+                rog::println!("wasc: {}", err);
+                std::process::exit(1);
+            }
+        }
+    };
+    let mut compiler = wasc::Compiler::new(platform).wavm(fl_wavm).save_temps(fl_save);
+    if !fl_gcc.is_empty() {
+        compiler = compiler.cc(fl_gcc);
+    }
+    if !fl_ar.is_empty() {
+        compiler = compiler.ar(fl_ar);
+    }
+    if let Some(pages) = fl_imported_memory_pages {
+        compiler = compiler.imported_memory_pages(pages);
+    }
+    if let Some(pages) = fl_imported_memory_max_pages {
+        compiler = compiler.imported_memory_max_pages(pages);
+    }
+    for e in fl_emit.split(',').filter(|e| !e.is_empty()) {
+        match e.parse::<context::Emit>() {
+            Ok(emit) => compiler = compiler.emit(emit),
+            Err(err) => {
+                rog::println!("wasc: {}", err);
+                std::process::exit(1);
             }
         }
-        x => {
-            rog::println!("wasc: unknown platform {}", x);
+    }
+    if let Err(err) = compiler.compile(&fl_source) {
+        report(&fl_source, &err);
+        std::process::exit(1);
+    }
+}
+
+// Usage of wasc spectest:
+//
+// wasc spectest
+//     --gcc [GCC binary]
+//     -p --platform [PLATFORM], posix_x86_64_spectest by default
+//     --runner [COMMAND], runs the binaries built for ckb-vm
+//     --skip [FILE,...], modules to skip, for example elem.39.wasm
+//     -v --verbose
+//     --wavm [WAVM binary]
+//     --work-dir [DIR], spectest_build by default
+//     path [JSON script, or a folder of them]
+fn spectest_main() {
+    let mut fl_path = String::from("");
+    let mut fl_platform = String::from("posix_x86_64_spectest");
+    let mut fl_wavm = std::env::current_exe()
+        .ok()
+        .and_then(|e| e.parent().map(|p| p.join("wavm")))
+        .and_then(|e| e.to_str().map(String::from))
+        .unwrap_or_else(|| String::from("wavm"));
+    let mut fl_gcc = String::from("");
+    let mut fl_runner = String::from("");
+    let mut fl_skip = String::from("");
+    let mut fl_work_dir = String::from("spectest_build");
+    let mut fl_verbose = false;
+    {
+        let mut ap = argparse::ArgumentParser::new();
+        ap.set_description("Run the WebAssembly spec tests converted by wast2json");
+        ap.refer(&mut fl_path)
+            .add_argument("path", argparse::Store, "JSON script, or a folder of them");
+        ap.refer(&mut fl_platform).add_option(
+            &["-p", "--platform"],
+            argparse::Store,
+            "posix_x86_64_spectest ckb_vm_spectest",
+        );
+        ap.refer(&mut fl_wavm)
+            .add_option(&["--wavm"], argparse::Store, "WAVM binary");
+        ap.refer(&mut fl_gcc).add_option(&["--gcc"], argparse::Store, "GCC");
+        ap.refer(&mut fl_runner).add_option(
+            &["--runner"],
+            argparse::Store,
+            "command which runs the binaries, for example ckb-vm-run",
+        );
+        ap.refer(&mut fl_skip).add_option(
+            &["--skip"],
+            argparse::Store,
+            "comma separated list of module file names to skip",
+        );
+        ap.refer(&mut fl_work_dir)
+            .add_option(&["--work-dir"], argparse::Store, "build folder");
+        ap.refer(&mut fl_verbose)
+            .add_option(&["-v", "--verbose"], argparse::StoreTrue, "");
+        let args: Vec<String> = std::env::args()
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(_, e)| e)
+            .collect();
+        if let Err(code) = ap.parse(args, &mut std::io::stdout(), &mut std::io::stderr()) {
+            std::process::exit(code);
+        }
+    }
+    if fl_path.is_empty() {
+        rog::println!("wasc: missing path operand");
+        std::process::exit(1);
+    }
+    if fl_verbose {
+        rog::reg("wasc");
+        rog::reg("wasc::compile");
+        rog::reg("wasc::gcc");
+        rog::reg("wasc::spectest");
+    }
+    let platform = match fl_platform.parse::<context::Platform>() {
+        Ok(platform) => platform,
+        Err(err) => {
+            rog::println!("wasc: {}", err);
             std::process::exit(1);
         }
     };
-    if fl_gcc.is_empty() {
-        match config.platform {
-            context::Platform::CKBVMAssemblyScript | context::Platform::CKBVMSpectest => {
-                fl_gcc = String::from("riscv64-unknown-elf-gcc");
-            }
-            context::Platform::PosixX8664
-            | context::Platform::PosixX8664Spectest
-            | context::Platform::PosixX8664Wasi => {
-                fl_gcc = String::from("gcc");
+    let mut runner = wasc::spectest::Runner::new(platform)
+        .wavm(fl_wavm)
+        .work_dir(fl_work_dir)
+        .runner(fl_runner.split_whitespace().map(String::from).collect());
+    if !fl_gcc.is_empty() {
+        runner = runner.cc(fl_gcc);
+    }
+    for e in fl_skip.split(',').filter(|e| !e.is_empty()) {
+        runner = runner.skip(e);
+    }
+    let script_list = match wasc::spectest::Runner::find_scripts(&fl_path) {
+        Ok(script_list) => script_list,
+        Err(err) => {
+            rog::println!("wasc: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let mut report = wasc::spectest::Report::default();
+    for script in script_list {
+        let result = match runner.run_script(&script) {
+            Ok(result) => result,
+            Err(err) => {
+                rog::println!("wasc: {}: {}", script.display(), err);
+                std::process::exit(1);
             }
-            _ => {}
+        };
+        for e in &result.results {
+            rog::println!("{}:{} {} ... {}", e.script.display(), e.line, e.kind, e.outcome);
         }
+        report.results.extend(result.results);
     }
-    config.binary_wavm = fl_wavm;
-    config.binary_cc = fl_gcc;
-
-    let middle = compile::compile(&fl_source, config)?;
-
-    gcc::build(&middle)?;
-
-    std::fs::copy(
-        middle.path_output.clone(),
-        middle.file.parent().unwrap().join(middle.file_stem.clone()),
-    )?;
+    rog::println!(
+        "spectest result: {} passed; {} failed; {} skipped",
+        report.passed(),
+        report.failed(),
+        report.skipped()
+    );
+    if report.failed() != 0 {
+        std::process::exit(1);
+    }
+}
 
-    if !fl_save {
-        rog::debugln!("remove {}", middle.path_prog.to_str().unwrap());
-        std::fs::remove_dir_all(middle.path_prog)?;
+// Print the error like rustc does: the message first, then where it happened and what it means for the user.
+fn report(source: &str, err: &wasc::WascError) {
+    rog::println!("error: {}", err);
+    if let Some(location) = err.location() {
+        rog::println!("  --> {}: {}", source, location);
+    }
+    let note = match err {
+        wasc::WascError::Unsupported { .. } => "the module is valid, but wasc does not support this feature yet",
+        wasc::WascError::Uninstantiable { .. } => "the module is valid, but instantiating it always traps",
+        wasc::WascError::Malformed { .. } | wasc::WascError::Internal(_) => {
+            "the module has been validated by wavm, this is a bug in wasc, please report it"
+        }
+        _ => return,
+    };
+    rog::println!("   |");
+    rog::println!("   = note: {}", note);
+    if err.location().is_some() {
+        rog::println!("   = note: offsets are relative to the module precompiled by wavm, kept in the build folder");
     }
-    Ok(())
 }
@@ -4,55 +4,65 @@
 #include <string.h>
 
 #include "common/wavm.h"
+#define WASC_TRAP_CKB
+#include "common/trap.h"
+#include "common/table.h"
+#include "common/memory.h"
 
 #ifndef WAVM_CKB_VM_ASSEMBLYSCRIPT_H
 #define WAVM_CKB_VM_ASSEMBLYSCRIPT_H
 
 #define WAVM_PAGE_SIZE 0x10000
-#ifndef MEMORY0_MAX_PAGE
-#define MEMORY0_MAX_PAGE 65536
-#endif /* MEMORY0_MAX_PAGE */
-
-#ifdef MEMORY0_DEFINED
-extern memory_instance memoryOffset0;
-extern uint8_t memory0[];
-extern uint32_t memory0_length;
-int32_t wavm_intrinsic_memory_grow(void *dummy, int32_t grow_by)
+// Memories are static arrays at the end of the program, the last one grows into the free memory after it.
+int64_t wasc_memory_grow(memory_instance *memory, uint64_t delta, uint64_t max_pages)
 {
-    if ((memoryOffset0.num_pages + grow_by) > MEMORY0_MAX_PAGE)
+    uint64_t old_pages = memory->num_pages;
+    if (delta > max_pages || old_pages + delta > max_pages)
     {
         return -1;
     }
-    int32_t old_pages = memoryOffset0.num_pages;
-    memory0_length += grow_by * WAVM_PAGE_SIZE;
-    memoryOffset0.num_pages += grow_by;
+    memory->num_pages += delta;
     return old_pages;
 }
-#else
-int32_t wavm_intrinsic_memory_grow(void *dummy, int32_t grow_by)
-{
-    return -1;
-}
-#endif
 
-void callIndirectFail()
+// An imported memory is the one the host defines, or else the static array of the glue code, which starts with num_pages
+// pages. Its pages past the array are available like grown ones.
+void wasc_memory_import(memory_instance *memory, memory_instance *source, uint64_t num_pages)
 {
-    exit(1);
+    if (source->num_pages == 0)
+    {
+        source->num_pages = num_pages;
+    }
+    memory->base = source->base;
+    memory->num_pages = source->num_pages;
 }
 
-void unreachableTrap()
+// Tables are static arrays, which the glue code makes as long as the maximum of the table.
+int32_t wasc_table_grow(table_instance *table, uint32_t delta, uintptr_t entry)
 {
-    exit(1);
+    uint32_t old_length = table->length;
+    if ((uint64_t)old_length + delta > table->maximum)
+    {
+        return -1;
+    }
+    for (uint32_t i = 0; i < delta; i++)
+    {
+        table->base[old_length + i] = entry;
+    }
+    table->length += delta;
+    return old_length;
 }
 
-void divideByZeroOrIntegerOverflowTrap()
+// There is a single thread on ckb-vm, nothing can notify a waiter: a wait which would block forever times out.
+int32_t wasc_atomic_wait(void *address, uint64_t expected, uint32_t size, int64_t timeout)
 {
-    exit(1);
+    uint64_t value = size == 4 ? *(uint32_t *)address : *(uint64_t *)address;
+    return value != expected ? 1 : 2;
 }
 
-void invalidFloatOperationTrap()
+int32_t wasc_atomic_notify(void *address, uint32_t count)
 {
-    exit(1);
+    return 0;
 }
 
 uint64_t __atomic_load_8(void *p, int32_t _mode)
@@ -86,38 +96,38 @@ static inline long __internal_syscall(long n, long _a0, long _a1, long _a2,
     __internal_syscall(n, (long)(a), (long)(b), (long)(c), (long)(d), (long)(e), \
                        (long)(f))
 
-#ifdef MEMORY0_DEFINED
+#ifdef WASC_MEMORY_EXPORTED
 wavm_ret_int64_t wavm_env_syscall(void *dummy, int64_t n, int64_t _a0, int64_t _a1, int64_t _a2, int64_t _a3, int64_t _a4, int64_t _a5, int64_t mode)
 {
     wavm_ret_int64_t ret;
     ret.dummy = dummy;
     if (mode & 0b100000)
     {
-        _a0 = (int64_t)&memoryOffset0.base[0] + _a0;
+        _a0 = (int64_t)wasc_memory_exported(dummy)->base + _a0;
     }
     if (mode & 0b010000)
     {
-        _a1 = (int64_t)&memoryOffset0.base[0] + _a1;
+        _a1 = (int64_t)wasc_memory_exported(dummy)->base + _a1;
     }
     if (mode & 0b001000)
     {
-        _a2 = (int64_t)&memoryOffset0.base[0] + _a2;
+        _a2 = (int64_t)wasc_memory_exported(dummy)->base + _a2;
     }
     if (mode & 0b000100)
     {
-        _a3 = (int64_t)&memoryOffset0.base[0] + _a3;
+        _a3 = (int64_t)wasc_memory_exported(dummy)->base + _a3;
     }
     if (mode & 0b000010)
     {
-        _a4 = (int64_t)&memoryOffset0.base[0] + _a4;
+        _a4 = (int64_t)wasc_memory_exported(dummy)->base + _a4;
     }
     if (mode & 0b000001)
     {
-        _a5 = (int64_t)&memoryOffset0.base[0] + _a5;
+        _a5 = (int64_t)wasc_memory_exported(dummy)->base + _a5;
     }
     ret.value = syscall(n, _a0, _a1, _a2, _a3, _a4, _a5);
     return ret;
 }
-#endif /* MEMORY0_DEFINED */
+#endif /* WASC_MEMORY_EXPORTED */
 
 #endif /* WAVM_CKB_VM_ASSEMBLYSCRIPT_H */
@@ -4,55 +4,65 @@
 #include <string.h>
 
 #include "common/wavm.h"
+#define WASC_TRAP_CKB
+#include "common/trap.h"
+#include "common/table.h"
+#include "common/memory.h"
 
 #ifndef WAVM_CKB_VM_SPECTEST_H
 #define WAVM_CKB_VM_SPECTEST_H
 
 #define WAVM_PAGE_SIZE 0x10000
-#ifndef MEMORY0_MAX_PAGE
-#define MEMORY0_MAX_PAGE 65536
-#endif /* MEMORY0_MAX_PAGE */
-
-#ifdef MEMORY0_DEFINED
-extern memory_instance memoryOffset0;
-extern uint8_t memory0[];
-extern uint32_t memory0_length;
-int32_t wavm_intrinsic_memory_grow(void *dummy, int32_t grow_by)
+// Memories are static arrays at the end of the program, the last one grows into the free memory after it.
+int64_t wasc_memory_grow(memory_instance *memory, uint64_t delta, uint64_t max_pages)
 {
-  if ((memoryOffset0.num_pages + grow_by) > MEMORY0_MAX_PAGE)
+  uint64_t old_pages = memory->num_pages;
+  if (delta > max_pages || old_pages + delta > max_pages)
   {
     return -1;
   }
-  int32_t old_pages = memoryOffset0.num_pages;
-  memory0_length += grow_by * WAVM_PAGE_SIZE;
-  memoryOffset0.num_pages += grow_by;
+  memory->num_pages += delta;
   return old_pages;
 }
-#else
-int32_t wavm_intrinsic_memory_grow(void *dummy, int32_t grow_by)
-{
-  return -1;
-}
-#endif
 
-void callIndirectFail()
+// An imported memory is the one the host defines, or else the static array of the glue code, which starts with num_pages
+// pages. Its pages past the array are available like grown ones.
+void wasc_memory_import(memory_instance *memory, memory_instance *source, uint64_t num_pages)
 {
-  exit(1);
+  if (source->num_pages == 0)
+  {
+    source->num_pages = num_pages;
+  }
+  memory->base = source->base;
+  memory->num_pages = source->num_pages;
 }
 
-void unreachableTrap()
+// Tables are static arrays, which the glue code makes as long as the maximum of the table.
+int32_t wasc_table_grow(table_instance *table, uint32_t delta, uintptr_t entry)
 {
-  exit(1);
+  uint32_t old_length = table->length;
+  if ((uint64_t)old_length + delta > table->maximum)
+  {
+    return -1;
+  }
+  for (uint32_t i = 0; i < delta; i++)
+  {
+    table->base[old_length + i] = entry;
+  }
+  table->length += delta;
+  return old_length;
 }
 
-void divideByZeroOrIntegerOverflowTrap()
+// There is a single thread on ckb-vm, nothing can notify a waiter: a wait which would block forever times out.
+int32_t wasc_atomic_wait(void *address, uint64_t expected, uint32_t size, int64_t timeout)
 {
-  exit(1);
+  uint64_t value = size == 4 ? *(uint32_t *)address : *(uint64_t *)address;
+  return value != expected ? 1 : 2;
 }
 
-void invalidFloatOperationTrap()
+int32_t wasc_atomic_notify(void *address, uint32_t count)
 {
-  exit(1);
+  return 0;
 }
 
 uint64_t __atomic_load_8(void *p, int32_t _mode)
@@ -65,23 +75,28 @@ int32_t wavm_spectest_global_i32 = 42;
 float wavm_spectest_global_f32 = 42.0;
 double wavm_spectest_global_f64 = 420;
 
+// One more slot than the length of the table, call_indirect goes through it when its index is out of bounds.
 uint32_t wavm_spectest_table_length = 10;
-uintptr_t wavm_spectest_table[10] = {};
+uintptr_t wavm_spectest_table[11] = {};
 
 void *wavm_spectest_print_i32(void *dummy, int32_t i)
 {
+  return dummy;
 }
 
 void *wavm_exported_function_print32(void *dummy, int32_t i)
 {
+  return dummy;
 }
 
 void *wavm_exported_function_print64(void *dummy, int64_t i)
 {
+  return dummy;
 }
 
 void *wavm_spectest_print(void *dummy)
 {
+  return dummy;
 }
 
 #endif /* WAVM_CKB_VM_SPECTEST_H */
@@ -0,0 +1,357 @@
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unwind.h>
+
+#include "wavm.h"
+#include "trap.h"
+
+#ifndef WASC_EXCEPTION_H
+#define WASC_EXCEPTION_H
+
+// Exceptions are thrown with the unwinder of the host, like C++ exceptions. WAVM compiled code catches them in landing
+// pads with the personality __gxx_personality_v0, whose catch clauses refer to runtimeExceptionTypeInfo, and enters and
+// leaves a catch block with __cxa_begin_catch and __cxa_end_catch. These are defined here for wasm exceptions only, so
+// a module can not be linked with C++ code which throws exceptions.
+
+// The exception class of wasm exceptions, "WASC\0EXC".
+#define WASC_EXCEPTION_CLASS 0x5741534300455843ull
+
+// The pointer encodings of the LSDA, see the Linux Standard Base.
+#define WASC_DW_EH_PE_absptr 0x00
+#define WASC_DW_EH_PE_uleb128 0x01
+#define WASC_DW_EH_PE_udata2 0x02
+#define WASC_DW_EH_PE_udata4 0x03
+#define WASC_DW_EH_PE_udata8 0x04
+#define WASC_DW_EH_PE_sleb128 0x09
+#define WASC_DW_EH_PE_sdata2 0x0a
+#define WASC_DW_EH_PE_sdata4 0x0b
+#define WASC_DW_EH_PE_sdata8 0x0c
+#define WASC_DW_EH_PE_pcrel 0x10
+#define WASC_DW_EH_PE_funcrel 0x40
+#define WASC_DW_EH_PE_indirect 0x80
+#define WASC_DW_EH_PE_omit 0xff
+
+// Catch clauses of WAVM compiled code name it, only its address matters.
+const uintptr_t runtimeExceptionTypeInfo = 0;
+
+// An exception in flight, or caught. Caught exceptions of a thread are stacked, the innermost catch block is on top.
+typedef struct wasc_unwind
+{
+  struct _Unwind_Exception header;
+  wasc_exception *exception;
+  struct wasc_unwind *next;
+} wasc_unwind;
+
+static __thread wasc_unwind *wasc_caught = NULL;
+
+uintptr_t wavm_intrinsic_create_exception(void *context, uintptr_t type_id, uintptr_t arguments,
+                                          uint32_t is_user_exception)
+{
+  const wasc_tag *tag = (const wasc_tag *)type_id;
+  wasc_exception *exception = malloc(sizeof(wasc_exception) + tag->num_params * sizeof(wasc_value));
+  if (exception == NULL)
+  {
+    fprintf(stderr, "wasc: out of memory for an exception\n");
+    exit(1);
+  }
+  exception->type_id = type_id;
+  exception->is_user_exception = is_user_exception;
+  exception->num_arguments = tag->num_params;
+  memcpy(exception->arguments, (const void *)arguments, tag->num_params * sizeof(wasc_value));
+  return (uintptr_t)exception;
+}
+
+void wasc_unwind_cleanup(_Unwind_Reason_Code reason, struct _Unwind_Exception *header)
+{
+  wasc_unwind *unwind = (wasc_unwind *)header;
+  free(unwind->exception);
+  free(unwind);
+}
+
+void wavm_intrinsic_throw_exception(void *context, uintptr_t exception)
+{
+  // Rethrowing the exception of a catch block leaves the block.
+  if (wasc_caught != NULL && wasc_caught->exception == (wasc_exception *)exception)
+  {
+    wasc_unwind *caught = wasc_caught;
+    wasc_caught = caught->next;
+    free(caught);
+  }
+  wasc_unwind *unwind = calloc(1, sizeof(wasc_unwind));
+  if (unwind == NULL)
+  {
+    fprintf(stderr, "wasc: out of memory for an exception\n");
+    exit(1);
+  }
+  unwind->header.exception_class = WASC_EXCEPTION_CLASS;
+  unwind->header.exception_cleanup = wasc_unwind_cleanup;
+  unwind->exception = (wasc_exception *)exception;
+  _Unwind_RaiseException(&unwind->header);
+  // It only returns when no wasm code catches the exception.
+  wasc_unwind_cleanup(_URC_NO_REASON, &unwind->header);
+  wasc_trap(WASC_TRAP_UNCAUGHT_EXCEPTION, __builtin_return_address(0));
+}
+
+// Returns the slot which holds the exception, WAVM compiled code loads the exception from it.
+void *__cxa_begin_catch(void *header)
+{
+  wasc_unwind *unwind = (wasc_unwind *)header;
+  unwind->next = wasc_caught;
+  wasc_caught = unwind;
+  return &unwind->exception;
+}
+
+void __cxa_end_catch(void)
+{
+  wasc_unwind *unwind = wasc_caught;
+  if (unwind != NULL)
+  {
+    wasc_caught = unwind->next;
+    wasc_unwind_cleanup(_URC_NO_REASON, &unwind->header);
+  }
+}
+
+static uintptr_t wasc_read_uleb128(const uint8_t **p)
+{
+  uintptr_t result = 0;
+  uint32_t shift = 0;
+  uint8_t byte;
+  do
+  {
+    byte = *(*p)++;
+    result |= (uintptr_t)(byte & 0x7f) << shift;
+    shift += 7;
+  } while (byte & 0x80);
+  return result;
+}
+
+static intptr_t wasc_read_sleb128(const uint8_t **p)
+{
+  uintptr_t result = 0;
+  uint32_t shift = 0;
+  uint8_t byte;
+  do
+  {
+    byte = *(*p)++;
+    result |= (uintptr_t)(byte & 0x7f) << shift;
+    shift += 7;
+  } while (byte & 0x80);
+  if (shift < 8 * sizeof(result) && (byte & 0x40))
+  {
+    result |= ~(uintptr_t)0 << shift;
+  }
+  return (intptr_t)result;
+}
+
+static size_t wasc_encoded_size(uint8_t encoding)
+{
+  switch (encoding & 0x0f)
+  {
+  case WASC_DW_EH_PE_udata2:
+  case WASC_DW_EH_PE_sdata2:
+    return 2;
+  case WASC_DW_EH_PE_udata4:
+  case WASC_DW_EH_PE_sdata4:
+    return 4;
+  default:
+    return 8;
+  }
+}
+
+// Read a pointer of the LSDA. Relative pointers are either relative to where they are, or to the start of the function.
+static uintptr_t wasc_read_encoded(const uint8_t **p, uint8_t encoding, uintptr_t function_start)
+{
+  const uint8_t *start = *p;
+  uintptr_t result;
+  switch (encoding & 0x0f)
+  {
+  case WASC_DW_EH_PE_uleb128:
+    result = wasc_read_uleb128(p);
+    break;
+  case WASC_DW_EH_PE_sleb128:
+    result = (uintptr_t)wasc_read_sleb128(p);
+    break;
+  case WASC_DW_EH_PE_udata2:
+  {
+    uint16_t value;
+    memcpy(&value, *p, sizeof(value));
+    result = value;
+    *p += sizeof(value);
+    break;
+  }
+  case WASC_DW_EH_PE_sdata2:
+  {
+    int16_t value;
+    memcpy(&value, *p, sizeof(value));
+    result = (uintptr_t)(intptr_t)value;
+    *p += sizeof(value);
+    break;
+  }
+  case WASC_DW_EH_PE_udata4:
+  {
+    uint32_t value;
+    memcpy(&value, *p, sizeof(value));
+    result = value;
+    *p += sizeof(value);
+    break;
+  }
+  case WASC_DW_EH_PE_sdata4:
+  {
+    int32_t value;
+    memcpy(&value, *p, sizeof(value));
+    result = (uintptr_t)(intptr_t)value;
+    *p += sizeof(value);
+    break;
+  }
+  default:
+  {
+    uint64_t value;
+    memcpy(&value, *p, sizeof(value));
+    result = value;
+    *p += sizeof(value);
+    break;
+  }
+  }
+  if (result == 0)
+  {
+    return 0;
+  }
+  switch (encoding & 0x70)
+  {
+  case WASC_DW_EH_PE_pcrel:
+    result += (uintptr_t)start;
+    break;
+  case WASC_DW_EH_PE_funcrel:
+    result += function_start;
+    break;
+  }
+  if (encoding & WASC_DW_EH_PE_indirect)
+  {
+    result = *(const uintptr_t *)result;
+  }
+  return result;
+}
+
+#define WASC_LANDING_PAD_NONE 0
+#define WASC_LANDING_PAD_CLEANUP 1
+#define WASC_LANDING_PAD_HANDLER 2
+
+// Find the landing pad of the call site the frame is in, and what it does with the exception: it catches it with the
+// clause selector, it only runs cleanups, or there is nothing to do. Wasm exceptions are caught by the catch clauses
+// of runtimeExceptionTypeInfo and by catch all clauses, other exceptions only run cleanups.
+static int wasc_find_landing_pad(struct _Unwind_Context *context, int native, uintptr_t *landing_pad,
+                                 intptr_t *selector)
+{
+  const uint8_t *p = (const uint8_t *)_Unwind_GetLanguageSpecificData(context);
+  if (p == NULL)
+  {
+    return WASC_LANDING_PAD_NONE;
+  }
+  uintptr_t function_start = _Unwind_GetRegionStart(context);
+  int before = 0;
+  uintptr_t ip = _Unwind_GetIPInfo(context, &before);
+  if (!before)
+  {
+    ip--;
+  }
+  uint8_t landing_pad_start_encoding = *p++;
+  uintptr_t landing_pad_start = function_start;
+  if (landing_pad_start_encoding != WASC_DW_EH_PE_omit)
+  {
+    landing_pad_start = wasc_read_encoded(&p, landing_pad_start_encoding, function_start);
+  }
+  uint8_t type_encoding = *p++;
+  const uint8_t *type_table = NULL;
+  if (type_encoding != WASC_DW_EH_PE_omit)
+  {
+    uintptr_t offset = wasc_read_uleb128(&p);
+    type_table = p + offset;
+  }
+  uint8_t call_site_encoding = *p++;
+  uintptr_t call_site_length = wasc_read_uleb128(&p);
+  const uint8_t *call_site_end = p + call_site_length;
+  const uint8_t *action_table = call_site_end;
+  while (p < call_site_end)
+  {
+    // Call site fields are offsets from the start of the function, they are never relative to where they are.
+    uintptr_t start = wasc_read_encoded(&p, call_site_encoding & 0x0f, 0);
+    uintptr_t length = wasc_read_encoded(&p, call_site_encoding & 0x0f, 0);
+    uintptr_t pad = wasc_read_encoded(&p, call_site_encoding & 0x0f, 0);
+    uintptr_t action = wasc_read_uleb128(&p);
+    if (ip < function_start + start)
+    {
+      break;
+    }
+    if (ip >= function_start + start + length)
+    {
+      continue;
+    }
+    if (pad == 0)
+    {
+      return WASC_LANDING_PAD_NONE;
+    }
+    *landing_pad = landing_pad_start + pad;
+    *selector = 0;
+    if (action == 0)
+    {
+      return WASC_LANDING_PAD_CLEANUP;
+    }
+    int cleanup = 0;
+    const uint8_t *a = action_table + action - 1;
+    for (;;)
+    {
+      intptr_t filter = wasc_read_sleb128(&a);
+      const uint8_t *next_field = a;
+      intptr_t next = wasc_read_sleb128(&a);
+      if (filter > 0 && native && type_table != NULL)
+      {
+        const uint8_t *entry = type_table - filter * wasc_encoded_size(type_encoding);
+        uintptr_t type_info = wasc_read_encoded(&entry, type_encoding, function_start);
+        if (type_info == 0 || type_info == (uintptr_t)&runtimeExceptionTypeInfo)
+        {
+          *selector = filter;
+          return WASC_LANDING_PAD_HANDLER;
+        }
+      }
+      else if (filter == 0)
+      {
+        cleanup = 1;
+      }
+      if (next == 0)
+      {
+        break;
+      }
+      a = next_field + next;
+    }
+    return cleanup ? WASC_LANDING_PAD_CLEANUP : WASC_LANDING_PAD_NONE;
+  }
+  return WASC_LANDING_PAD_NONE;
+}
+
+_Unwind_Reason_Code __gxx_personality_v0(int version, _Unwind_Action actions, _Unwind_Exception_Class exception_class,
+                                         struct _Unwind_Exception *header, struct _Unwind_Context *context)
+{
+  if (version != 1)
+  {
+    return _URC_FATAL_PHASE1_ERROR;
+  }
+  uintptr_t landing_pad = 0;
+  intptr_t selector = 0;
+  int found = wasc_find_landing_pad(context, exception_class == WASC_EXCEPTION_CLASS, &landing_pad, &selector);
+  if (actions & _UA_SEARCH_PHASE)
+  {
+    return found == WASC_LANDING_PAD_HANDLER ? _URC_HANDLER_FOUND : _URC_CONTINUE_UNWIND;
+  }
+  if (found == WASC_LANDING_PAD_NONE)
+  {
+    return _URC_CONTINUE_UNWIND;
+  }
+  _Unwind_SetGR(context, __builtin_eh_return_data_regno(0), (uintptr_t)header);
+  _Unwind_SetGR(context, __builtin_eh_return_data_regno(1), (uintptr_t)selector);
+  _Unwind_SetIP(context, landing_pad);
+  return _URC_INSTALL_CONTEXT;
+}
+
+#endif /* WASC_EXCEPTION_H */
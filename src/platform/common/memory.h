@@ -0,0 +1,143 @@
+#include <stdint.h>
+#include <string.h>
+
+#include "wavm.h"
+#include "trap.h"
+
+#ifndef WASC_MEMORY_H
+#define WASC_MEMORY_H
+
+#define WASC_MEMORY_PAGE_SIZE 0x10000
+
+// Emitted by the glue code, they find the memories and data segments of an instance, see WASC_MEMORY_ID.
+memory_instance *wasc_memory(void *context, uintptr_t id);
+wasc_segment *wasc_data_segment(void *context, uintptr_t index);
+int wasc_memory_shared(uintptr_t id);
+uint64_t wasc_memory_max_pages(uintptr_t id);
+// Implemented by the platform, it returns the old number of pages, or -1.
+int64_t wasc_memory_grow(memory_instance *memory, uint64_t delta, uint64_t max_pages);
+// Implemented by the platform. Waiting returns 0 when notified, 1 when the value is not the expected one and 2 on
+// timeout, notifying returns the number of waiters woken.
+int32_t wasc_atomic_wait(void *address, uint64_t expected, uint32_t size, int64_t timeout);
+int32_t wasc_atomic_notify(void *address, uint32_t count);
+
+// Whether [offset, offset + count) fits in a memory or a segment of the given length.
+int wasc_memory_range_valid(uint64_t offset, uint64_t count, uint64_t length)
+{
+  return offset <= length && count <= length - offset;
+}
+
+intptr_t wavm_intrinsic_memory_grow(void *context, uintptr_t delta, uintptr_t id)
+{
+  memory_instance *memory = wasc_memory(context, id);
+  if (memory == NULL)
+  {
+    return -1;
+  }
+  return wasc_memory_grow(memory, delta, wasc_memory_max_pages(id));
+}
+
+uintptr_t wavm_intrinsic_memory_size(void *context, uintptr_t id)
+{
+  memory_instance *memory = wasc_memory(context, id);
+  if (memory == NULL)
+  {
+    return 0;
+  }
+  return memory->num_pages;
+}
+
+void wavm_intrinsic_memory_init(void *context, uintptr_t dest, uint32_t source, uint32_t count, uintptr_t instance_id,
+                                uintptr_t memory_id, uintptr_t index)
+{
+  memory_instance *memory = wasc_memory(context, memory_id);
+  wasc_segment *segment = wasc_data_segment(context, index);
+  if (!wasc_memory_range_valid(dest, count, memory->num_pages * WASC_MEMORY_PAGE_SIZE) ||
+      !wasc_memory_range_valid(source, count, segment->length))
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_MEMORY, __builtin_return_address(0));
+  }
+  if (count != 0)
+  {
+    memcpy(memory->base + dest, (const uint8_t *)segment->base + source, count);
+  }
+}
+
+void wavm_intrinsic_data_drop(void *context, uintptr_t instance_id, uintptr_t index)
+{
+  wasc_segment *segment = wasc_data_segment(context, index);
+  segment->base = NULL;
+  segment->length = 0;
+}
+
+void wavm_intrinsic_memory_copy(void *context, uintptr_t dest, uintptr_t source, uintptr_t count, uintptr_t dest_id,
+                                uintptr_t source_id)
+{
+  memory_instance *dest_memory = wasc_memory(context, dest_id);
+  memory_instance *source_memory = wasc_memory(context, source_id);
+  if (!wasc_memory_range_valid(dest, count, dest_memory->num_pages * WASC_MEMORY_PAGE_SIZE) ||
+      !wasc_memory_range_valid(source, count, source_memory->num_pages * WASC_MEMORY_PAGE_SIZE))
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_MEMORY, __builtin_return_address(0));
+  }
+  memmove(dest_memory->base + dest, source_memory->base + source, count);
+}
+
+void wavm_intrinsic_memory_fill(void *context, uintptr_t dest, uint32_t value, uintptr_t count, uintptr_t id)
+{
+  memory_instance *memory = wasc_memory(context, id);
+  if (!wasc_memory_range_valid(dest, count, memory->num_pages * WASC_MEMORY_PAGE_SIZE))
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_MEMORY, __builtin_return_address(0));
+  }
+  memset(memory->base + dest, (uint8_t)value, count);
+}
+
+// The address of a wait or notify, which must be aligned and in bounds.
+uint8_t *wasc_atomic_address(memory_instance *memory, uintptr_t address, uint32_t size, void *pc)
+{
+  if (address & (size - 1))
+  {
+    wasc_trap(WASC_TRAP_MISALIGNED_ATOMIC, pc);
+  }
+  if (!wasc_memory_range_valid(address, size, memory->num_pages * WASC_MEMORY_PAGE_SIZE))
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_MEMORY, pc);
+  }
+  return memory->base + address;
+}
+
+int32_t wavm_intrinsic_atomic_notify(void *context, uintptr_t address, int32_t count, uintptr_t memory_id)
+{
+  uint8_t *p = wasc_atomic_address(wasc_memory(context, memory_id), address, 4, __builtin_return_address(0));
+  // Nobody can wait on a memory which is not shared.
+  if (!wasc_memory_shared(memory_id))
+  {
+    return 0;
+  }
+  return wasc_atomic_notify(p, (uint32_t)count);
+}
+
+int32_t wavm_intrinsic_atomic_wait_i32(void *context, uintptr_t address, int32_t expected, int64_t timeout,
+                                       uintptr_t memory_id)
+{
+  uint8_t *p = wasc_atomic_address(wasc_memory(context, memory_id), address, 4, __builtin_return_address(0));
+  if (!wasc_memory_shared(memory_id))
+  {
+    wasc_trap(WASC_TRAP_WAIT_ON_UNSHARED_MEMORY, __builtin_return_address(0));
+  }
+  return wasc_atomic_wait(p, (uint32_t)expected, 4, timeout);
+}
+
+int32_t wavm_intrinsic_atomic_wait_i64(void *context, uintptr_t address, int64_t expected, int64_t timeout,
+                                       uintptr_t memory_id)
+{
+  uint8_t *p = wasc_atomic_address(wasc_memory(context, memory_id), address, 8, __builtin_return_address(0));
+  if (!wasc_memory_shared(memory_id))
+  {
+    wasc_trap(WASC_TRAP_WAIT_ON_UNSHARED_MEMORY, __builtin_return_address(0));
+  }
+  return wasc_atomic_wait(p, (uint64_t)expected, 8, timeout);
+}
+
+#endif /* WASC_MEMORY_H */
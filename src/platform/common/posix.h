@@ -0,0 +1,684 @@
+#include <pthread.h>
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <time.h>
+#include <unistd.h>
+#include <errno.h>
+#ifdef __linux__
+#include <linux/futex.h>
+#include <sys/syscall.h>
+#endif
+
+#include "wavm.h"
+#include "trap.h"
+
+#ifndef WASC_POSIX_H
+#define WASC_POSIX_H
+
+#define WASC_PAGE_SIZE 0x10000
+// WAVM compiled code does not check memory bounds. An address is a 32 bits index plus a 32 bits offset, so it never
+// goes further than 8GiB from the base of the memory, the part of this region which is not in use is never accessible.
+#define WASC_MEMORY_RESERVE ((size_t)1 << 33)
+
+void wasc_sigsegv_install();
+
+// Instances are aligned to 4GiB, so that WAVM compiled code can get the instance back from the context pointer. Reserve
+// twice the size, keep the aligned part and give the rest back.
+void *wasc_instance_alloc(size_t size)
+{
+  size_t align = (size_t)1 << 32;
+  uint8_t *p = mmap(NULL, align * 2, PROT_NONE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+  if (p == MAP_FAILED)
+  {
+    return NULL;
+  }
+  uint8_t *instance = (uint8_t *)(((uintptr_t)p + align - 1) & ~(uintptr_t)(align - 1));
+  if (instance != p)
+  {
+    munmap(p, instance - p);
+  }
+  munmap(instance + align, p + align - instance);
+  if (mprotect(instance, size, PROT_READ | PROT_WRITE) != 0)
+  {
+    munmap(instance, align);
+    return NULL;
+  }
+  wasc_sigsegv_install();
+  return instance;
+}
+
+void wasc_instance_free(void *instance, size_t size)
+{
+  munmap(instance, (size_t)1 << 32);
+}
+
+// Every reserved memory region, the signal handler looks for the faulting address in them. Instances are created and
+// released by several threads while the handler may walk the list, so entries are only pushed, never unlinked nor
+// freed: a released region clears its base and the entry is taken again by the next one.
+typedef struct wasc_memory_region
+{
+  uint8_t *base;
+  struct wasc_memory_region *next;
+} wasc_memory_region;
+
+wasc_memory_region *wasc_memory_region_list = NULL;
+
+// Return 0, or -1 if it is out of memory.
+int wasc_memory_region_add(uint8_t *base)
+{
+  for (wasc_memory_region *e = __atomic_load_n(&wasc_memory_region_list, __ATOMIC_ACQUIRE); e != NULL; e = e->next)
+  {
+    uint8_t *expected = NULL;
+    if (__atomic_compare_exchange_n(&e->base, &expected, base, 0, __ATOMIC_SEQ_CST, __ATOMIC_SEQ_CST))
+    {
+      return 0;
+    }
+  }
+  wasc_memory_region *region = malloc(sizeof(wasc_memory_region));
+  if (region == NULL)
+  {
+    return -1;
+  }
+  region->base = base;
+  region->next = __atomic_load_n(&wasc_memory_region_list, __ATOMIC_RELAXED);
+  while (!__atomic_compare_exchange_n(&wasc_memory_region_list, &region->next, region, 1, __ATOMIC_RELEASE,
+                                      __ATOMIC_RELAXED))
+  {
+  }
+  return 0;
+}
+
+void wasc_memory_region_remove(uint8_t *base)
+{
+  for (wasc_memory_region *e = __atomic_load_n(&wasc_memory_region_list, __ATOMIC_ACQUIRE); e != NULL; e = e->next)
+  {
+    if (__atomic_load_n(&e->base, __ATOMIC_SEQ_CST) == base)
+    {
+      __atomic_store_n(&e->base, NULL, __ATOMIC_SEQ_CST);
+      return;
+    }
+  }
+}
+struct sigaction wasc_sigsegv_previous;
+
+// The address of the faulting instruction, to tell in which function the trap happens.
+void *wasc_sigsegv_pc(void *ucontext)
+{
+#if defined(__linux__) && defined(__x86_64__)
+  // REG_RIP, which is only defined with _GNU_SOURCE.
+  return (void *)((ucontext_t *)ucontext)->uc_mcontext.gregs[16];
+#elif defined(__APPLE__) && defined(__x86_64__)
+  return (void *)((ucontext_t *)ucontext)->uc_mcontext->__ss.__rip;
+#else
+  return NULL;
+#endif
+}
+
+// The stack of the thread, below which a stack overflow faults, see wasc_sigsegv_install.
+__thread uint8_t *wasc_stack_low = NULL;
+
+// A stack overflow faults on the guard pages right below the stack of the thread. They are at most 1MiB large, the
+// gap Linux keeps below the stack of the main thread.
+#define WASC_STACK_GUARD ((size_t)1 << 20)
+
+// The handler runs on the alternate stack of the thread, it only calls functions which are async-signal-safe, see
+// wasc_trap_signal.
+void wasc_sigsegv_handler(int sig, siginfo_t *info, void *ucontext)
+{
+  uint8_t *addr = (uint8_t *)info->si_addr;
+  for (wasc_memory_region *e = __atomic_load_n(&wasc_memory_region_list, __ATOMIC_ACQUIRE); e != NULL; e = e->next)
+  {
+    uint8_t *base = __atomic_load_n(&e->base, __ATOMIC_SEQ_CST);
+    if (base != NULL && addr >= base && addr < base + WASC_MEMORY_RESERVE)
+    {
+      wasc_trap_signal(WASC_TRAP_OUT_OF_BOUNDS_MEMORY, wasc_sigsegv_pc(ucontext));
+    }
+  }
+  uint8_t *stack_low = wasc_stack_low;
+  if (stack_low != NULL && addr < stack_low && addr >= stack_low - WASC_STACK_GUARD)
+  {
+    wasc_trap_signal(WASC_TRAP_STACK_OVERFLOW, wasc_sigsegv_pc(ucontext));
+  }
+  // Not an access to a linear memory, give the signal to whoever handled it before.
+  if (wasc_sigsegv_previous.sa_flags & SA_SIGINFO)
+  {
+    wasc_sigsegv_previous.sa_sigaction(sig, info, ucontext);
+    return;
+  }
+  if (wasc_sigsegv_previous.sa_handler != SIG_DFL && wasc_sigsegv_previous.sa_handler != SIG_IGN)
+  {
+    wasc_sigsegv_previous.sa_handler(sig);
+    return;
+  }
+  // Returning runs the faulting instruction again, this time it kills the process as usual.
+  sigaction(SIGSEGV, &wasc_sigsegv_previous, NULL);
+}
+
+// The alternate stack of every thread which installs one, it is released when the thread exits.
+pthread_key_t wasc_sigsegv_stack_key;
+
+#define WASC_SIGNAL_STACK_SIZE ((size_t)1 << 16)
+
+void wasc_sigsegv_stack_free(void *stack)
+{
+  stack_t disable;
+  memset(&disable, 0, sizeof(disable));
+  disable.ss_flags = SS_DISABLE;
+  sigaltstack(&disable, NULL);
+  munmap(stack, WASC_SIGNAL_STACK_SIZE);
+}
+
+void wasc_sigsegv_install_once(void)
+{
+  pthread_key_create(&wasc_sigsegv_stack_key, wasc_sigsegv_stack_free);
+  struct sigaction action;
+  memset(&action, 0, sizeof(action));
+  action.sa_sigaction = wasc_sigsegv_handler;
+  action.sa_flags = SA_SIGINFO | SA_NODEFER | SA_ONSTACK;
+  sigemptyset(&action.sa_mask);
+  sigaction(SIGSEGV, &action, &wasc_sigsegv_previous);
+}
+
+#if defined(__linux__)
+// Only declared with _GNU_SOURCE.
+int pthread_getattr_np(pthread_t thread, pthread_attr_t *attr);
+#endif
+
+// The lowest address of the stack of the calling thread, or NULL when it is not known.
+uint8_t *wasc_stack_low_get(void)
+{
+#if defined(__linux__)
+  pthread_attr_t attr;
+  void *addr = NULL;
+  size_t size = 0;
+  if (pthread_getattr_np(pthread_self(), &attr) != 0)
+  {
+    return NULL;
+  }
+  pthread_attr_getstack(&attr, &addr, &size);
+  pthread_attr_destroy(&attr);
+  return (uint8_t *)addr;
+#elif defined(__APPLE__)
+  return (uint8_t *)pthread_get_stackaddr_np(pthread_self()) - pthread_get_stacksize_np(pthread_self());
+#else
+  return NULL;
+#endif
+}
+
+// The first thread installs the handler, the others wait until it is done: a memory must not be used before. Every
+// thread which creates an instance, or runs a thread of wasi-threads, also gets an alternate stack, so that the
+// handler can run when the wasm code overflows the stack of the thread. A thread which calls into an instance it did
+// not create should call it too, a stack overflow kills the process otherwise.
+void wasc_sigsegv_install()
+{
+  static pthread_once_t once = PTHREAD_ONCE_INIT;
+  pthread_once(&once, wasc_sigsegv_install_once);
+  if (pthread_getspecific(wasc_sigsegv_stack_key) != NULL)
+  {
+    return;
+  }
+  void *stack = mmap(NULL, WASC_SIGNAL_STACK_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+  if (stack == MAP_FAILED)
+  {
+    return;
+  }
+  stack_t alternate;
+  memset(&alternate, 0, sizeof(alternate));
+  alternate.ss_sp = stack;
+  alternate.ss_size = WASC_SIGNAL_STACK_SIZE;
+  if (sigaltstack(&alternate, NULL) != 0)
+  {
+    munmap(stack, WASC_SIGNAL_STACK_SIZE);
+    return;
+  }
+  pthread_setspecific(wasc_sigsegv_stack_key, stack);
+  wasc_stack_low = wasc_stack_low_get();
+}
+
+// Set the memory to num_pages zeroed pages. The first call reserves the region, later calls reset the memory in place,
+// the address of the memory never changes. Running out of address space is fatal, like a trap.
+void wasc_memory_init(memory_instance *memory, uint64_t num_pages)
+{
+  if (memory->base == NULL)
+  {
+    uint8_t *base = mmap(NULL, WASC_MEMORY_RESERVE, PROT_NONE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+    if (base == MAP_FAILED || wasc_memory_region_add(base) != 0)
+    {
+      fprintf(stderr, "wasc: failed to reserve linear memory\n");
+      exit(1);
+    }
+    wasc_sigsegv_install();
+    memory->base = base;
+  }
+  else if (memory->num_pages != 0)
+  {
+    // Mapping fresh pages over the used ones drops their content and gives them back to the system.
+    mmap(memory->base, memory->num_pages * WASC_PAGE_SIZE, PROT_NONE,
+         MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE | MAP_FIXED, -1, 0);
+  }
+  if (num_pages != 0 && mprotect(memory->base, num_pages * WASC_PAGE_SIZE, PROT_READ | PROT_WRITE) != 0)
+  {
+    fprintf(stderr, "wasc: failed to commit linear memory\n");
+    exit(1);
+  }
+  memory->num_pages = num_pages;
+}
+
+// A shared memory may be grown by several threads at once, the lock keeps them from committing the same pages twice.
+char wasc_memory_lock = 0;
+
+void wasc_memory_lock_acquire()
+{
+  while (__atomic_test_and_set(&wasc_memory_lock, __ATOMIC_ACQUIRE))
+  {
+  }
+}
+
+void wasc_memory_lock_release()
+{
+  __atomic_clear(&wasc_memory_lock, __ATOMIC_RELEASE);
+}
+
+// Every instance of a thread has its own memory_instance, which WAVM compiled code reads, for each memory it shares with
+// other threads. Growing a shared memory updates all of them.
+typedef struct wasc_memory_view
+{
+  memory_instance *memory;
+  struct wasc_memory_view *next;
+} wasc_memory_view;
+
+wasc_memory_view *wasc_memory_view_list = NULL;
+
+int wasc_memory_view_add(memory_instance *memory)
+{
+  for (wasc_memory_view *e = wasc_memory_view_list; e != NULL; e = e->next)
+  {
+    if (e->memory == memory)
+    {
+      return 0;
+    }
+  }
+  wasc_memory_view *view = malloc(sizeof(wasc_memory_view));
+  if (view == NULL)
+  {
+    return -1;
+  }
+  view->memory = memory;
+  view->next = wasc_memory_view_list;
+  wasc_memory_view_list = view;
+  return 0;
+}
+
+void wasc_memory_view_remove(memory_instance *memory)
+{
+  wasc_memory_view **e = &wasc_memory_view_list;
+  while (*e != NULL && (*e)->memory != memory)
+  {
+    e = &(*e)->next;
+  }
+  if (*e != NULL)
+  {
+    wasc_memory_view *view = *e;
+    *e = view->next;
+    free(view);
+  }
+}
+
+// Let memory share the pages of source. Return 0, or -1 if it is out of memory.
+int wasc_memory_attach(memory_instance *memory, memory_instance *source)
+{
+  wasc_memory_lock_acquire();
+  int r = -1;
+  if (wasc_memory_view_add(source) == 0 && wasc_memory_view_add(memory) == 0)
+  {
+    memory->base = source->base;
+    memory->num_pages = source->num_pages;
+    r = 0;
+  }
+  wasc_memory_lock_release();
+  return r;
+}
+
+// Stop sharing the pages of memory, which are released by the instance that owns them.
+void wasc_memory_detach(memory_instance *memory)
+{
+  wasc_memory_lock_acquire();
+  wasc_memory_view_remove(memory);
+  wasc_memory_lock_release();
+  memory->base = NULL;
+  memory->num_pages = 0;
+}
+
+// Let memory share the pages of an imported memory. A memory the host defines is used as it is, it must have been
+// reserved by wasc_memory_init. Otherwise the first instance which imports it reserves it, with num_pages zeroed pages,
+// and it is never released. Running out of memory is fatal, like in wasc_memory_init.
+void wasc_memory_import(memory_instance *memory, memory_instance *source, uint64_t num_pages)
+{
+  wasc_memory_lock_acquire();
+  if (source->base == NULL)
+  {
+    wasc_memory_init(source, num_pages);
+  }
+  wasc_memory_lock_release();
+  if (wasc_memory_attach(memory, source) != 0)
+  {
+    fprintf(stderr, "wasc: failed to import linear memory\n");
+    exit(1);
+  }
+}
+
+// Grow the memory in place, new pages are zeroed by the system. Return the old number of pages, or -1.
+int64_t wasc_memory_grow(memory_instance *memory, uint64_t delta, uint64_t max_pages)
+{
+  wasc_memory_lock_acquire();
+  uint64_t old_pages = memory->num_pages;
+  uint64_t new_pages = old_pages + delta;
+  int64_t r = old_pages;
+  if (delta > max_pages || new_pages > max_pages)
+  {
+    r = -1;
+  }
+  else if (delta != 0)
+  {
+    uint8_t *addr = memory->base + old_pages * WASC_PAGE_SIZE;
+    if (mprotect(addr, (new_pages - old_pages) * WASC_PAGE_SIZE, PROT_READ | PROT_WRITE) != 0)
+    {
+      r = -1;
+    }
+    else
+    {
+      __atomic_store_n(&memory->num_pages, new_pages, __ATOMIC_SEQ_CST);
+      for (wasc_memory_view *e = wasc_memory_view_list; e != NULL; e = e->next)
+      {
+        if (e->memory->base == memory->base)
+        {
+          __atomic_store_n(&e->memory->num_pages, new_pages, __ATOMIC_SEQ_CST);
+        }
+      }
+    }
+  }
+  wasc_memory_lock_release();
+  return r;
+}
+
+#ifdef __linux__
+// Wait with a futex. A 64 bits value is waited for on its low half, which is where it starts on little endian machines:
+// notifying the address wakes waiters of both sizes.
+int32_t wasc_atomic_wait(void *address, uint64_t expected, uint32_t size, int64_t timeout)
+{
+  uint64_t value = size == 4 ? __atomic_load_n((uint32_t *)address, __ATOMIC_SEQ_CST)
+                             : __atomic_load_n((uint64_t *)address, __ATOMIC_SEQ_CST);
+  if (value != expected)
+  {
+    return 1;
+  }
+  // FUTEX_WAIT_BITSET takes an absolute time, which stays right when the wait is interrupted and started again.
+  struct timespec deadline;
+  if (timeout >= 0)
+  {
+    clock_gettime(CLOCK_MONOTONIC, &deadline);
+    deadline.tv_sec += timeout / 1000000000;
+    deadline.tv_nsec += timeout % 1000000000;
+    if (deadline.tv_nsec >= 1000000000)
+    {
+      deadline.tv_sec += 1;
+      deadline.tv_nsec -= 1000000000;
+    }
+  }
+  int interrupted = 0;
+  for (;;)
+  {
+    long r = syscall(SYS_futex, address, FUTEX_WAIT_BITSET_PRIVATE, (uint32_t)expected,
+                     timeout >= 0 ? &deadline : NULL, NULL, FUTEX_BITSET_MATCH_ANY);
+    if (r == 0)
+    {
+      return 0;
+    }
+    switch (errno)
+    {
+    case EINTR:
+      interrupted = 1;
+      break;
+    case ETIMEDOUT:
+      return 2;
+    case EAGAIN:
+      // The value changed before the futex was entered, or while the wait was interrupted by a signal.
+      return interrupted ? 0 : 1;
+    default:
+      return 0;
+    }
+  }
+}
+
+int32_t wasc_atomic_notify(void *address, uint32_t count)
+{
+  long r = syscall(SYS_futex, address, FUTEX_WAKE_PRIVATE, count > INT32_MAX ? INT32_MAX : count, NULL, NULL, 0);
+  return r < 0 ? 0 : (int32_t)r;
+}
+#else
+// Without futexes, a waiter sleeps on the condition variable of a bucket chosen by its address. The waiters of a bucket
+// are queued in the order they came, notifying an address wakes the first count waiters of that address.
+typedef struct wasc_waiter
+{
+  void *address;
+  int notified;
+  struct wasc_waiter *next;
+} wasc_waiter;
+
+typedef struct
+{
+  pthread_mutex_t lock;
+  pthread_cond_t cond;
+  wasc_waiter *list;
+} wasc_wait_bucket;
+
+#define WASC_WAIT_BUCKETS 64
+wasc_wait_bucket wasc_wait_bucket_list[WASC_WAIT_BUCKETS] = {
+    [0 ... WASC_WAIT_BUCKETS - 1] = {PTHREAD_MUTEX_INITIALIZER, PTHREAD_COND_INITIALIZER, NULL}};
+
+wasc_wait_bucket *wasc_wait_bucket_of(void *address)
+{
+  return &wasc_wait_bucket_list[((uintptr_t)address >> 2) % WASC_WAIT_BUCKETS];
+}
+
+int32_t wasc_atomic_wait(void *address, uint64_t expected, uint32_t size, int64_t timeout)
+{
+  wasc_wait_bucket *bucket = wasc_wait_bucket_of(address);
+  pthread_mutex_lock(&bucket->lock);
+  uint64_t value = size == 4 ? __atomic_load_n((uint32_t *)address, __ATOMIC_SEQ_CST)
+                             : __atomic_load_n((uint64_t *)address, __ATOMIC_SEQ_CST);
+  if (value != expected)
+  {
+    pthread_mutex_unlock(&bucket->lock);
+    return 1;
+  }
+  wasc_waiter waiter = {address, 0, NULL};
+  wasc_waiter **tail = &bucket->list;
+  while (*tail != NULL)
+  {
+    tail = &(*tail)->next;
+  }
+  *tail = &waiter;
+  // pthread_cond_timedwait takes an absolute time of the realtime clock.
+  struct timespec deadline;
+  if (timeout >= 0)
+  {
+    clock_gettime(CLOCK_REALTIME, &deadline);
+    deadline.tv_sec += timeout / 1000000000;
+    deadline.tv_nsec += timeout % 1000000000;
+    if (deadline.tv_nsec >= 1000000000)
+    {
+      deadline.tv_sec += 1;
+      deadline.tv_nsec -= 1000000000;
+    }
+  }
+  while (!waiter.notified)
+  {
+    if (timeout < 0)
+    {
+      pthread_cond_wait(&bucket->cond, &bucket->lock);
+    }
+    else if (pthread_cond_timedwait(&bucket->cond, &bucket->lock, &deadline) == ETIMEDOUT)
+    {
+      break;
+    }
+  }
+  // A waiter which timed out is still queued, a notified one was removed by wasc_atomic_notify.
+  if (!waiter.notified)
+  {
+    wasc_waiter **e = &bucket->list;
+    while (*e != &waiter)
+    {
+      e = &(*e)->next;
+    }
+    *e = waiter.next;
+  }
+  pthread_mutex_unlock(&bucket->lock);
+  return waiter.notified ? 0 : 2;
+}
+
+int32_t wasc_atomic_notify(void *address, uint32_t count)
+{
+  wasc_wait_bucket *bucket = wasc_wait_bucket_of(address);
+  pthread_mutex_lock(&bucket->lock);
+  int32_t woken = 0;
+  wasc_waiter **e = &bucket->list;
+  while (*e != NULL && (uint32_t)woken < count && woken < INT32_MAX)
+  {
+    if ((*e)->address == address)
+    {
+      (*e)->notified = 1;
+      *e = (*e)->next;
+      woken++;
+    }
+    else
+    {
+      e = &(*e)->next;
+    }
+  }
+  // The waiters of other addresses in the bucket wake up too, and go back to sleep.
+  if (woken != 0)
+  {
+    pthread_cond_broadcast(&bucket->cond);
+  }
+  pthread_mutex_unlock(&bucket->lock);
+  return woken;
+}
+#endif
+
+void wasc_memory_free(memory_instance *memory)
+{
+  if (memory->base == NULL)
+  {
+    return;
+  }
+  wasc_memory_region_remove(memory->base);
+  wasc_memory_lock_acquire();
+  wasc_memory_view_remove(memory);
+  wasc_memory_lock_release();
+  munmap(memory->base, WASC_MEMORY_RESERVE);
+  memory->base = NULL;
+  memory->num_pages = 0;
+}
+
+// WAVM compiled code does not check table bounds either, call_indirect clamps its index to the length of the table, see
+// instrument. A table reserves one element more than its maximum, the whole 32 bits index range when it has none, and
+// the part past its length is mapped to zero pages, which cost nothing until written: the element right past the end
+// reads as a null element, and calling it traps with callIndirectFail.
+size_t wasc_table_reserve(uint32_t maximum)
+{
+  size_t page = (size_t)sysconf(_SC_PAGESIZE);
+  return (((size_t)maximum + 1) * sizeof(uintptr_t) + page - 1) & ~(page - 1);
+}
+
+// Set the table to length null elements. Like a memory, the first call reserves the table for up to maximum elements
+// and later calls reset it in place.
+void wasc_table_init(table_instance *table, uint32_t length, uint32_t maximum)
+{
+  if (table->base == NULL)
+  {
+    size_t size = wasc_table_reserve(maximum);
+    void *base = mmap(NULL, size, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+    if (base == MAP_FAILED)
+    {
+      fprintf(stderr, "wasc: failed to reserve table\n");
+      exit(1);
+    }
+    table->base = base;
+    table->maximum = maximum;
+  }
+  else if (table->length != 0)
+  {
+    mmap(table->base, table->length * sizeof(uintptr_t), PROT_READ,
+         MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE | MAP_FIXED, -1, 0);
+  }
+  if (length != 0 && mprotect(table->base, length * sizeof(uintptr_t), PROT_READ | PROT_WRITE) != 0)
+  {
+    fprintf(stderr, "wasc: failed to commit table\n");
+    exit(1);
+  }
+  table->length = length;
+}
+
+// Let table share the elements of an imported table, which never grows. A table the host defines is used as it is, it
+// must have been reserved by wasc_table_init. Otherwise the first instance which imports it reserves it, with length
+// null elements, and it is never released: like the tables of instances, it traps on an index out of range.
+void wasc_table_import(table_instance *table, table_instance *source, uint32_t length)
+{
+  wasc_memory_lock_acquire();
+  if (source->base == NULL)
+  {
+    wasc_table_init(source, length, length);
+  }
+  wasc_memory_lock_release();
+  table->base = source->base;
+  table->length = source->length;
+  table->maximum = source->length;
+}
+
+// Grow the table in place up to its maximum, new elements are set to entry. Return the old length, or -1. Like a memory,
+// a table may be grown by several threads at once, they take the lock of memories.
+int32_t wasc_table_grow(table_instance *table, uint32_t delta, uintptr_t entry)
+{
+  wasc_memory_lock_acquire();
+  uint32_t old_length = table->length;
+  uint64_t new_length = (uint64_t)old_length + delta;
+  int32_t r = old_length;
+  if (new_length > table->maximum)
+  {
+    r = -1;
+  }
+  else if (delta != 0)
+  {
+    if (mprotect(table->base, new_length * sizeof(uintptr_t), PROT_READ | PROT_WRITE) != 0)
+    {
+      r = -1;
+    }
+    else
+    {
+      for (uint64_t i = old_length; i < new_length; i++)
+      {
+        table->base[i] = entry;
+      }
+      __atomic_store_n(&table->length, (uint32_t)new_length, __ATOMIC_SEQ_CST);
+    }
+  }
+  wasc_memory_lock_release();
+  return r;
+}
+
+void wasc_table_free(table_instance *table)
+{
+  if (table->base != NULL)
+  {
+    munmap(table->base, wasc_table_reserve(table->maximum));
+    table->base = NULL;
+    table->length = 0;
+  }
+}
+
+#endif /* WASC_POSIX_H */
@@ -0,0 +1,126 @@
+#include <stdint.h>
+#include <string.h>
+
+#include "wavm.h"
+#include "trap.h"
+
+#ifndef WASC_TABLE_H
+#define WASC_TABLE_H
+
+// Emitted by the glue code, it finds the table of an instance by its ID, see WASC_TABLE_ID.
+table_instance *wasc_table(void *context, uintptr_t id);
+// Implemented by the platform, it returns the old length of the table, or -1.
+int32_t wasc_table_grow(table_instance *table, uint32_t delta, uintptr_t entry);
+// Emitted by the glue code, it finds an element segment of an instance. Segments hold references.
+wasc_segment *wasc_element_segment(void *context, uintptr_t index);
+
+// Table entries are stored relative to tableReferenceBias, see the glue code. A null reference is stored as zero.
+extern const uint64_t tableReferenceBias[4];
+
+uintptr_t wasc_table_entry(void *reference)
+{
+  if (reference == NULL)
+  {
+    return 0;
+  }
+  return (uintptr_t)reference - (uintptr_t)tableReferenceBias;
+}
+
+void *wasc_table_reference(uintptr_t entry)
+{
+  if (entry == 0)
+  {
+    return NULL;
+  }
+  return (void *)(entry + (uintptr_t)tableReferenceBias);
+}
+
+// Called by the glue code when it initializes a table, an active element segment must fit in the table.
+void wasc_table_check(table_instance *table, uint32_t offset, uint32_t length)
+{
+  if ((uint64_t)offset + length > table->length)
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_TABLE, NULL);
+  }
+}
+
+void *wavm_intrinsic_table_get(void *context, uint32_t index, uintptr_t id)
+{
+  table_instance *table = wasc_table(context, id);
+  if (index >= table->length)
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_TABLE, __builtin_return_address(0));
+  }
+  return wasc_table_reference(table->base[index]);
+}
+
+void wavm_intrinsic_table_set(void *context, uint32_t index, void *reference, uintptr_t id)
+{
+  table_instance *table = wasc_table(context, id);
+  if (index >= table->length)
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_TABLE, __builtin_return_address(0));
+  }
+  table->base[index] = wasc_table_entry(reference);
+}
+
+uint32_t wavm_intrinsic_table_size(void *context, uintptr_t id)
+{
+  return wasc_table(context, id)->length;
+}
+
+int32_t wavm_intrinsic_table_grow(void *context, void *reference, uint32_t delta, uintptr_t id)
+{
+  return wasc_table_grow(wasc_table(context, id), delta, wasc_table_entry(reference));
+}
+
+void wavm_intrinsic_table_fill(void *context, uint32_t index, void *reference, uint32_t count, uintptr_t id)
+{
+  table_instance *table = wasc_table(context, id);
+  if ((uint64_t)index + count > table->length)
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_TABLE, __builtin_return_address(0));
+  }
+  uintptr_t entry = wasc_table_entry(reference);
+  for (uint32_t i = 0; i < count; i++)
+  {
+    table->base[index + i] = entry;
+  }
+}
+
+void wavm_intrinsic_table_init(void *context, uint32_t dest, uint32_t source, uint32_t count, uintptr_t instance_id,
+                               uintptr_t id, uintptr_t index)
+{
+  table_instance *table = wasc_table(context, id);
+  wasc_segment *segment = wasc_element_segment(context, index);
+  if ((uint64_t)dest + count > table->length || (uint64_t)source + count > segment->length)
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_TABLE, __builtin_return_address(0));
+  }
+  void *const *reference = segment->base;
+  for (uint32_t i = 0; i < count; i++)
+  {
+    table->base[dest + i] = wasc_table_entry(reference[source + i]);
+  }
+}
+
+void wavm_intrinsic_elem_drop(void *context, uintptr_t instance_id, uintptr_t index)
+{
+  wasc_segment *segment = wasc_element_segment(context, index);
+  segment->base = NULL;
+  segment->length = 0;
+}
+
+void wavm_intrinsic_table_copy(void *context, uint32_t dest, uint32_t source, uint32_t count, uintptr_t dest_id,
+                               uintptr_t source_id)
+{
+  table_instance *dest_table = wasc_table(context, dest_id);
+  table_instance *source_table = wasc_table(context, source_id);
+  if ((uint64_t)dest + count > dest_table->length || (uint64_t)source + count > source_table->length)
+  {
+    wasc_trap(WASC_TRAP_OUT_OF_BOUNDS_TABLE, __builtin_return_address(0));
+  }
+  memmove(dest_table->base + dest, source_table->base + source, count * sizeof(uintptr_t));
+}
+
+#endif /* WASC_TABLE_H */
@@ -0,0 +1,169 @@
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#ifndef WASC_TRAP_CKB
+#include <unistd.h>
+#endif
+
+#include "trap_code.h"
+
+#ifndef WASC_TRAP_H
+#define WASC_TRAP_H
+
+// A trap terminates the process with a reserved code, so that it can not be confused with a program that exits by
+// itself. Scripts on ckb-vm report negative error codes, which no wasm program exits with.
+#ifdef WASC_TRAP_CKB
+#define WASC_TRAP_EXIT_CODE(trap) (-100 - (trap))
+#else
+#define WASC_TRAP_EXIT_CODE(trap) (200 + (trap))
+#endif
+
+// Called before the process is terminated. An embedder which sets it can recover from the trap by jumping out of it
+// with longjmp, otherwise the process is terminated when it returns. On posix, out of bounds memory accesses and stack
+// overflows are reported from a signal handler: the hook must then only call async-signal-safe functions, or leave with
+// siglongjmp.
+void (*wasc_trap_hook)(int32_t trap, const char *function) = NULL;
+
+const char *wasc_trap_name(int32_t trap)
+{
+  switch (trap)
+  {
+  case WASC_TRAP_UNREACHABLE:
+    return "unreachable executed";
+  case WASC_TRAP_DIVIDE_BY_ZERO_OR_INTEGER_OVERFLOW:
+    return "integer divide by zero or integer overflow";
+  case WASC_TRAP_INVALID_FLOAT_OPERATION:
+    return "invalid conversion to integer";
+  case WASC_TRAP_CALL_INDIRECT:
+    return "indirect call to a null or mismatched function";
+  case WASC_TRAP_OUT_OF_BOUNDS_MEMORY:
+    return "out of bounds memory access";
+  case WASC_TRAP_OUT_OF_BOUNDS_TABLE:
+    return "out of bounds table access";
+  case WASC_TRAP_MISALIGNED_ATOMIC:
+    return "misaligned atomic memory access";
+  case WASC_TRAP_WAIT_ON_UNSHARED_MEMORY:
+    return "wait on unshared memory";
+  case WASC_TRAP_UNCAUGHT_EXCEPTION:
+    return "uncaught exception";
+  case WASC_TRAP_STACK_OVERFLOW:
+    return "call stack exhausted";
+  default:
+    return "unknown trap";
+  }
+}
+
+#ifdef WASC_TRAP_CKB
+// There is no room for function names in a script.
+const char *wasc_trap_function(void *pc)
+{
+  return NULL;
+}
+#else
+// Every wasm function of the module with its address, emitted by the glue code.
+extern const void *wasc_function_address[];
+extern const char *wasc_function_name[];
+extern const uint32_t wasc_function_length;
+
+// The function which contains an instruction is the one which starts closest before it.
+const char *wasc_trap_function(void *pc)
+{
+  const char *function = NULL;
+  uintptr_t start = 0;
+  for (uint32_t i = 0; i < wasc_function_length; i++)
+  {
+    uintptr_t address = (uintptr_t)wasc_function_address[i];
+    if (address <= (uintptr_t)pc && address >= start)
+    {
+      start = address;
+      function = wasc_function_name[i];
+    }
+  }
+  return function;
+}
+#endif
+
+void wasc_trap(int32_t trap, void *pc)
+{
+  const char *function = wasc_trap_function(pc);
+  if (wasc_trap_hook != NULL)
+  {
+    wasc_trap_hook(trap, function);
+  }
+#ifndef WASC_TRAP_CKB
+  if (function != NULL)
+  {
+    fprintf(stderr, "wasc: trap: %s in %s\n", wasc_trap_name(trap), function);
+  }
+  else
+  {
+    fprintf(stderr, "wasc: trap: %s\n", wasc_trap_name(trap));
+  }
+#endif
+  exit(WASC_TRAP_EXIT_CODE(trap));
+}
+
+#ifndef WASC_TRAP_CKB
+void wasc_trap_write(const char *text)
+{
+  size_t length = strlen(text);
+  while (length != 0)
+  {
+    ssize_t r = write(STDERR_FILENO, text, length);
+    if (r <= 0)
+    {
+      return;
+    }
+    text += r;
+    length -= r;
+  }
+}
+
+// Like wasc_trap, from a signal handler: it only calls async-signal-safe functions besides the hook.
+void wasc_trap_signal(int32_t trap, void *pc)
+{
+  const char *function = wasc_trap_function(pc);
+  if (wasc_trap_hook != NULL)
+  {
+    wasc_trap_hook(trap, function);
+  }
+  wasc_trap_write("wasc: trap: ");
+  wasc_trap_write(wasc_trap_name(trap));
+  if (function != NULL)
+  {
+    wasc_trap_write(" in ");
+    wasc_trap_write(function);
+  }
+  wasc_trap_write("\n");
+  _exit(WASC_TRAP_EXIT_CODE(trap));
+}
+#endif
+
+// Called by WAVM compiled code, the return address is in the function which traps.
+void callIndirectFail()
+{
+  wasc_trap(WASC_TRAP_CALL_INDIRECT, __builtin_return_address(0));
+}
+
+void unreachableTrap()
+{
+  wasc_trap(WASC_TRAP_UNREACHABLE, __builtin_return_address(0));
+}
+
+void divideByZeroOrIntegerOverflowTrap()
+{
+  wasc_trap(WASC_TRAP_DIVIDE_BY_ZERO_OR_INTEGER_OVERFLOW, __builtin_return_address(0));
+}
+
+void invalidFloatOperationTrap()
+{
+  wasc_trap(WASC_TRAP_INVALID_FLOAT_OPERATION, __builtin_return_address(0));
+}
+
+void misalignedAtomicTrap()
+{
+  wasc_trap(WASC_TRAP_MISALIGNED_ATOMIC, __builtin_return_address(0));
+}
+
+#endif /* WASC_TRAP_H */
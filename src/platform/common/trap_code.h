@@ -0,0 +1,16 @@
+#ifndef WASC_TRAP_CODE_H
+#define WASC_TRAP_CODE_H
+
+// Trap kinds, given to the trap hook. The public header of a module includes them as they are.
+#define WASC_TRAP_UNREACHABLE 1
+#define WASC_TRAP_DIVIDE_BY_ZERO_OR_INTEGER_OVERFLOW 2
+#define WASC_TRAP_INVALID_FLOAT_OPERATION 3
+#define WASC_TRAP_CALL_INDIRECT 4
+#define WASC_TRAP_OUT_OF_BOUNDS_MEMORY 5
+#define WASC_TRAP_OUT_OF_BOUNDS_TABLE 6
+#define WASC_TRAP_MISALIGNED_ATOMIC 7
+#define WASC_TRAP_WAIT_ON_UNSHARED_MEMORY 8
+#define WASC_TRAP_UNCAUGHT_EXCEPTION 9
+#define WASC_TRAP_STACK_OVERFLOW 10
+
+#endif /* WASC_TRAP_CODE_H */
@@ -27,10 +27,126 @@ typedef struct
     double value;
 } wavm_ret_double;
 
+// References are pointers to the objects of WAVM, null is NULL. A function reference points 32 bytes before the code of
+// the function, at the type ID and the other fields WAVM puts there. The host may pass any other pointer as an extern
+// reference, wasm code never looks into it.
+typedef void *wasc_funcref;
+typedef void *wasc_externref;
+
+typedef struct
+{
+    void *dummy;
+    wasc_funcref value;
+} wavm_ret_wasc_funcref;
+
+typedef struct
+{
+    void *dummy;
+    wasc_externref value;
+} wavm_ret_wasc_externref;
+
+// A v128 value, as WAVM compiled code sees it: two 64 bits lanes, passed and returned in vector registers.
+typedef int64_t wasc_v128 __attribute__((vector_size(16)));
+
 typedef struct memory_instance
 {
     uint8_t *base;
     uint64_t num_pages;
 } memory_instance;
 
+// WAVM compiled code only reads the base of a table, the length and the maximum are kept for the table intrinsics.
+typedef struct table_instance
+{
+    uintptr_t *base;
+    uint32_t length;
+    uint32_t maximum;
+} table_instance;
+
+// A data or element segment which memory.init or table.init may copy from. Dropping a segment empties it, active and
+// declarative segments are empty from the start.
+typedef struct wasc_segment
+{
+    const void *base;
+    uint32_t length;
+} wasc_segment;
+
+// An exception tag. WAVM compiled code identifies the tag of an exception by the address of the wasc_tag the glue code
+// defines for it, or the host defines for an imported tag.
+typedef struct wasc_tag
+{
+    uint32_t num_params;
+} wasc_tag;
+
+// A value of any type, in the 16 bytes slots WAVM uses for the values an exception carries.
+typedef union wasc_value
+{
+    int32_t i32;
+    int64_t i64;
+    float f32;
+    double f64;
+    wasc_v128 v128;
+    void *ref;
+} wasc_value;
+
+// An exception created by createException. WAVM compiled code compares type_id with the address of the tags it
+// catches, and reads the values from arguments.
+typedef struct wasc_exception
+{
+    uintptr_t type_id;
+    uint32_t is_user_exception;
+    uint32_t num_arguments;
+    wasc_value arguments[];
+} wasc_exception;
+
+// Like tables below, the memory intrinsics get the index of a memory in its compartment, computed from the
+// memoryOffsetN symbol of the memory: (memoryOffsetN - offsetof(CompartmentRuntimeData, memories)) / 16.
+#define WASC_WAVM_MEMORIES_OFFSET 8
+#define WASC_MEMORY_ID(offset) (((uintptr_t)(offset) - WASC_WAVM_MEMORIES_OFFSET) / sizeof(memory_instance))
+
+// Addresses and lengths in a memory, and numbers of pages, are given to the memory intrinsics and returned by them as
+// pointer sized values: WAVM compiled code zero extends those of 32 bits memories, and truncates the results.
+
+// WAVM compiled code gives the table intrinsics the index of a table in its compartment, which it computes from the
+// tableOffsetN symbol of the table: (tableOffsetN - offsetof(CompartmentRuntimeData, tables)) / sizeof(void *). In a
+// WAVM compartment, the tables follow a pointer and 255 memories of 16 bytes.
+#define WASC_WAVM_TABLES_OFFSET 4088
+#define WASC_TABLE_ID(offset) (((uintptr_t)(offset) - WASC_WAVM_TABLES_OFFSET) / sizeof(uintptr_t))
+
+// Define a symbol with the given value. WAVM compiled code finds the fields of an instance through the addresses of
+// symbols, the glue code defines them as the offsets of the fields.
+#define WASC_STRINGIFY(x) #x
+#ifdef __APPLE__
+#define WASC_SET_SYMBOL(name, value) __asm__(".globl _" #name "\n.set _" #name ", " WASC_STRINGIFY(value))
+#else
+#define WASC_SET_SYMBOL(name, value) __asm__(".globl " #name "\n.set " #name ", " WASC_STRINGIFY(value))
+#endif
+
+// Define a function which a reference can point to. WAVM compiled code checks the type of a function before calling
+// it through a table: the type ID is stored right before the code of the function, as WAVM does for the functions it
+// compiles. Imported functions have no such prefix, so WAVM compiled code calls them through a small stub which has one.
+#if defined(__APPLE__)
+#define WASC_SYMBOL(name) "_" #name
+#define WASC_JUMP(name) "jmp _" #name
+#define WASC_CALL(name) "call _" #name
+#elif defined(__riscv)
+#define WASC_SYMBOL(name) #name
+#define WASC_JUMP(name) "tail " #name
+#define WASC_CALL(name) "call " #name
+#else
+#define WASC_SYMBOL(name) #name
+#define WASC_JUMP(name) "jmp " #name "@PLT"
+#define WASC_CALL(name) "call " #name "@PLT"
+#endif
+#if defined(__APPLE__)
+#define WASC_SECTION_PUSH ".text\n"
+#define WASC_SECTION_POP ""
+#else
+#define WASC_SECTION_PUSH ".pushsection .text\n"
+#define WASC_SECTION_POP ".popsection\n"
+#endif
+#define WASC_FUNCTION_ELEMENT(name, function, type_id)                                                          \
+  extern const uint8_t name[];                                                                                 \
+  __asm__(WASC_SECTION_PUSH ".balign 16\n.quad 0, 0, 0, " WASC_STRINGIFY(type_id) "\n.globl " WASC_SYMBOL(name) "\n" \
+          WASC_SYMBOL(name) ":\n" WASC_JUMP(function) "\n" WASC_SECTION_POP)
+
 #endif /* WAVM_H */
@@ -1,11 +1,15 @@
 #include <dirent.h>
 #include <fcntl.h>
 #include <errno.h>
+#include <limits.h>
+#include <poll.h>
+#include <pthread.h>
 #include <stddef.h>
 #include <stdint.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
+#include <sys/ioctl.h>
 #include <sys/stat.h>
 #include <sys/types.h>
 #include <sys/uio.h>
@@ -13,6 +17,11 @@
 #include <unistd.h>
 
 #include "common/wavm.h"
+#include "common/posix.h"
+#include "common/trap.h"
+#include "common/table.h"
+#include "common/memory.h"
+#include "common/exception.h"
 #include "common/wasi.h"
 
 #ifndef WAVM_POSIX_X86_64_WASI_H
@@ -23,70 +32,6 @@
 extern int32_t g_argc;
 extern char **g_argv;
 
-#define WAVM_PAGE_SIZE 0x10000
-#ifndef MEMORY0_MAX_PAGE
-#define MEMORY0_MAX_PAGE 65536
-#endif /* MEMORY0_MAX_PAGE */
-
-extern memory_instance memoryOffset0;
-extern uint8_t *memory0;
-
-int32_t wavm_intrinsic_memory_grow(void *dummy, int32_t grow_by)
-{
-#ifdef DEBUG
-  printf("wavm_intrinsic_memory_grow grow_by=%d\n", grow_by);
-#endif
-  if (grow_by == 0)
-  {
-    return memoryOffset0.num_pages;
-  }
-  int32_t old_pages = memoryOffset0.num_pages;
-  int32_t new_pages = old_pages + grow_by;
-  if (new_pages > MEMORY0_MAX_PAGE)
-  {
-    return -1;
-  }
-  size_t old_size = old_pages * WAVM_PAGE_SIZE;
-  size_t new_size = new_pages * WAVM_PAGE_SIZE;
-  memory0 = (uint8_t *)realloc(memory0, new_size);
-  memset(memory0 + old_size, 0, new_size - old_size);
-  memoryOffset0.base = memory0;
-  memoryOffset0.num_pages = new_pages;
-  return old_pages;
-}
-
-void callIndirectFail()
-{
-#ifdef DEBUG
-  printf("callIndirectFail\n");
-#endif
-  exit(1);
-}
-
-void unreachableTrap()
-{
-#ifdef DEBUG
-  printf("unreachableTrap\n");
-#endif
-  exit(1);
-}
-
-void divideByZeroOrIntegerOverflowTrap()
-{
-#ifdef DEBUG
-  printf("divideByZeroOrIntegerOverflowTrap\n");
-#endif
-  exit(1);
-}
-
-void invalidFloatOperationTrap()
-{
-#ifdef DEBUG
-  printf("invalidFloatOperationTrap\n");
-#endif
-  exit(1);
-}
-
 #define STDIO_RIGHTS (__WASI_RIGHT_FD_READ | __WASI_RIGHT_FD_FDSTAT_SET_FLAGS | __WASI_RIGHT_FD_WRITE | \
                       __WASI_RIGHT_FD_FILESTAT_GET | __WASI_RIGHT_POLL_FD_READWRITE)
 #define REGULAR_FILE_RIGHTS (__WASI_RIGHT_FD_DATASYNC | __WASI_RIGHT_FD_READ | __WASI_RIGHT_FD_SEEK |         \
@@ -105,14 +50,17 @@ void invalidFloatOperationTrap()
                           __WASI_RIGHT_PATH_REMOVE_DIRECTORY | __WASI_RIGHT_POLL_FD_READWRITE)
 #define INHERITING_DIRECTORY_RIGHTS (DIRECTORY_RIGHTS | REGULAR_FILE_RIGHTS)
 
+// A preopen is a directory of the host which the module may access, under the path the guest knows it by. Preopens
+// take the file descriptors following the standard streams, in the order they are given. Nothing outside of them can
+// be reached: without any preopen, the module has no access to the file system.
 typedef struct Preopen
 {
   char *path;
   int32_t path_len;
 } Preopen;
 
-#define PREOPEN_CNT 7
-Preopen preopen[PREOPEN_CNT] = {
+#define PREOPEN_MAX 64
+Preopen preopen[PREOPEN_MAX] = {
     {
         .path = "<stdin>",
         .path_len = 7,
@@ -125,113 +73,227 @@ Preopen preopen[PREOPEN_CNT] = {
         .path = "<stderr>",
         .path_len = 8,
     },
-    {
-        .path = "./",
-        .path_len = 2,
-    },
-    {
-        .path = "../",
-        .path_len = 3,
-    },
-    {
-        .path = "/",
-        .path_len = 1,
-    },
-    {
-        .path = "/tmp",
-        .path_len = 4,
-    },
 };
+int32_t preopen_cnt = 3;
 
 #define FD_RIGHTS_CNT 1024
 
+// The rights of every file descriptor the module owns. Other file descriptors of the process, such as inherited ones,
+// are not open for the module.
 typedef struct Fdrights
 {
   __wasi_rights_t base;
   __wasi_rights_t inheriting;
+  int32_t open;
 } Fdrights;
 
 Fdrights fdrights[FD_RIGHTS_CNT] = {
     {
         .base = STDIO_RIGHTS,
         .inheriting = 0,
+        .open = 1,
     },
     {
         .base = STDIO_RIGHTS,
         .inheriting = 0,
+        .open = 1,
     },
     {
         .base = STDIO_RIGHTS,
         .inheriting = 0,
+        .open = 1,
     },
+};
+
+// Guards preopen and fdrights, threads of wasi-threads open, renumber and close file descriptors concurrently.
+pthread_mutex_t fd_table_lock = PTHREAD_MUTEX_INITIALIZER;
+
+// Whether fd is a preopen. A preopen which is closed or renumbered loses its path, whatever file descriptor takes its
+// number is not a preopen.
+int is_preopen(int32_t fd)
+{
+  return fd >= 3 && fd < PREOPEN_MAX && preopen[fd].path != NULL;
+}
+
+void clear_preopen(int32_t fd)
+{
+  if (is_preopen(fd))
+  {
+    free(preopen[fd].path);
+    preopen[fd].path = NULL;
+    preopen[fd].path_len = 0;
+  }
+}
+
+// Open the directory host_path as the preopen guest_path, at the next file descriptor. A file descriptor the process
+// inherited there is moved out of the way, it stays open. Return 0, or -1 with errno set.
+int wasc_wasi_preopen(const char *host_path, const char *guest_path)
+{
+  pthread_mutex_lock(&fd_table_lock);
+  if (preopen_cnt == PREOPEN_MAX)
+  {
+    pthread_mutex_unlock(&fd_table_lock);
+    errno = EMFILE;
+    return -1;
+  }
+  int32_t fd = preopen_cnt;
+  int host_fd = open(host_path, O_RDONLY | O_DIRECTORY | O_CLOEXEC);
+  if (host_fd < 0)
+  {
+    pthread_mutex_unlock(&fd_table_lock);
+    return -1;
+  }
+  if (host_fd != fd)
+  {
+    if (fcntl(fd, F_GETFD) >= 0 && (fcntl(fd, F_DUPFD, PREOPEN_MAX) < 0 || close(fd) != 0))
     {
-        .base = DIRECTORY_RIGHTS,
-        .inheriting = INHERITING_DIRECTORY_RIGHTS,
-    },
-    {
-        .base = DIRECTORY_RIGHTS,
-        .inheriting = INHERITING_DIRECTORY_RIGHTS,
-    },
-    {
-        .base = DIRECTORY_RIGHTS,
-        .inheriting = INHERITING_DIRECTORY_RIGHTS,
-    },
+      close(host_fd);
+      pthread_mutex_unlock(&fd_table_lock);
+      return -1;
+    }
+    if (dup2(host_fd, fd) < 0)
     {
-        .base = DIRECTORY_RIGHTS,
-        .inheriting = INHERITING_DIRECTORY_RIGHTS,
-    },
-};
+      close(host_fd);
+      pthread_mutex_unlock(&fd_table_lock);
+      return -1;
+    }
+    close(host_fd);
+  }
+  preopen[fd].path = strdup(guest_path);
+  preopen[fd].path_len = strlen(guest_path);
+  fdrights[fd].base = DIRECTORY_RIGHTS;
+  fdrights[fd].inheriting = INHERITING_DIRECTORY_RIGHTS;
+  fdrights[fd].open = 1;
+  preopen_cnt++;
+  pthread_mutex_unlock(&fd_table_lock);
+  return 0;
+}
+
+// Preopen a directory given as host_path::guest_path, or host_path when both are the same.
+void init_wasi_dir(const char *dir)
+{
+  const char *sep = strstr(dir, "::");
+  char *host_path = sep == NULL ? strdup(dir) : strndup(dir, sep - dir);
+  const char *guest_path = sep == NULL ? dir : sep + 2;
+  if (wasc_wasi_preopen(host_path, guest_path) != 0)
+  {
+    fprintf(stderr, "wasc: failed to preopen '%s': %s\n", host_path, strerror(errno));
+    exit(1);
+  }
+  free(host_path);
+}
 
-void init_wasi()
+// Preopens are listed in the WASC_DIRS environment variable, separated by ';', then by --dir options in front of the
+// arguments of the module, optionally ended by "--". These options are not passed to the module.
+void init_wasi(void)
 {
-  for (int fd = 3; fd < PREOPEN_CNT; fd++)
+  char *dirs = getenv("WASC_DIRS");
+  if (dirs != NULL)
   {
-    if (fcntl(fd, F_GETFD, 0) >= 0)
+    char *list = strdup(dirs);
+    char *save = NULL;
+    for (char *dir = strtok_r(list, ";", &save); dir != NULL; dir = strtok_r(NULL, ";", &save))
     {
-      close(fd);
+      init_wasi_dir(dir);
     }
+    free(list);
   }
-  for (int fd = 3; fd < PREOPEN_CNT; fd++)
+  int32_t i = 1;
+  while (i < g_argc)
   {
-    int tfd = open(preopen[fd].path, O_RDONLY);
-    if (tfd < 0)
+    if (strcmp(g_argv[i], "--dir") == 0 && i + 1 < g_argc)
     {
-      printf("opening '%s': %s\n", "./", strerror(errno));
-      exit(1);
+      init_wasi_dir(g_argv[i + 1]);
+      i += 2;
     }
-    if (tfd != fd)
+    else if (strncmp(g_argv[i], "--dir=", 6) == 0)
     {
-      printf("fd %d could not be freed up before preopen\n", fd);
-      exit(1);
+      init_wasi_dir(g_argv[i] + 6);
+      i += 1;
     }
+    else
+    {
+      if (i > 1 && strcmp(g_argv[i], "--") == 0)
+      {
+        i += 1;
+      }
+      break;
+    }
+  }
+  if (i > 1)
+  {
+    g_argv[i - 1] = g_argv[0];
+    g_argv += i - 1;
+    g_argc -= i - 1;
+  }
+}
+
+// Every address and length a module passes to a WASI function goes through these accessors, so the host never reads
+// or writes outside the exported memory.
+
+// Get the host address of the num_bytes bytes at address in the exported memory, or NULL if they are not all inside.
+void *wasi_memory(void *dummy, uint32_t address, uint64_t num_bytes)
+{
+  memory_instance *memory = wasc_memory_exported(dummy);
+  if (memory->base == NULL ||
+      !wasc_memory_range_valid(address, num_bytes, memory->num_pages * WASC_MEMORY_PAGE_SIZE))
+  {
+    return NULL;
+  }
+  return &memory->base[address];
+}
+
+#define MAX_PATH_LENGTH 1024
+
+// Copy a path out of the exported memory into a buffer of MAX_PATH_LENGTH bytes, and terminate it.
+__wasi_errno_t wasi_memory_path(void *dummy, uint32_t address, uint32_t num_bytes, char path[MAX_PATH_LENGTH])
+{
+  if (num_bytes >= MAX_PATH_LENGTH)
+  {
+    return __WASI_ENAMETOOLONG;
+  }
+  char *p = wasi_memory(dummy, address, num_bytes);
+  if (p == NULL)
+  {
+    return __WASI_EFAULT;
   }
+  memcpy(path, p, num_bytes);
+  path[num_bytes] = '\0';
+  return 0;
 }
 
 #define MAX_IOV 128
 
-struct iovec host_iov[MAX_IOV];
+__thread struct iovec host_iov[MAX_IOV];
 
-struct iovec *copy_iov_to_host(uint32_t iov_offset, uint32_t iovs_len)
+// Translate the iovs_len __wasi_iovec_t at iov_offset into host iovecs, checking every buffer.
+__wasi_errno_t copy_iov_to_host(void *dummy, uint32_t iov_offset, uint32_t iovs_len, struct iovec **iovs)
 {
   if (iovs_len > MAX_IOV)
   {
-    printf("copy_iov_to_host called with iovs_len > 128\n");
-    exit(1);
+    return __WASI_EINVAL;
   }
-  struct iovec *wasi_iov = (struct iovec *)&memoryOffset0.base[iov_offset];
-  for (int32_t i = 0; i < iovs_len; i++)
+  uint32_t *wasi_iov = wasi_memory(dummy, iov_offset, (uint64_t)iovs_len * 8);
+  if (wasi_iov == NULL)
   {
-    uint32_t buffer_address = *((uint32_t *)&memoryOffset0.base[iov_offset + i * 8]);
-    uint8_t *buf = &memoryOffset0.base[buffer_address];
-    uint32_t buffer_length = *((uint32_t *)&memoryOffset0.base[iov_offset + i * 8 + 4]);
+    return __WASI_EFAULT;
+  }
+  for (uint32_t i = 0; i < iovs_len; i++)
+  {
+    uint32_t buffer_address = wasi_iov[i * 2];
+    uint32_t buffer_length = wasi_iov[i * 2 + 1];
+    uint8_t *buf = wasi_memory(dummy, buffer_address, buffer_length);
+    if (buf == NULL)
+    {
+      return __WASI_EFAULT;
+    }
     host_iov[i].iov_base = buf;
     host_iov[i].iov_len = buffer_length;
   }
-  return host_iov;
+  *iovs = host_iov;
+  return 0;
 }
 
-#define MAX_PATH_LENGTH 1024
-
 __wasi_timestamp_t conv_host_timespec_2_wasi_timestamp(struct timespec t)
 {
   return t.tv_sec * 1000000000 + t.tv_nsec;
@@ -245,16 +307,7 @@ struct timespec conv_wasi_timestamp_2_host_timespec(__wasi_timestamp_t t)
   return r;
 }
 
-int32_t conv_wasi_lookupflags_2_host_lookupflags(__wasi_lookupflags_t lookup_flags)
-{
-  int32_t f = 0;
-  if ((lookup_flags & __WASI_LOOKUP_SYMLINK_FOLLOW) == 0)
-  {
-    f |= AT_SYMLINK_NOFOLLOW;
-  }
-  return f;
-}
-
+// Return the host advice, or -1 when the guest gives an advice which does not exist.
 int32_t conv_wasi_advice_2_host_advice(__wasi_advice_t wasi_advice)
 {
   switch (wasi_advice)
@@ -272,8 +325,7 @@ int32_t conv_wasi_advice_2_host_advice(__wasi_advice_t wasi_advice)
   case __WASI_ADVICE_NOREUSE:
     return POSIX_FADV_NOREUSE;
   default:
-    printf("unhandled advice %d\n", wasi_advice);
-    exit(1);
+    return -1;
   }
 }
 
@@ -349,13 +401,19 @@ __wasi_errno_t conv_host_errno_2_wasi_errno(int error)
     return __WASI_EDOM;
   case ERANGE: // 34
     return __WASI_ERANGE;
+  case ENAMETOOLONG: // 36
+    return __WASI_ENAMETOOLONG;
   case ENOTEMPTY: // 39
     return __WASI_ENOTEMPTY;
+  case ELOOP: // 40
+    return __WASI_ELOOP;
   case ENOTSUP: // 95
     return __WASI_ENOTSUP;
+  case EDQUOT: // 122
+    return __WASI_EDQUOT;
   default:
-    printf("unhandled posix errno=%d %s\n", errno, strerror(errno));
-    exit(1);
+    // An error which WASI has no code for is still an error of the host, and the guest can handle it.
+    return __WASI_EIO;
   }
 }
 
@@ -437,6 +495,190 @@ wavm_ret_int32_t pack_errno(void *dummy, int32_t value)
   return ret;
 }
 
+// Check that fd is open for the module, with all the given rights. The caller holds fd_table_lock.
+__wasi_errno_t check_rights_locked(int32_t fd, __wasi_rights_t rights)
+{
+  if (fd < 0 || fd >= FD_RIGHTS_CNT || !fdrights[fd].open)
+  {
+    return __WASI_EBADF;
+  }
+  if ((fdrights[fd].base & rights) != rights)
+  {
+    return __WASI_ENOTCAPABLE;
+  }
+  return 0;
+}
+
+__wasi_errno_t check_rights(int32_t fd, __wasi_rights_t rights)
+{
+  pthread_mutex_lock(&fd_table_lock);
+  __wasi_errno_t err = check_rights_locked(fd, rights);
+  pthread_mutex_unlock(&fd_table_lock);
+  return err;
+}
+
+#ifdef O_PATH
+#define RESOLVE_OPEN_FLAGS (O_PATH | O_DIRECTORY | O_NOFOLLOW | O_CLOEXEC)
+#else
+#define RESOLVE_OPEN_FLAGS (O_RDONLY | O_DIRECTORY | O_NOFOLLOW | O_CLOEXEC)
+#endif
+#define RESOLVE_MAX_DEPTH 128
+#define RESOLVE_MAX_SYMLINKS 32
+
+// Resolve a guest path relative to dir_fd, one component at a time, so that it never leaves dir_fd: absolute paths,
+// ".." above dir_fd and symbolic links to the outside are refused with ENOTCAPABLE. Symbolic links are followed,
+// except the last component when follow is 0. On success, parent is set to a directory the caller closes, and name to
+// the last component, to be used with the *at functions without following symbolic links.
+__wasi_errno_t resolve_path(int32_t dir_fd, const char *path, int follow, int *parent, char name[NAME_MAX + 1])
+{
+  if (path[0] == '\0')
+  {
+    return __WASI_ENOENT;
+  }
+  if (path[0] == '/')
+  {
+    return __WASI_ENOTCAPABLE;
+  }
+  char rest[MAX_PATH_LENGTH];
+  if (strlen(path) >= MAX_PATH_LENGTH)
+  {
+    return __WASI_ENAMETOOLONG;
+  }
+  strcpy(rest, path);
+  int stack[RESOLVE_MAX_DEPTH];
+  int32_t depth = 0;
+  int32_t symlinks = 0;
+  __wasi_errno_t err = 0;
+  char *p = rest;
+  while (1)
+  {
+    int top = depth == 0 ? dir_fd : stack[depth - 1];
+    while (*p == '/')
+    {
+      p++;
+    }
+    size_t n = strcspn(p, "/");
+    char *next = p + n;
+    int trailing_slash = *next == '/';
+    int last = next[strspn(next, "/")] == '\0';
+    if (n > NAME_MAX)
+    {
+      err = __WASI_ENAMETOOLONG;
+      break;
+    }
+    memcpy(name, p, n);
+    name[n] = '\0';
+    if (!last && strcmp(name, ".") == 0)
+    {
+      p = next;
+      continue;
+    }
+    if (strcmp(name, "..") == 0)
+    {
+      if (depth == 0)
+      {
+        err = __WASI_ENOTCAPABLE;
+        break;
+      }
+      close(stack[--depth]);
+      if (last)
+      {
+        strcpy(name, ".");
+        break;
+      }
+      p = next;
+      continue;
+    }
+    if (last && !follow && !trailing_slash)
+    {
+      break;
+    }
+    struct stat st;
+    if (fstatat(top, name, &st, AT_SYMLINK_NOFOLLOW) != 0)
+    {
+      // The last component may not exist yet, when it is being created.
+      if (!last || errno != ENOENT)
+      {
+        err = conv_host_errno_2_wasi_errno(errno);
+      }
+      break;
+    }
+    if (S_ISLNK(st.st_mode))
+    {
+      // Replace the link by its target in the rest of the path.
+      char target[MAX_PATH_LENGTH];
+      ssize_t target_len = readlinkat(top, name, target, sizeof(target));
+      if (target_len < 0)
+      {
+        err = conv_host_errno_2_wasi_errno(errno);
+        break;
+      }
+      if (++symlinks > RESOLVE_MAX_SYMLINKS)
+      {
+        err = __WASI_ELOOP;
+        break;
+      }
+      if (target_len == 0)
+      {
+        err = __WASI_ENOENT;
+        break;
+      }
+      if (target[0] == '/')
+      {
+        err = __WASI_ENOTCAPABLE;
+        break;
+      }
+      size_t next_len = strlen(next);
+      if (target_len + next_len >= MAX_PATH_LENGTH)
+      {
+        err = __WASI_ENAMETOOLONG;
+        break;
+      }
+      memcpy(target + target_len, next, next_len + 1);
+      strcpy(rest, target);
+      p = rest;
+      continue;
+    }
+    if (last)
+    {
+      break;
+    }
+    if (depth == RESOLVE_MAX_DEPTH)
+    {
+      err = __WASI_ENAMETOOLONG;
+      break;
+    }
+    int fd = openat(top, name, RESOLVE_OPEN_FLAGS);
+    if (fd < 0)
+    {
+      err = conv_host_errno_2_wasi_errno(errno);
+      break;
+    }
+    stack[depth++] = fd;
+    p = next;
+  }
+  if (err == 0)
+  {
+    if (depth == 0)
+    {
+      *parent = fcntl(dir_fd, F_DUPFD_CLOEXEC, 0);
+      if (*parent < 0)
+      {
+        err = conv_host_errno_2_wasi_errno(errno);
+      }
+    }
+    else
+    {
+      *parent = stack[--depth];
+    }
+  }
+  while (depth > 0)
+  {
+    close(stack[--depth]);
+  }
+  return err;
+}
+
 wavm_ret_int32_t wavm_wasi_args_sizes_get(void *dummy, int32_t argc_address, int32_t arg_buf_size_address)
 {
   (void)dummy;
@@ -448,8 +690,14 @@ wavm_ret_int32_t wavm_wasi_args_sizes_get(void *dummy, int32_t argc_address, int
   {
     num_arg_buffer_bytes = num_arg_buffer_bytes + strlen(g_argv[i]) + 1;
   }
-  *((uint32_t *)&memoryOffset0.base[argc_address]) = g_argc;
-  *((uint32_t *)&memoryOffset0.base[arg_buf_size_address]) = num_arg_buffer_bytes;
+  uint32_t *argc = wasi_memory(dummy, argc_address, sizeof(uint32_t));
+  uint32_t *arg_buf_size = wasi_memory(dummy, arg_buf_size_address, sizeof(uint32_t));
+  if (argc == NULL || arg_buf_size == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  *argc = g_argc;
+  *arg_buf_size = num_arg_buffer_bytes;
   return pack_errno(dummy, 0);
 }
 
@@ -459,13 +707,23 @@ wavm_ret_int32_t wavm_wasi_args_get(void *dummy, int32_t argv_address, int32_t a
 #ifdef DEBUG
   printf("wavm_wasi_args_get\n");
 #endif
-  int32_t next_arg_buf_address = arg_buf_address;
+  uint32_t *argv = wasi_memory(dummy, argv_address, (uint64_t)g_argc * sizeof(uint32_t));
+  if (argv == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  uint32_t next_arg_buf_address = arg_buf_address;
   for (int32_t i = 0; i < g_argc; ++i)
   {
     char *arg = g_argv[i];
-    int32_t num_arg_bytes = strlen(arg) + 1;
-    memcpy(&memoryOffset0.base[next_arg_buf_address], arg, num_arg_bytes);
-    *((uint32_t *)&memoryOffset0.base[argv_address + i * 4]) = next_arg_buf_address;
+    uint32_t num_arg_bytes = strlen(arg) + 1;
+    char *arg_buf = wasi_memory(dummy, next_arg_buf_address, num_arg_bytes);
+    if (arg_buf == NULL)
+    {
+      return pack_errno(dummy, __WASI_EFAULT);
+    }
+    memcpy(arg_buf, arg, num_arg_bytes);
+    argv[i] = next_arg_buf_address;
     next_arg_buf_address += num_arg_bytes;
   }
   return pack_errno(dummy, 0);
@@ -487,8 +745,14 @@ wavm_ret_int32_t wavm_wasi_environ_sizes_get(void *dummy, int32_t env_count_addr
     envc++;
     num_env_buffer_bytes = num_env_buffer_bytes + strlen(*ep) + 1;
   }
-  *((uint32_t *)&memoryOffset0.base[env_count_address]) = envc;
-  *((uint32_t *)&memoryOffset0.base[env_buf_size_address]) = num_env_buffer_bytes;
+  uint32_t *env_count = wasi_memory(dummy, env_count_address, sizeof(uint32_t));
+  uint32_t *env_buf_size = wasi_memory(dummy, env_buf_size_address, sizeof(uint32_t));
+  if (env_count == NULL || env_buf_size == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  *env_count = envc;
+  *env_buf_size = num_env_buffer_bytes;
   return pack_errno(dummy, 0);
 }
 
@@ -498,16 +762,22 @@ wavm_ret_int32_t wavm_wasi_environ_get(void *dummy, int32_t env_address, int32_t
 #ifdef DEBUG
   printf("wavm_wasi_environ_get\n");
 #endif
-  int32_t next_env_buf_address = env_buf_address;
-  int32_t i = 0;
+  uint32_t next_env_buf_address = env_buf_address;
+  uint32_t next_env_address = env_address;
   for (char **ep = environ; *ep != NULL; ep++)
   {
     char *env = *ep;
-    int32_t num_env_bytes = strlen(env) + 1;
-    memcpy(&memoryOffset0.base[next_env_buf_address], env, num_env_bytes);
-    *((uint32_t *)&memoryOffset0.base[env_address + i * 4]) = next_env_buf_address;
+    uint32_t num_env_bytes = strlen(env) + 1;
+    char *env_buf = wasi_memory(dummy, next_env_buf_address, num_env_bytes);
+    uint32_t *env_ptr = wasi_memory(dummy, next_env_address, sizeof(uint32_t));
+    if (env_buf == NULL || env_ptr == NULL)
+    {
+      return pack_errno(dummy, __WASI_EFAULT);
+    }
+    memcpy(env_buf, env, num_env_bytes);
+    *env_ptr = next_env_buf_address;
     next_env_buf_address += num_env_bytes;
-    ++i;
+    next_env_address += sizeof(uint32_t);
   }
   return pack_errno(dummy, 0);
 }
@@ -518,12 +788,17 @@ wavm_ret_int32_t wavm_wasi_clock_res_get(void *dummy, int32_t clock_id, int32_t
 #ifdef DEBUG
   printf("wavm_wasi_clock_res_get\n");
 #endif
+  uint64_t *resolution = wasi_memory(dummy, resolution_address, sizeof(uint64_t));
+  if (resolution == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   struct timespec tp;
   if (clock_getres(clock_id, &tp) != 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint64_t *)&memoryOffset0.base[resolution_address]) = conv_host_timespec_2_wasi_timestamp(tp);
+  *resolution = conv_host_timespec_2_wasi_timestamp(tp);
   return pack_errno(dummy, 0);
 }
 
@@ -534,12 +809,17 @@ wavm_ret_int32_t wavm_wasi_clock_time_get(void *dummy, int32_t clock_id, int64_t
 #ifdef DEBUG
   printf("wavm_wasi_clock_time_get\n");
 #endif
+  uint64_t *time = wasi_memory(dummy, time_address, sizeof(uint64_t));
+  if (time == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   struct timespec tp;
   if (clock_gettime(clock_id, &tp) != 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint64_t *)&memoryOffset0.base[time_address]) = conv_host_timespec_2_wasi_timestamp(tp);
+  *time = conv_host_timespec_2_wasi_timestamp(tp);
   return pack_errno(dummy, 0);
 }
 
@@ -550,9 +830,21 @@ wavm_ret_int32_t wavm_wasi_fd_advise(void *dummy, int32_t fd, int64_t offset, in
 #ifdef DEBUG
   printf("wavm_wasi_fd_advise\n");
 #endif
-  if (posix_fadvise(fd, offset, num_bytes, conv_wasi_advice_2_host_advice(advice)) != 0)
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_ADVISE);
+  if (err != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, err);
+  }
+  int32_t host_advice = conv_wasi_advice_2_host_advice(advice);
+  if (host_advice < 0)
+  {
+    return pack_errno(dummy, __WASI_EINVAL);
+  }
+  // posix_fadvise returns the error instead of setting errno.
+  int e = posix_fadvise(fd, offset, num_bytes, host_advice);
+  if (e != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   return pack_errno(dummy, 0);
 }
@@ -563,9 +855,16 @@ wavm_ret_int32_t wavm_wasi_fd_allocate(void *dummy, int32_t fd, int64_t offset,
 #ifdef DEBUG
   printf("wavm_wasi_fd_allocate\n");
 #endif
-  if (posix_fallocate(fd, offset, num_bytes) != 0)
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_ALLOCATE);
+  if (err != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, err);
+  }
+  // posix_fallocate returns the error instead of setting errno.
+  int e = posix_fallocate(fd, offset, num_bytes);
+  if (e != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   return pack_errno(dummy, 0);
 }
@@ -576,9 +875,25 @@ wavm_ret_int32_t wavm_wasi_fd_close(void *dummy, int32_t fd)
 #ifdef DEBUG
   printf("wavm_wasi_fd_close fd=%d\n", fd);
 #endif
-  if (close(fd) != 0)
-  {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+  pthread_mutex_lock(&fd_table_lock);
+  __wasi_errno_t err = check_rights_locked(fd, 0);
+  if (err != 0)
+  {
+    pthread_mutex_unlock(&fd_table_lock);
+    return pack_errno(dummy, err);
+  }
+  // The file descriptor is released even when close fails. Closing it under the lock keeps another thread from
+  // opening a file at the same number before its rights are cleared.
+  int r = close(fd);
+  int e = errno;
+  fdrights[fd].base = 0;
+  fdrights[fd].inheriting = 0;
+  fdrights[fd].open = 0;
+  clear_preopen(fd);
+  pthread_mutex_unlock(&fd_table_lock);
+  if (r != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   return pack_errno(dummy, 0);
 }
@@ -589,6 +904,11 @@ wavm_ret_int32_t wavm_wasi_fd_datasync(void *dummy, int32_t fd)
 #ifdef DEBUG
   printf("wavm_wasi_fd_datasync fd=%d\n", fd);
 #endif
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_DATASYNC);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   if (fdatasync(fd) != 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
@@ -602,6 +922,16 @@ wavm_ret_int32_t wavm_wasi_fd_fdstat_get(void *dummy, int32_t fd, int32_t fdstat
 #ifdef DEBUG
   printf("wavm_wasi_fd_fdstat_get fd=%d\n", fd);
 #endif
+  __wasi_errno_t err = check_rights(fd, 0);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  __wasi_fdstat_t *fdstat = wasi_memory(dummy, fdstat_address, sizeof(__wasi_fdstat_t));
+  if (fdstat == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   struct stat host_stat;
   struct __wasi_fdstat_t wasi_fdstat;
   int32_t fl = fcntl(fd, F_GETFL);
@@ -615,9 +945,11 @@ wavm_ret_int32_t wavm_wasi_fd_fdstat_get(void *dummy, int32_t fd, int32_t fdstat
   }
   wasi_fdstat.fs_filetype = conv_host_mode_2_wasi_filetype(host_stat.st_mode);
   wasi_fdstat.fs_flags = conv_host_fdflag_2_wasi_fdflag(fl);
+  pthread_mutex_lock(&fd_table_lock);
   wasi_fdstat.fs_rights_base = fdrights[fd].base;
   wasi_fdstat.fs_rights_inheriting = fdrights[fd].inheriting;
-  *((__wasi_fdstat_t *)&memoryOffset0.base[fdstat_address]) = wasi_fdstat;
+  pthread_mutex_unlock(&fd_table_lock);
+  *fdstat = wasi_fdstat;
   return pack_errno(dummy, 0);
 }
 
@@ -627,6 +959,11 @@ wavm_ret_int32_t wavm_wasi_fd_fdstat_set_flags(void *dummy, int32_t fd, int32_t
 #ifdef DEBUG
   printf("wavm_wasi_fd_fdstat_set_flags fd=%d flags=%d\n", fd, flags);
 #endif
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_FDSTAT_SET_FLAGS);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   int32_t flag = conv_wasi_fdflag_2_host_fdflag(flags);
   if (fcntl(fd, F_SETFL, flag) != 0)
   {
@@ -635,16 +972,27 @@ wavm_ret_int32_t wavm_wasi_fd_fdstat_set_flags(void *dummy, int32_t fd, int32_t
   return pack_errno(dummy, 0);
 }
 
-wavm_ret_int32_t wavm_wasi_fd_fdstat_set_rights(void *dummy, int32_t fd, int32_t rights,
-                                                         int32_t inheriting_rights)
+wavm_ret_int32_t wavm_wasi_fd_fdstat_set_rights(void *dummy, int32_t fd, int64_t rights,
+                                                         int64_t inheriting_rights)
 {
   (void)dummy;
 #ifdef DEBUG
-  printf("wavm_wasi_fd_fdstat_set_rights fd=%d rights=%d inheriting_rights=%d\n", fd, rights, inheriting_rights);
+  printf("wavm_wasi_fd_fdstat_set_rights fd=%d rights=%ld inheriting_rights=%ld\n", fd, rights, inheriting_rights);
 #endif
-  fdrights[fd].base = rights;
-  fdrights[fd].inheriting = inheriting_rights;
-  return pack_errno(dummy, 0);
+  pthread_mutex_lock(&fd_table_lock);
+  __wasi_errno_t err = check_rights_locked(fd, 0);
+  // Rights can only be dropped.
+  if (err == 0 && ((rights & ~fdrights[fd].base) != 0 || (inheriting_rights & ~fdrights[fd].inheriting) != 0))
+  {
+    err = __WASI_ENOTCAPABLE;
+  }
+  if (err == 0)
+  {
+    fdrights[fd].base = rights;
+    fdrights[fd].inheriting = inheriting_rights;
+  }
+  pthread_mutex_unlock(&fd_table_lock);
+  return pack_errno(dummy, err);
 }
 
 wavm_ret_int32_t wavm_wasi_fd_filestat_get(void *dummy, int32_t fd, int32_t filestat_address)
@@ -653,6 +1001,16 @@ wavm_ret_int32_t wavm_wasi_fd_filestat_get(void *dummy, int32_t fd, int32_t file
 #ifdef DEBUG
   printf("wavm_wasi_fd_filestat_get fd=%d\n", fd);
 #endif
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_FILESTAT_GET);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  __wasi_filestat_t *filestat = wasi_memory(dummy, filestat_address, sizeof(__wasi_filestat_t));
+  if (filestat == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   struct stat host_filestat;
   if (fstat(fd, &host_filestat))
   {
@@ -667,7 +1025,7 @@ wavm_ret_int32_t wavm_wasi_fd_filestat_get(void *dummy, int32_t fd, int32_t file
   wasi_filestat.st_atim = conv_host_timespec_2_wasi_timestamp(host_filestat.st_atim);
   wasi_filestat.st_mtim = conv_host_timespec_2_wasi_timestamp(host_filestat.st_mtim);
   wasi_filestat.st_ctim = conv_host_timespec_2_wasi_timestamp(host_filestat.st_ctim);
-  *((__wasi_filestat_t *)&memoryOffset0.base[filestat_address]) = wasi_filestat;
+  *filestat = wasi_filestat;
   return pack_errno(dummy, 0);
 }
 
@@ -677,6 +1035,11 @@ wavm_ret_int32_t wavm_wasi_fd_filestat_set_size(void *dummy, int32_t fd, int64_t
 #ifdef DEBUG
   printf("wavm_wasi_fd_filestat_set_size fd=%d num_bytes=%ld\n", fd, num_bytes);
 #endif
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_FILESTAT_SET_SIZE);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   if (ftruncate(fd, (off_t)num_bytes) != 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
@@ -692,6 +1055,11 @@ wavm_ret_int32_t wavm_wasi_fd_filestat_set_times(void *dummy, int32_t fd, int64_
   printf("wavm_wasi_fd_filestat_set_times fd=%d last_access_time64=%ld last_write_time64=%ld flags=%d\n",
          fd, last_access_time64, last_write_time64, flags);
 #endif
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_FILESTAT_SET_TIMES);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   struct timespec tp;
   if (clock_gettime(CLOCK_REALTIME, &tp) != 0)
   {
@@ -739,13 +1107,28 @@ wavm_ret_int32_t wavm_wasi_fd_pread(void *dummy, int32_t fd, int32_t iovs_addres
   printf("wavm_wasi_fd_pread fd=%d iovs_address=%d num_iovs=%d num_bytes_read_address=%d\n",
          fd, iovs_address, num_iovs, num_bytes_read_address);
 #endif
-  struct iovec *iovs = copy_iov_to_host(iovs_address, num_iovs);
-  size_t size = preadv(fd, iovs, num_iovs, offset);
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_READ | __WASI_RIGHT_FD_SEEK);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  struct iovec *iovs;
+  err = copy_iov_to_host(dummy, iovs_address, num_iovs, &iovs);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint32_t *num_bytes_read = wasi_memory(dummy, num_bytes_read_address, sizeof(uint32_t));
+  if (num_bytes_read == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  ssize_t size = preadv(fd, iovs, num_iovs, offset);
   if (size < 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint32_t *)&memoryOffset0.base[num_bytes_read_address]) = size;
+  *num_bytes_read = size;
   return pack_errno(dummy, 0);
 }
 
@@ -755,12 +1138,25 @@ wavm_ret_int32_t wavm_wasi_fd_prestat_get(void *dummy, int32_t fd, int32_t prest
 #ifdef DEBUG
   printf("wavm_wasi_fd_prestat_get fd=%d\n", fd);
 #endif
-  if (fd < 3 || fd >= PREOPEN_CNT)
+  __wasi_errno_t err = check_rights(fd, 0);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint32_t *prestat = wasi_memory(dummy, prestat_address, 2 * sizeof(uint32_t));
+  if (prestat == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  pthread_mutex_lock(&fd_table_lock);
+  if (!is_preopen(fd))
   {
+    pthread_mutex_unlock(&fd_table_lock);
     return pack_errno(dummy, __WASI_EBADF);
   }
-  *(uint32_t *)&memoryOffset0.base[prestat_address] = __WASI_PREOPENTYPE_DIR;
-  *(uint32_t *)&memoryOffset0.base[prestat_address + 4] = preopen[fd].path_len;
+  prestat[0] = __WASI_PREOPENTYPE_DIR;
+  prestat[1] = preopen[fd].path_len;
+  pthread_mutex_unlock(&fd_table_lock);
   return pack_errno(dummy, 0);
 }
 
@@ -771,12 +1167,26 @@ wavm_ret_int32_t wavm_wasi_fd_prestat_dir_name(void *dummy, int32_t fd, int32_t
 #ifdef DEBUG
   printf("wavm_wasi_fd_prestat_dir_name fd=%d\n", fd);
 #endif
-  if (fd < 3 || fd >= PREOPEN_CNT)
+  __wasi_errno_t err = check_rights(fd, 0);
+  if (err != 0)
   {
+    return pack_errno(dummy, err);
+  }
+  pthread_mutex_lock(&fd_table_lock);
+  if (!is_preopen(fd))
+  {
+    pthread_mutex_unlock(&fd_table_lock);
     return pack_errno(dummy, __WASI_EBADF);
   }
-  int32_t l = preopen[fd].path_len <= buffer_length ? preopen[fd].path_len : buffer_length;
-  memcpy((char *)&memoryOffset0.base[buffer_address], preopen[fd].path, l);
+  uint32_t l = (uint32_t)preopen[fd].path_len <= (uint32_t)buffer_length ? preopen[fd].path_len : buffer_length;
+  char *buffer = wasi_memory(dummy, buffer_address, l);
+  if (buffer == NULL)
+  {
+    pthread_mutex_unlock(&fd_table_lock);
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  memcpy(buffer, preopen[fd].path, l);
+  pthread_mutex_unlock(&fd_table_lock);
   return pack_errno(dummy, 0);
 }
 
@@ -787,13 +1197,28 @@ wavm_ret_int32_t wavm_wasi_fd_pwrite(void *dummy, int32_t fd, int32_t iovs_addre
 #ifdef DEBUG
   printf("wavm_wasi_fd_pwrite fd=%d num_iovs=%d\n", fd, num_iovs);
 #endif
-  struct iovec *iovs = copy_iov_to_host(iovs_address, num_iovs);
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_WRITE | __WASI_RIGHT_FD_SEEK);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  struct iovec *iovs;
+  err = copy_iov_to_host(dummy, iovs_address, num_iovs, &iovs);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint32_t *num_bytes_written = wasi_memory(dummy, num_bytes_written_address, sizeof(uint32_t));
+  if (num_bytes_written == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   ssize_t size = pwritev(fd, iovs, num_iovs, offset);
   if (size < 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint32_t *)&memoryOffset0.base[num_bytes_written_address]) = size;
+  *num_bytes_written = size;
   return pack_errno(dummy, 0);
 }
 
@@ -805,13 +1230,28 @@ wavm_ret_int32_t wavm_wasi_fd_read(void *dummy, int32_t fd, int32_t iovs_address
   printf("wavm_wasi_fd_read fd=%d iovs_address=%d num_iovs=%d num_bytes_read_address=%d\n",
          fd, iovs_address, num_iovs, num_bytes_read_address);
 #endif
-  struct iovec *iovs = copy_iov_to_host(iovs_address, num_iovs);
-  size_t size = readv(fd, iovs, num_iovs);
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_READ);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  struct iovec *iovs;
+  err = copy_iov_to_host(dummy, iovs_address, num_iovs, &iovs);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint32_t *num_bytes_read = wasi_memory(dummy, num_bytes_read_address, sizeof(uint32_t));
+  if (num_bytes_read == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  ssize_t size = readv(fd, iovs, num_iovs);
   if (size < 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint32_t *)&memoryOffset0.base[num_bytes_read_address]) = size;
+  *num_bytes_read = size;
   return pack_errno(dummy, 0);
 }
 
@@ -824,11 +1264,31 @@ wavm_ret_int32_t wavm_wasi_fd_readdir(void *dummy, int32_t dir_fd, int32_t buffe
   printf("wavm_wasi_fd_readdir dir_fd=%d buffer_address=%d num_buffer_bytes=%d first_cookie=%ld\n",
          dir_fd, buffer_address, num_buffer_bytes, first_cookie);
 #endif
-  DIR *dir = fdopendir(dir_fd);
-  if (!dir)
+  __wasi_errno_t err = check_rights(dir_fd, __WASI_RIGHT_FD_READDIR);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint8_t *buffer = wasi_memory(dummy, buffer_address, (uint32_t)num_buffer_bytes);
+  uint32_t *out_num_buffer_bytes_used = wasi_memory(dummy, out_num_buffer_bytes_used_address, sizeof(uint32_t));
+  if (buffer == NULL || out_num_buffer_bytes_used == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  // The stream owns the descriptor it is opened on and closes it with closedir, it gets a duplicate of dir_fd, which
+  // stays open for the guest.
+  int stream_fd = dup(dir_fd);
+  if (stream_fd < 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
+  DIR *dir = fdopendir(stream_fd);
+  if (!dir)
+  {
+    int e = errno;
+    close(stream_fd);
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
+  }
   seekdir(dir, first_cookie);
 
   struct dirent *dirp;
@@ -843,7 +1303,7 @@ wavm_ret_int32_t wavm_wasi_fd_readdir(void *dummy, int32_t dir_fd, int32_t buffe
     }
 
     uint32_t cap_using = sizeof(wasi_dirent) + strlen((*dirp).d_name);
-    if (num_buffer_bytes_used + cap_using > num_buffer_bytes)
+    if (num_buffer_bytes_used + cap_using > (uint32_t)num_buffer_bytes)
     {
       break;
     }
@@ -852,12 +1312,13 @@ wavm_ret_int32_t wavm_wasi_fd_readdir(void *dummy, int32_t dir_fd, int32_t buffe
     wasi_dirent.d_namlen = strlen((*dirp).d_name);
     wasi_dirent.d_type = as_wasi_file_type((*dirp).d_type);
 
-    memcpy(&memoryOffset0.base[buffer_address + num_buffer_bytes_used], &wasi_dirent, sizeof(wasi_dirent));
+    memcpy(&buffer[num_buffer_bytes_used], &wasi_dirent, sizeof(wasi_dirent));
     num_buffer_bytes_used += sizeof(wasi_dirent);
-    memcpy(&memoryOffset0.base[buffer_address + num_buffer_bytes_used], (*dirp).d_name, wasi_dirent.d_namlen);
+    memcpy(&buffer[num_buffer_bytes_used], (*dirp).d_name, wasi_dirent.d_namlen);
     num_buffer_bytes_used += wasi_dirent.d_namlen;
   }
-  *((uint32_t *)&memoryOffset0.base[out_num_buffer_bytes_used_address]) = num_buffer_bytes_used;
+  closedir(dir);
+  *out_num_buffer_bytes_used = num_buffer_bytes_used;
   return pack_errno(dummy, 0);
 }
 
@@ -867,14 +1328,38 @@ wavm_ret_int32_t wavm_wasi_fd_renumber(void *dummy, int32_t from_fd, int32_t to_
 #ifdef DEBUG
   printf("wavm_wasi_fd_renumber from_fd=%d to_fd=%d\n", from_fd, to_fd);
 #endif
-  if (close(to_fd) < 0)
+  pthread_mutex_lock(&fd_table_lock);
+  __wasi_errno_t err = check_rights_locked(from_fd, 0);
+  if (err == 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    err = check_rights_locked(to_fd, 0);
   }
-  if (fcntl(from_fd, F_DUPFD) < 0)
+  if (err != 0 || from_fd == to_fd)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    pthread_mutex_unlock(&fd_table_lock);
+    return pack_errno(dummy, err);
+  }
+  // to_fd is closed and becomes from_fd, with its rights. A preopen keeps its path if its new number can hold one.
+  if (dup2(from_fd, to_fd) < 0)
+  {
+    int e = errno;
+    pthread_mutex_unlock(&fd_table_lock);
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
+  }
+  close(from_fd);
+  fdrights[to_fd] = fdrights[from_fd];
+  fdrights[from_fd].base = 0;
+  fdrights[from_fd].inheriting = 0;
+  fdrights[from_fd].open = 0;
+  clear_preopen(to_fd);
+  if (is_preopen(from_fd) && to_fd >= 3 && to_fd < PREOPEN_MAX)
+  {
+    preopen[to_fd] = preopen[from_fd];
+    preopen[from_fd].path = NULL;
+    preopen[from_fd].path_len = 0;
   }
+  clear_preopen(from_fd);
+  pthread_mutex_unlock(&fd_table_lock);
   return pack_errno(dummy, 0);
 }
 
@@ -885,12 +1370,23 @@ wavm_ret_int32_t wavm_wasi_fd_seek(void *dummy, int32_t fd, int64_t offset, int3
 #ifdef DEBUG
   printf("wavm_wasi_fd_seek fd=%d offset=%ld whence=%d\n", fd, offset, whence);
 #endif
+  __wasi_errno_t err = check_rights(fd, offset == 0 && whence == __WASI_WHENCE_CUR ? __WASI_RIGHT_FD_TELL
+                                                                                   : __WASI_RIGHT_FD_SEEK);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint64_t *new_offset = wasi_memory(dummy, new_offset_address, sizeof(uint64_t));
+  if (new_offset == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   int64_t off = lseek(fd, (off_t)offset, whence);
   if (off < 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint64_t *)&memoryOffset0.base[new_offset_address]) = off;
+  *new_offset = off;
   return pack_errno(dummy, 0);
 }
 
@@ -900,6 +1396,11 @@ wavm_ret_int32_t wavm_wasi_fd_sync(void *dummy, int32_t fd)
 #ifdef DEBUG
   printf("wavm_wasi_fd_sync fd=%d\n", fd);
 #endif
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_SYNC);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   if (fsync(fd) != 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
@@ -913,12 +1414,22 @@ wavm_ret_int32_t wavm_wasi_fd_tell(void *dummy, int32_t fd, int32_t offset_addre
 #ifdef DEBUG
   printf("wavm_wasi_fd_tell fd=%d\n", fd);
 #endif
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_TELL);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint64_t *offset = wasi_memory(dummy, offset_address, sizeof(uint64_t));
+  if (offset == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   int64_t off = lseek(fd, 0, SEEK_CUR);
   if (off < 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint64_t *)&memoryOffset0.base[offset_address]) = off;
+  *offset = off;
   return pack_errno(dummy, 0);
 }
 
@@ -929,13 +1440,28 @@ wavm_ret_int32_t wavm_wasi_fd_write(void *dummy, int32_t fd, int32_t iovs_addres
 #ifdef DEBUG
   printf("wavm_wasi_fd_write fd=%d num_iovs=%d\n", fd, num_iovs);
 #endif
-  struct iovec *iovs = copy_iov_to_host(iovs_address, num_iovs);
+  __wasi_errno_t err = check_rights(fd, __WASI_RIGHT_FD_WRITE);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  struct iovec *iovs;
+  err = copy_iov_to_host(dummy, iovs_address, num_iovs, &iovs);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  uint32_t *num_bytes_written = wasi_memory(dummy, num_bytes_written_address, sizeof(uint32_t));
+  if (num_bytes_written == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
   ssize_t size = writev(fd, iovs, num_iovs);
   if (size < 0)
   {
     return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
   }
-  *((uint32_t *)&memoryOffset0.base[num_bytes_written_address]) = size;
+  *num_bytes_written = size;
   return pack_errno(dummy, 0);
 }
 
@@ -943,14 +1469,32 @@ wavm_ret_int32_t wavm_wasi_path_create_directory(void *dummy, int32_t dir_fd, in
 {
   (void)dummy;
   char path[MAX_PATH_LENGTH];
-  memcpy(path, &memoryOffset0.base[path_address], num_path_bytes);
-  path[num_path_bytes] = '\0';
+  __wasi_errno_t err = wasi_memory_path(dummy, path_address, num_path_bytes, path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
   printf("wavm_wasi_path_create_directory dir_fd=%d path_name=%s\n", dir_fd, path);
 #endif
-  if (mkdirat(dir_fd, path, 0666) != 0)
+  err = check_rights(dir_fd, __WASI_RIGHT_PATH_CREATE_DIRECTORY);
+  if (err != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, err);
+  }
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(dir_fd, path, 0, &parent, name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int r = mkdirat(parent, name, 0777);
+  int e = errno;
+  close(parent);
+  if (r != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   return pack_errno(dummy, 0);
 }
@@ -961,15 +1505,38 @@ wavm_ret_int32_t wavm_wasi_path_filestat_get(void *dummy, int32_t dir_fd, int32_
 {
   (void)dummy;
   char path[MAX_PATH_LENGTH];
-  memcpy(path, &memoryOffset0.base[path_address], num_path_bytes);
-  path[num_path_bytes] = '\0';
+  __wasi_errno_t err = wasi_memory_path(dummy, path_address, num_path_bytes, path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
   printf("wavm_wasi_path_filestat_get dir_fd=%d path_name=%s lookup_flags=%d\n", dir_fd, path, lookup_flags);
 #endif
+  err = check_rights(dir_fd, __WASI_RIGHT_PATH_FILESTAT_GET);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  __wasi_filestat_t *filestat = wasi_memory(dummy, filestat_address, sizeof(__wasi_filestat_t));
+  if (filestat == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(dir_fd, path, lookup_flags & __WASI_LOOKUP_SYMLINK_FOLLOW, &parent, name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   struct stat host_filestat;
-  if (fstatat(dir_fd, path, &host_filestat, conv_wasi_lookupflags_2_host_lookupflags(lookup_flags)) != 0)
+  int r = fstatat(parent, name, &host_filestat, AT_SYMLINK_NOFOLLOW);
+  int e = errno;
+  close(parent);
+  if (r != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   __wasi_filestat_t wasi_filestat;
   wasi_filestat.st_dev = (__wasi_device_t)host_filestat.st_dev;
@@ -980,7 +1547,7 @@ wavm_ret_int32_t wavm_wasi_path_filestat_get(void *dummy, int32_t dir_fd, int32_
   wasi_filestat.st_atim = conv_host_timespec_2_wasi_timestamp(host_filestat.st_atim);
   wasi_filestat.st_mtim = conv_host_timespec_2_wasi_timestamp(host_filestat.st_mtim);
   wasi_filestat.st_ctim = conv_host_timespec_2_wasi_timestamp(host_filestat.st_ctim);
-  *((__wasi_filestat_t *)&memoryOffset0.base[filestat_address]) = wasi_filestat;
+  *filestat = wasi_filestat;
   return pack_errno(dummy, 0);
 }
 
@@ -991,11 +1558,19 @@ wavm_ret_int32_t wavm_wasi_path_filestat_set_times(void *dummy, int32_t dir_fd,
 {
   (void)dummy;
   char path[MAX_PATH_LENGTH];
-  memcpy(path, &memoryOffset0.base[path_address], num_path_bytes);
-  path[num_path_bytes] = '\0';
+  __wasi_errno_t err = wasi_memory_path(dummy, path_address, num_path_bytes, path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
   printf("wavm_wasi_path_filestat_set_times path=%s\n", path);
 #endif
+  err = check_rights(dir_fd, __WASI_RIGHT_PATH_FILESTAT_SET_TIMES);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   struct timespec tp;
   if (clock_gettime(CLOCK_REALTIME, &tp) != 0)
   {
@@ -1027,21 +1602,21 @@ wavm_ret_int32_t wavm_wasi_path_filestat_set_times(void *dummy, int32_t dir_fd,
     timespecs[1].tv_nsec = UTIME_OMIT;
   }
 
-  int host_fd = openat(dir_fd, path, flags, 0644);
-  if (host_fd < 0)
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(dir_fd, path, lookup_flags & __WASI_LOOKUP_SYMLINK_FOLLOW, &parent, name);
+  if (err != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
-  }
-  if (futimens(host_fd, timespecs) != 0)
-  {
-    close(host_fd);
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, err);
   }
-  else
+  int r = utimensat(parent, name, timespecs, AT_SYMLINK_NOFOLLOW);
+  int e = errno;
+  close(parent);
+  if (r != 0)
   {
-    close(host_fd);
-    return pack_errno(dummy, 0);
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
+  return pack_errno(dummy, 0);
 }
 
 wavm_ret_int32_t wavm_wasi_path_link(void *dummy, int32_t dir_fd, int32_t lookup_flags,
@@ -1050,17 +1625,52 @@ wavm_ret_int32_t wavm_wasi_path_link(void *dummy, int32_t dir_fd, int32_t lookup
 {
   (void)dummy;
   char old_path[MAX_PATH_LENGTH];
-  memcpy(old_path, &memoryOffset0.base[old_path_address], num_old_path_bytes);
-  old_path[num_old_path_bytes] = '\0';
+  __wasi_errno_t err = wasi_memory_path(dummy, old_path_address, num_old_path_bytes, old_path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
   char new_path[MAX_PATH_LENGTH];
-  memcpy(new_path, &memoryOffset0.base[new_path_address], num_new_path_bytes);
-  new_path[num_new_path_bytes] = '\0';
+  err = wasi_memory_path(dummy, new_path_address, num_new_path_bytes, new_path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
   printf("wavm_wasi_path_link old_path=%s new_path=%s\n", old_path, new_path);
 #endif
-  if (linkat(dir_fd, old_path, new_fd, new_path, conv_wasi_lookupflags_2_host_lookupflags(lookup_flags)) != 0)
+  err = check_rights(dir_fd, __WASI_RIGHT_PATH_LINK_SOURCE);
+  if (err != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, err);
+  }
+  err = check_rights(new_fd, __WASI_RIGHT_PATH_LINK_TARGET);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int old_parent;
+  char old_name[NAME_MAX + 1];
+  err = resolve_path(dir_fd, old_path, lookup_flags & __WASI_LOOKUP_SYMLINK_FOLLOW, &old_parent, old_name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int new_parent;
+  char new_name[NAME_MAX + 1];
+  err = resolve_path(new_fd, new_path, 0, &new_parent, new_name);
+  if (err != 0)
+  {
+    close(old_parent);
+    return pack_errno(dummy, err);
+  }
+  int r = linkat(old_parent, old_name, new_parent, new_name, 0);
+  int e = errno;
+  close(old_parent);
+  close(new_parent);
+  if (r != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   return pack_errno(dummy, 0);
 }
@@ -1071,34 +1681,79 @@ wavm_ret_int32_t wavm_wasi_path_open(void *dummy, int32_t dirfd, int32_t dirflag
 {
   (void)dummy;
   char path[MAX_PATH_LENGTH];
-  memcpy(path, &memoryOffset0.base[path_address], num_path_bytes);
-  path[num_path_bytes] = '\0';
+  __wasi_errno_t err = wasi_memory_path(dummy, path_address, num_path_bytes, path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
   printf("wavm_wasi_path_open path=%s dirflags=%d open_flags=%d requested_rights=%ld requested_inheriting_rights=%ld fd_flags=%d\n",
          path, dirflags, open_flags, requested_rights, requested_inheriting_rights, fd_flags);
 #endif
+  __wasi_rights_t needed_rights = __WASI_RIGHT_PATH_OPEN;
+  if (open_flags & __WASI_O_CREAT)
+  {
+    needed_rights |= __WASI_RIGHT_PATH_CREATE_FILE;
+  }
+  if (open_flags & __WASI_O_TRUNC)
+  {
+    needed_rights |= __WASI_RIGHT_PATH_FILESTAT_SET_SIZE;
+  }
+  pthread_mutex_lock(&fd_table_lock);
+  err = check_rights_locked(dirfd, needed_rights);
+  if (err != 0)
+  {
+    pthread_mutex_unlock(&fd_table_lock);
+    return pack_errno(dummy, err);
+  }
+  // The new file descriptor gets the requested rights the directory lets it inherit.
+  __wasi_rights_t rights = requested_rights & fdrights[dirfd].inheriting;
+  __wasi_rights_t inheriting_rights = requested_inheriting_rights & fdrights[dirfd].inheriting;
+  pthread_mutex_unlock(&fd_table_lock);
   int flags = conv_wasi_opflag_2_host_opflag(open_flags) | conv_wasi_fdflag_2_host_fdflag(fd_flags);
-  if ((requested_rights & __WASI_RIGHT_FD_READ) &&
-      (requested_rights & __WASI_RIGHT_FD_WRITE))
+  if ((rights & __WASI_RIGHT_FD_READ) &&
+      (rights & __WASI_RIGHT_FD_WRITE))
   {
     flags |= O_RDWR;
   }
-  else if ((requested_rights & __WASI_RIGHT_FD_WRITE))
+  else if ((rights & __WASI_RIGHT_FD_WRITE))
   {
     flags |= O_WRONLY;
   }
-  else if ((requested_rights & __WASI_RIGHT_FD_READ))
+  else if ((rights & __WASI_RIGHT_FD_READ))
   {
     flags |= O_RDONLY;
   }
-  int host_fd = openat(dirfd, path, flags, 0644);
+  uint32_t *opened_fd = wasi_memory(dummy, fd_address, sizeof(uint32_t));
+  if (opened_fd == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(dirfd, path, dirflags & __WASI_LOOKUP_SYMLINK_FOLLOW, &parent, name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int host_fd = openat(parent, name, flags | O_NOFOLLOW, 0644);
+  int e = errno;
+  close(parent);
   if (host_fd < 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
-  fdrights[host_fd].base = requested_rights;
-  fdrights[host_fd].inheriting = requested_inheriting_rights;
-  *((uint32_t *)&memoryOffset0.base[fd_address]) = host_fd;
+  if (host_fd >= FD_RIGHTS_CNT)
+  {
+    close(host_fd);
+    return pack_errno(dummy, __WASI_EMFILE);
+  }
+  pthread_mutex_lock(&fd_table_lock);
+  fdrights[host_fd].base = rights;
+  fdrights[host_fd].inheriting = inheriting_rights;
+  fdrights[host_fd].open = 1;
+  pthread_mutex_unlock(&fd_table_lock);
+  *opened_fd = host_fd;
   return pack_errno(dummy, 0);
 }
 
@@ -1107,24 +1762,75 @@ wavm_ret_int32_t wavm_wasi_path_readlink(void *dummy, int32_t fd, int32_t path_a
                                                   int32_t out_num_buffer_bytes_used_address)
 {
   (void)dummy;
+  char path[MAX_PATH_LENGTH];
+  __wasi_errno_t err = wasi_memory_path(dummy, path_address, num_path_bytes, path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
-  printf("wavm_wasi_path_readlink\n");
+  printf("wavm_wasi_path_readlink fd=%d path=%s\n", fd, path);
 #endif
-  return pack_errno(dummy, __WASI_EPERM);
+  err = check_rights(fd, __WASI_RIGHT_PATH_READLINK);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  char *buffer = wasi_memory(dummy, buffer_address, (uint32_t)num_buffer_bytes);
+  uint32_t *out_num_buffer_bytes_used = wasi_memory(dummy, out_num_buffer_bytes_used_address, sizeof(uint32_t));
+  if (buffer == NULL || out_num_buffer_bytes_used == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(fd, path, 0, &parent, name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  // The link is truncated to the buffer, without a terminating NUL.
+  ssize_t r = readlinkat(parent, name, buffer, (uint32_t)num_buffer_bytes);
+  int e = errno;
+  close(parent);
+  if (r < 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
+  }
+  *out_num_buffer_bytes_used = r;
+  return pack_errno(dummy, 0);
 }
 
 wavm_ret_int32_t wavm_wasi_path_remove_directory(void *dummy, int32_t dir_fd, int32_t path_address, int32_t num_path_bytes)
 {
   (void)dummy;
   char path[MAX_PATH_LENGTH];
-  memcpy(path, &memoryOffset0.base[path_address], num_path_bytes);
-  path[num_path_bytes] = '\0';
+  __wasi_errno_t err = wasi_memory_path(dummy, path_address, num_path_bytes, path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
   printf("wavm_wasi_path_remove_directory path_name=%s\n", path);
 #endif
-  if (unlinkat(dir_fd, path, AT_REMOVEDIR) != 0)
+  err = check_rights(dir_fd, __WASI_RIGHT_PATH_REMOVE_DIRECTORY);
+  if (err != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, err);
+  }
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(dir_fd, path, 0, &parent, name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int r = unlinkat(parent, name, AT_REMOVEDIR);
+  int e = errno;
+  close(parent);
+  if (r != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   return pack_errno(dummy, 0);
 }
@@ -1134,58 +1840,441 @@ wavm_ret_int32_t wavm_wasi_path_rename(void *dummy, int32_t old_fd, int32_t old_
                                                 int32_t num_new_path_bytes)
 {
   (void)dummy;
+  char old_path[MAX_PATH_LENGTH];
+  __wasi_errno_t err = wasi_memory_path(dummy, old_path_address, num_old_path_bytes, old_path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  char new_path[MAX_PATH_LENGTH];
+  err = wasi_memory_path(dummy, new_path_address, num_new_path_bytes, new_path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
-  printf("wavm_wasi_path_rename\n");
+  printf("wavm_wasi_path_rename old_path=%s new_path=%s\n", old_path, new_path);
 #endif
-  return pack_errno(dummy, __WASI_EPERM);
+  err = check_rights(old_fd, __WASI_RIGHT_PATH_RENAME_SOURCE);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  err = check_rights(new_fd, __WASI_RIGHT_PATH_RENAME_TARGET);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int old_parent;
+  char old_name[NAME_MAX + 1];
+  err = resolve_path(old_fd, old_path, 0, &old_parent, old_name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int new_parent;
+  char new_name[NAME_MAX + 1];
+  err = resolve_path(new_fd, new_path, 0, &new_parent, new_name);
+  if (err != 0)
+  {
+    close(old_parent);
+    return pack_errno(dummy, err);
+  }
+  int r = renameat(old_parent, old_name, new_parent, new_name);
+  int e = errno;
+  close(old_parent);
+  close(new_parent);
+  if (r != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
+  }
+  return pack_errno(dummy, 0);
 }
 
 wavm_ret_int32_t wavm_wasi_path_symlink(void *dummy, int32_t old_path_address, int32_t num_old_path_bytes,
                                                  int32_t fd, int32_t new_path_address, int32_t num_new_path_bytes)
 {
   (void)dummy;
+  char old_path[MAX_PATH_LENGTH];
+  __wasi_errno_t err = wasi_memory_path(dummy, old_path_address, num_old_path_bytes, old_path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  char new_path[MAX_PATH_LENGTH];
+  err = wasi_memory_path(dummy, new_path_address, num_new_path_bytes, new_path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
-  printf("wavm_wasi_path_symlink\n");
+  printf("wavm_wasi_path_symlink old_path=%s fd=%d new_path=%s\n", old_path, fd, new_path);
 #endif
-  return pack_errno(dummy, __WASI_EPERM);
+  err = check_rights(fd, __WASI_RIGHT_PATH_SYMLINK);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  // The content of the link is stored as it is, resolve_path keeps it inside the preopens when it is followed.
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(fd, new_path, 0, &parent, name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int r = symlinkat(old_path, parent, name);
+  int e = errno;
+  close(parent);
+  if (r != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
+  }
+  return pack_errno(dummy, 0);
 }
 
 wavm_ret_int32_t wavm_wasi_path_unlink_file(void *dummy, int32_t dir_fd, int32_t path_address, int32_t num_path_bytes)
 {
   (void)dummy;
   char path[MAX_PATH_LENGTH];
-  memcpy(path, &memoryOffset0.base[path_address], num_path_bytes);
-  path[num_path_bytes] = '\0';
+  __wasi_errno_t err = wasi_memory_path(dummy, path_address, num_path_bytes, path);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
 #ifdef DEBUG
   printf("wavm_wasi_path_unlink_file path_name=%s\n", path);
 #endif
-  if (unlinkat(dir_fd, path, 0) != 0)
+  err = check_rights(dir_fd, __WASI_RIGHT_PATH_UNLINK_FILE);
+  if (err != 0)
   {
-    return pack_errno(dummy, conv_host_errno_2_wasi_errno(errno));
+    return pack_errno(dummy, err);
+  }
+  int parent;
+  char name[NAME_MAX + 1];
+  err = resolve_path(dir_fd, path, 0, &parent, name);
+  if (err != 0)
+  {
+    return pack_errno(dummy, err);
+  }
+  int r = unlinkat(parent, name, 0);
+  int e = errno;
+  close(parent);
+  if (r != 0)
+  {
+    return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
   }
   return pack_errno(dummy, 0);
 }
 
+// The state of a subscription while poll_oneoff waits.
+typedef struct wasi_poll_subscription
+{
+  __wasi_subscription_t subscription;
+  // A subscription which can not be waited for is reported at once with this error.
+  __wasi_errno_t error;
+  // For clocks, the nanoseconds from the start of poll_oneoff to the timeout.
+  uint64_t timeout;
+} wasi_poll_subscription;
+
+uint64_t wasi_poll_elapsed(struct timespec start)
+{
+  struct timespec now;
+  clock_gettime(CLOCK_MONOTONIC, &now);
+  return conv_host_timespec_2_wasi_timestamp(now) - conv_host_timespec_2_wasi_timestamp(start);
+}
+
+// Sleep until the clock subscription times out. It returns 0 once it did, or an errno when interrupted.
+int wasi_poll_sleep(wasi_poll_subscription *sub, struct timespec start)
+{
+  struct __wasi_subscription_u_clock_t *clock = &sub->subscription.u.clock;
+  int r;
+  if (clock->flags & __WASI_SUBSCRIPTION_CLOCK_ABSTIME)
+  {
+    struct timespec deadline = conv_wasi_timestamp_2_host_timespec(clock->timeout);
+    r = clock_nanosleep(clock->clock_id, TIMER_ABSTIME, &deadline, NULL);
+  }
+  else
+  {
+    uint64_t elapsed = wasi_poll_elapsed(start);
+    struct timespec duration = conv_wasi_timestamp_2_host_timespec(sub->timeout > elapsed ? sub->timeout - elapsed : 0);
+    r = clock_nanosleep(clock->clock_id, 0, &duration, NULL);
+  }
+  // The CPU time clocks can not always be slept on, their timeouts are waited for on the monotonic clock.
+  if (r == EINVAL || r == ENOTSUP)
+  {
+    uint64_t elapsed = wasi_poll_elapsed(start);
+    struct timespec duration = conv_wasi_timestamp_2_host_timespec(sub->timeout > elapsed ? sub->timeout - elapsed : 0);
+    r = clock_nanosleep(CLOCK_MONOTONIC, 0, &duration, NULL);
+  }
+  return r;
+}
+
+// Clock subscriptions are waited for with clock_nanosleep, or with the timeout of poll when there are file descriptors
+// to watch too. Every subscription which is satisfied when the wait ends is reported.
 wavm_ret_int32_t wavm_wasi_poll_oneoff(void *dummy, int32_t in_address, int32_t out_address,
                                                 int32_t num_subscriptions, int32_t out_num_events_address)
 {
   (void)dummy;
 #ifdef DEBUG
-  printf("wavm_wasi_poll_oneoff\n");
+  printf("wavm_wasi_poll_oneoff num_subscriptions=%d\n", num_subscriptions);
 #endif
-  return pack_errno(dummy, __WASI_EPERM);
+  uint32_t n = (uint32_t)num_subscriptions;
+  if (n == 0)
+  {
+    return pack_errno(dummy, __WASI_EINVAL);
+  }
+  __wasi_subscription_t *in = wasi_memory(dummy, in_address, (uint64_t)n * sizeof(__wasi_subscription_t));
+  __wasi_event_t *out = wasi_memory(dummy, out_address, (uint64_t)n * sizeof(__wasi_event_t));
+  uint32_t *out_num_events = wasi_memory(dummy, out_num_events_address, sizeof(uint32_t));
+  if (in == NULL || out == NULL || out_num_events == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  wasi_poll_subscription *subs = calloc(n, sizeof(wasi_poll_subscription));
+  struct pollfd *fds = calloc(n, sizeof(struct pollfd));
+  if (subs == NULL || fds == NULL)
+  {
+    free(subs);
+    free(fds);
+    return pack_errno(dummy, __WASI_ENOMEM);
+  }
+  struct timespec start;
+  clock_gettime(CLOCK_MONOTONIC, &start);
+  int has_error = 0;
+  uint32_t num_fds = 0;
+  // The clock subscription which times out first, n if there is none.
+  uint32_t earliest = n;
+  for (uint32_t i = 0; i < n; i++)
+  {
+    wasi_poll_subscription *sub = &subs[i];
+    sub->subscription = in[i];
+    // poll skips the negative file descriptors.
+    fds[i].fd = -1;
+    switch (sub->subscription.type)
+    {
+    case __WASI_EVENTTYPE_CLOCK:
+    {
+      struct __wasi_subscription_u_clock_t *clock = &sub->subscription.u.clock;
+      struct timespec now;
+      if (clock->clock_id > __WASI_CLOCK_THREAD_CPUTIME_ID || clock_gettime(clock->clock_id, &now) != 0)
+      {
+        sub->error = __WASI_EINVAL;
+        break;
+      }
+      sub->timeout = clock->timeout;
+      if (clock->flags & __WASI_SUBSCRIPTION_CLOCK_ABSTIME)
+      {
+        __wasi_timestamp_t t = conv_host_timespec_2_wasi_timestamp(now);
+        sub->timeout = clock->timeout > t ? clock->timeout - t : 0;
+      }
+      if (earliest == n || sub->timeout < subs[earliest].timeout)
+      {
+        earliest = i;
+      }
+      break;
+    }
+    case __WASI_EVENTTYPE_FD_READ:
+    case __WASI_EVENTTYPE_FD_WRITE:
+    {
+      __wasi_fd_t fd = sub->subscription.u.fd_readwrite.fd;
+      sub->error = check_rights(fd, __WASI_RIGHT_POLL_FD_READWRITE);
+      if (sub->error != 0)
+      {
+        break;
+      }
+      fds[i].fd = fd;
+      fds[i].events = sub->subscription.type == __WASI_EVENTTYPE_FD_READ ? POLLIN : POLLOUT;
+      num_fds++;
+      break;
+    }
+    default:
+      sub->error = __WASI_EINVAL;
+      break;
+    }
+    has_error |= sub->error != 0;
+  }
+
+  uint32_t num_events = 0;
+  while (num_events == 0)
+  {
+    // Whether the earliest clock subscription timed out, even if the monotonic clock does not show it, as when an
+    // absolute timeout on the realtime clock is reached after the clock was set.
+    int earliest_expired = 0;
+    if (num_fds > 0)
+    {
+      int timeout_ms = -1;
+      if (has_error)
+      {
+        timeout_ms = 0;
+      }
+      else if (earliest < n)
+      {
+        uint64_t elapsed = wasi_poll_elapsed(start);
+        uint64_t remaining = subs[earliest].timeout > elapsed ? subs[earliest].timeout - elapsed : 0;
+        uint64_t ms = (remaining + 999999) / 1000000;
+        timeout_ms = ms > INT_MAX ? INT_MAX : (int)ms;
+      }
+      int r = poll(fds, n, timeout_ms);
+      if (r < 0 && errno != EINTR)
+      {
+        int e = errno;
+        free(subs);
+        free(fds);
+        return pack_errno(dummy, conv_host_errno_2_wasi_errno(e));
+      }
+    }
+    else if (!has_error)
+    {
+      earliest_expired = wasi_poll_sleep(&subs[earliest], start) == 0;
+    }
+
+    uint64_t elapsed = wasi_poll_elapsed(start);
+    for (uint32_t i = 0; i < n; i++)
+    {
+      wasi_poll_subscription *sub = &subs[i];
+      __wasi_event_t event;
+      memset(&event, 0, sizeof(event));
+      event.userdata = sub->subscription.userdata;
+      event.type = sub->subscription.type;
+      if (sub->error != 0)
+      {
+        event.error = sub->error;
+      }
+      else if (sub->subscription.type == __WASI_EVENTTYPE_CLOCK)
+      {
+        if (sub->timeout > elapsed && !(i == earliest && earliest_expired))
+        {
+          continue;
+        }
+      }
+      else
+      {
+        short revents = fds[i].revents;
+        if (revents == 0)
+        {
+          continue;
+        }
+        if (revents & POLLNVAL)
+        {
+          event.error = __WASI_EBADF;
+        }
+        else if ((revents & POLLERR) && !(revents & (POLLIN | POLLOUT)))
+        {
+          event.error = __WASI_EIO;
+        }
+        if (revents & POLLHUP)
+        {
+          event.u.fd_readwrite.flags = __WASI_EVENT_FD_READWRITE_HANGUP;
+        }
+        int available;
+        if (event.type == __WASI_EVENTTYPE_FD_READ && ioctl(fds[i].fd, FIONREAD, &available) == 0 && available > 0)
+        {
+          event.u.fd_readwrite.nbytes = available;
+        }
+      }
+      out[num_events++] = event;
+    }
+  }
+  free(subs);
+  free(fds);
+  *out_num_events = num_events;
+  return pack_errno(dummy, 0);
 }
 
+// exit terminates every thread of the process. It must not run twice at once, a thread which exits while another one
+// is exiting waits for the end of the process.
 void *wavm_wasi_proc_exit(void *dummy, int32_t code)
 {
 #ifdef DEBUG
   printf("wavm_wasi_proc_exit code=%d\n", code);
 #endif
   (void)dummy;
+  static char exiting = 0;
+  if (__atomic_test_and_set(&exiting, __ATOMIC_SEQ_CST))
+  {
+    for (;;)
+    {
+      pause();
+    }
+  }
   exit(code);
   return dummy;
 }
 
+// The native stack of a spawned thread, as large as the default one of the main thread. The wasm code of the thread
+// has its own stack in the linear memory, which it gets through the start argument.
+#define WASC_THREAD_STACK_SIZE ((size_t)8 << 20)
+// Thread IDs are positive and use at most 29 bits.
+#define WASC_THREAD_ID_MAX 0x1fffffff
+
+typedef struct wasc_thread
+{
+  void *instance;
+  int32_t tid;
+  int32_t start_arg;
+} wasc_thread;
+
+int32_t wasc_thread_id = 0;
+
+#ifdef wavm_exported_function_wasi_thread_start
+void *wasc_thread_main(void *p)
+{
+  wasc_thread *thread = (wasc_thread *)p;
+  wasc_sigsegv_install();
+  wavm_exported_function_wasi_thread_start(WASC_CONTEXT(thread->instance), thread->tid, thread->start_arg);
+  wasc_release_thread(thread->instance);
+  free(thread);
+  return NULL;
+}
+#endif
+
+// Run wasi_thread_start(tid, start_arg) of a new instance in a new thread, the instance shares the shared and imported
+// memories of the caller. Return the ID of the thread, or a negative error.
+wavm_ret_int32_t wavm_wasi_thread_spawn(void *dummy, int32_t start_arg)
+{
+#ifdef DEBUG
+  printf("wavm_wasi_thread_spawn start_arg=%d\n", start_arg);
+#endif
+#ifdef wavm_exported_function_wasi_thread_start
+  int32_t tid = __atomic_add_fetch(&wasc_thread_id, 1, __ATOMIC_SEQ_CST);
+  if (tid > WASC_THREAD_ID_MAX)
+  {
+    return pack_errno(dummy, -__WASI_EAGAIN);
+  }
+  wasc_thread *thread = malloc(sizeof(wasc_thread));
+  if (thread == NULL)
+  {
+    return pack_errno(dummy, -__WASI_EAGAIN);
+  }
+  thread->instance = wasc_instantiate_thread(WASC_INSTANCE(dummy));
+  if (thread->instance == NULL)
+  {
+    free(thread);
+    return pack_errno(dummy, -__WASI_EAGAIN);
+  }
+  thread->tid = tid;
+  thread->start_arg = start_arg;
+  pthread_attr_t attr;
+  pthread_t handle;
+  pthread_attr_init(&attr);
+  pthread_attr_setdetachstate(&attr, PTHREAD_CREATE_DETACHED);
+  pthread_attr_setstacksize(&attr, WASC_THREAD_STACK_SIZE);
+  int r = pthread_create(&handle, &attr, wasc_thread_main, thread);
+  pthread_attr_destroy(&attr);
+  if (r != 0)
+  {
+    wasc_release_thread(thread->instance);
+    free(thread);
+    return pack_errno(dummy, -__WASI_EAGAIN);
+  }
+  return pack_errno(dummy, tid);
+#else
+  // A module without wasi_thread_start can not run threads.
+  return pack_errno(dummy, -__WASI_ENOSYS);
+#endif
+}
+
 wavm_ret_int32_t wavm_wasi_sched_yield(void *dummy)
 {
   (void)dummy;
@@ -1201,9 +2290,14 @@ wavm_ret_int32_t wavm_wasi_random_get(void *dummy, int32_t buffer_address, int32
   printf("wavm_wasi_random_get buffer_address=%d num_buffer_bytes=%d\n", buffer_address, num_buffer_bytes);
 #endif
   (void)dummy;
-  for (int32_t i = 0; i < num_buffer_bytes; i++)
+  uint8_t *buffer = wasi_memory(dummy, buffer_address, (uint32_t)num_buffer_bytes);
+  if (buffer == NULL)
+  {
+    return pack_errno(dummy, __WASI_EFAULT);
+  }
+  for (uint32_t i = 0; i < (uint32_t)num_buffer_bytes; i++)
   {
-    memoryOffset0.base[buffer_address + i] = rand() % 256;
+    buffer[i] = rand() % 256;
   }
   return pack_errno(dummy, 0);
 }
@@ -0,0 +1,692 @@
+use super::aot_generator::cnaming;
+use super::compile;
+use super::context;
+use super::error::WascError;
+use super::gcc;
+
+// Runner runs the json scripts converted from the WebAssembly spec tests by wast2json. Every module of a script is
+// compiled with a C harness which runs the commands that follow it, and reports the result of each command.
+//
+// Example:
+//
+//   let runner = wasc::spectest::Runner::new(wasc::context::Platform::PosixX8664Spectest)
+//       .wavm("/usr/local/bin/wavm")
+//       .work_dir("/tmp/spectest");
+//   let report = runner.run("./res/spectest")?;
+//   println!("{} passed; {} failed; {} skipped", report.passed(), report.failed(), report.skipped());
+pub struct Runner {
+    config: context::Config,
+    work_dir: std::path::PathBuf,
+    skip: Vec<String>,
+    runner: Vec<String>,
+    executor: Option<Box<Executor>>,
+}
+
+// An executor runs a harness binary with the given arguments and returns what it prints.
+pub type Executor = dyn Fn(&std::path::Path, &[String]) -> std::io::Result<Vec<u8>>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "ok"),
+            Outcome::Fail(reason) => write!(f, "FAILED ({})", reason),
+            Outcome::Skip(reason) => write!(f, "skipped ({})", reason),
+        }
+    }
+}
+
+// The result of a command of a script.
+#[derive(Clone, Debug)]
+pub struct CommandResult {
+    pub script: std::path::PathBuf,
+    pub line: u64,
+    pub kind: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub results: Vec<CommandResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.count(|e| *e == Outcome::Pass)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|e| matches!(e, Outcome::Fail(_)))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|e| matches!(e, Outcome::Skip(_)))
+    }
+
+    fn count<F: Fn(&Outcome) -> bool>(&self, f: F) -> usize {
+        self.results.iter().filter(|e| f(&e.outcome)).count()
+    }
+}
+
+// A command which runs in the harness, with the C code that runs it.
+struct HarnessCommand<'a> {
+    command: &'a serde_json::Value,
+    code: Vec<String>,
+}
+
+impl Runner {
+    pub fn new(platform: context::Platform) -> Self {
+        let config = context::Config {
+            binary_cc: platform.default_cc().to_string(),
+            binary_ar: platform.default_ar().to_string(),
+            platform,
+            ..Default::default()
+        };
+        Runner {
+            config,
+            work_dir: std::path::PathBuf::from("spectest_build"),
+            skip: vec![],
+            runner: vec![],
+            executor: None,
+        }
+    }
+
+    // Path of the WAVM binary.
+    pub fn wavm<S: Into<String>>(mut self, binary: S) -> Self {
+        self.config.binary_wavm = binary.into();
+        self
+    }
+
+    // Path of the C compiler.
+    pub fn cc<S: Into<String>>(mut self, binary: S) -> Self {
+        self.config.binary_cc = binary.into();
+        self
+    }
+
+    // Folder where the modules are compiled, one sub folder per script.
+    pub fn work_dir<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.work_dir = path.as_ref().to_path_buf();
+        self
+    }
+
+    // Skip a module by its file name in the script, for example "elem.39.wasm". Its commands are reported as skipped.
+    pub fn skip<S: Into<String>>(mut self, file_name: S) -> Self {
+        self.skip.push(file_name.into());
+        self
+    }
+
+    // Command which runs the harness binaries, they are run directly if it is empty. The binary and its arguments are
+    // appended to the command. ckb-vm binaries need one, and it must print the messages of the debug syscall.
+    pub fn runner(mut self, command: Vec<String>) -> Self {
+        self.runner = command;
+        self
+    }
+
+    // Run the harness binaries with a function instead of a command.
+    pub fn executor(mut self, executor: Box<Executor>) -> Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    pub fn config(&self) -> &context::Config {
+        &self.config
+    }
+
+    // Find the scripts of a path: the path itself if it is a file, otherwise every json file in the folder and its
+    // sub folders, for example a folder converted by wast2json or a folder of such folders.
+    pub fn find_scripts<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<std::path::PathBuf>, WascError> {
+        let path = path.as_ref();
+        if path.is_file() {
+            return Ok(vec![path.to_path_buf()]);
+        }
+        let mut script_list = vec![];
+        for e in path.read_dir()? {
+            let e = e?.path();
+            if e.is_dir() {
+                script_list.extend(Runner::find_scripts(&e)?);
+            } else if e.extension().and_then(|x| x.to_str()) == Some("json") {
+                script_list.push(e);
+            }
+        }
+        script_list.sort();
+        Ok(script_list)
+    }
+
+    // Run every script of a path.
+    pub fn run<P: AsRef<std::path::Path>>(&self, path: P) -> Result<Report, WascError> {
+        let mut report = Report::default();
+        for e in Runner::find_scripts(path)? {
+            report.results.extend(self.run_script(&e)?.results);
+        }
+        Ok(report)
+    }
+
+    // Run a script. Failures of the modules are reported as failed commands, an error is only returned when the
+    // script can not be run at all.
+    pub fn run_script<P: AsRef<std::path::Path>>(&self, script: P) -> Result<Report, WascError> {
+        let script = script.as_ref();
+        if let context::Platform::CKBVMAssemblyScript | context::Platform::CKBVMSpectest = self.config.platform {
+            if self.runner.is_empty() && self.executor.is_none() {
+                let reason = "ckb-vm binaries can not run here, give a runner";
+                return Err(WascError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, reason)));
+            }
+        }
+        let file_json = std::fs::File::open(script)?;
+        let json: serde_json::Value = serde_json::from_reader(std::io::BufReader::new(file_json))
+            .map_err(|e| WascError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        let command_list = match json["commands"].as_array() {
+            Some(e) => e,
+            None => {
+                let reason = format!("{:?} has no commands", script);
+                return Err(WascError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    reason,
+                )));
+            }
+        };
+        let script_stem = script.file_stem().unwrap().to_str().unwrap();
+        let work_dir = self.work_dir.join(script_stem);
+        std::fs::create_dir_all(&work_dir)?;
+
+        let mut report = Report::default();
+        let mut result = |command: &serde_json::Value, outcome: Outcome| {
+            report.results.push(CommandResult {
+                script: script.to_path_buf(),
+                line: command["line"].as_u64().unwrap_or(0),
+                kind: command["type"].as_str().unwrap_or("").to_string(),
+                outcome,
+            })
+        };
+        let mut module: Option<(&serde_json::Value, Vec<&serde_json::Value>)> = None;
+        for command in command_list {
+            match command["type"].as_str().unwrap_or("") {
+                "module" => {
+                    if let Some((module_command, module_command_list)) = module.take() {
+                        for (c, outcome) in self.run_module(script, &work_dir, module_command, module_command_list)? {
+                            result(c, outcome);
+                        }
+                    }
+                    module = Some((command, vec![]));
+                }
+                "action" | "assert_return" | "assert_trap" | "assert_exhaustion" | "assert_exception" => match module {
+                    Some((_, ref mut module_command_list)) => module_command_list.push(command),
+                    None => result(command, Outcome::Fail(String::from("no module to run it"))),
+                },
+                "assert_malformed" | "assert_invalid" => {
+                    let outcome = self.check_rejected(script, &work_dir, command)?;
+                    result(command, outcome);
+                }
+                "assert_unlinkable" => {
+                    let outcome = self.check_unlinkable(script, &work_dir, command)?;
+                    result(command, outcome);
+                }
+                "assert_uninstantiable" => {
+                    let outcome = self.check_uninstantiable(script, &work_dir, command)?;
+                    result(command, outcome);
+                }
+                "register" => {
+                    result(command, Outcome::Skip(String::from("linking modules is not supported")));
+                }
+                _ => {
+                    result(command, Outcome::Skip(String::from("unknown command")));
+                }
+            }
+        }
+        if let Some((module_command, module_command_list)) = module.take() {
+            for (c, outcome) in self.run_module(script, &work_dir, module_command, module_command_list)? {
+                result(c, outcome);
+            }
+        }
+        report.results.sort_by_key(|e| e.line);
+        Ok(report)
+    }
+
+    // Copy a module of a script into the work folder. The name of a module becomes the name of its C symbols, so the
+    // dots in it are replaced.
+    fn copy_module(
+        &self,
+        script: &std::path::Path,
+        work_dir: &std::path::Path,
+        command: &serde_json::Value,
+    ) -> Result<std::path::PathBuf, WascError> {
+        let file_name = command["filename"].as_str().unwrap_or("");
+        let path = std::path::Path::new(file_name);
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("wasm");
+        let stem = path.file_stem().and_then(|e| e.to_str()).unwrap_or("");
+        let stem: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let dst = work_dir.join(format!("{}.{}", stem, extension));
+        std::fs::copy(script.with_file_name(file_name), &dst)?;
+        Ok(dst)
+    }
+
+    fn is_skipped(&self, command: &serde_json::Value) -> bool {
+        let file_name = command["filename"].as_str().unwrap_or("");
+        self.skip.iter().any(|e| e == file_name)
+    }
+
+    // Malformed and invalid modules must be rejected by WAVM. A module WAVM accepts but wasc can not read is a bug of
+    // wasc, not a rejection.
+    fn check_rejected(
+        &self,
+        script: &std::path::Path,
+        work_dir: &std::path::Path,
+        command: &serde_json::Value,
+    ) -> Result<Outcome, WascError> {
+        if self.is_skipped(command) {
+            return Ok(Outcome::Skip(String::from("skipped by the user")));
+        }
+        let path = self.copy_module(script, work_dir, command)?;
+        Ok(match compile::compile(&path, self.config.clone()) {
+            Ok(_) => Outcome::Fail(String::from("the module is accepted")),
+            Err(WascError::WavmFailed { .. }) => Outcome::Pass,
+            Err(err) => Outcome::Fail(format!("the module is rejected for another reason: {}", err)),
+        })
+    }
+
+    // Unlinkable modules import something the platform does not provide, they must fail to link. wasc itself rejects
+    // those whose imported memory does not match the one of the platform, as uninstantiable.
+    fn check_unlinkable(
+        &self,
+        script: &std::path::Path,
+        work_dir: &std::path::Path,
+        command: &serde_json::Value,
+    ) -> Result<Outcome, WascError> {
+        if self.is_skipped(command) {
+            return Ok(Outcome::Skip(String::from("skipped by the user")));
+        }
+        let path = self.copy_module(script, work_dir, command)?;
+        let linked = compile::compile(&path, self.config.clone()).and_then(|middle| gcc::build(&middle));
+        Ok(match linked {
+            Ok(_) => Outcome::Fail(String::from("the module is linked")),
+            Err(WascError::LinkFailed { .. }) | Err(WascError::Uninstantiable { .. }) => Outcome::Pass,
+            Err(err) => Outcome::Fail(format!("the module fails for another reason: {}", err)),
+        })
+    }
+
+    // Uninstantiable modules are valid, but trap when they are instantiated.
+    fn check_uninstantiable(
+        &self,
+        script: &std::path::Path,
+        work_dir: &std::path::Path,
+        command: &serde_json::Value,
+    ) -> Result<Outcome, WascError> {
+        if self.is_skipped(command) {
+            return Ok(Outcome::Skip(String::from("skipped by the user")));
+        }
+        let path = self.copy_module(script, work_dir, command)?;
+        let middle = match compile::compile(&path, self.config.clone()) {
+            Ok(middle) => middle,
+            Err(WascError::Uninstantiable { .. }) => return Ok(Outcome::Pass),
+            Err(err) => return Ok(Outcome::Fail(format!("the module does not compile: {}", err))),
+        };
+        let instantiate = vec![
+            String::from("context = WASC_CONTEXT(wasc_instantiate());"),
+            String::from("wasc_spectest_report(0, \"fail the module is instantiated\");"),
+        ];
+        let on_trap = vec![String::from("wasc_spectest_report(0, \"pass\");")];
+        let mut outcome_list = self.run_harness(&middle, instantiate, on_trap, &[], &[command])?;
+        Ok(outcome_list.remove(0))
+    }
+
+    // Compile a module with the commands that follow it, and run them.
+    fn run_module<'a>(
+        &self,
+        script: &std::path::Path,
+        work_dir: &std::path::Path,
+        module_command: &'a serde_json::Value,
+        command_list: Vec<&'a serde_json::Value>,
+    ) -> Result<Vec<(&'a serde_json::Value, Outcome)>, WascError> {
+        let mut outcome_list: Vec<(&serde_json::Value, Outcome)> = vec![];
+        if self.is_skipped(module_command) {
+            outcome_list.push((module_command, Outcome::Skip(String::from("skipped by the user"))));
+            for e in command_list {
+                outcome_list.push((e, Outcome::Skip(String::from("the module is skipped"))));
+            }
+            return Ok(outcome_list);
+        }
+        let path = self.copy_module(script, work_dir, module_command)?;
+        let middle = match compile::compile(&path, self.config.clone()) {
+            Ok(middle) => middle,
+            Err(err) => {
+                outcome_list.push((
+                    module_command,
+                    Outcome::Fail(format!("the module does not compile: {}", err)),
+                ));
+                for e in command_list {
+                    outcome_list.push((e, Outcome::Fail(String::from("the module does not compile"))));
+                }
+                return Ok(outcome_list);
+            }
+        };
+        let module_name = module_command["name"].as_str();
+        let mut harness_command_list: Vec<HarnessCommand> = vec![];
+        for e in command_list {
+            match emit_command(e, harness_command_list.len() + 1, module_name, &middle.file_stem) {
+                Ok(code) => harness_command_list.push(HarnessCommand { command: e, code }),
+                Err(reason) => outcome_list.push((e, Outcome::Skip(reason))),
+            }
+        }
+        let instantiate = vec![
+            String::from("context = WASC_CONTEXT(wasc_instantiate());"),
+            String::from("wasc_spectest_report(0, \"pass\");"),
+        ];
+        let on_trap = vec![
+            String::from("wasc_spectest_report_trap(0, trap);"),
+            String::from("wasc_spectest_print(\"wasc-spectest end\");"),
+            String::from("return 0;"),
+        ];
+        let mut command_list = vec![module_command];
+        command_list.extend(harness_command_list.iter().map(|e| e.command));
+        let outcome = self.run_harness(&middle, instantiate, on_trap, &harness_command_list, &command_list)?;
+        outcome_list.extend(command_list.into_iter().zip(outcome));
+        Ok(outcome_list)
+    }
+
+    // Append the harness to the C code of a module, build and run it. The command at index 0 is the instantiation,
+    // the others are the harness commands. Return the outcome of every command.
+    fn run_harness(
+        &self,
+        middle: &context::Middle,
+        instantiate: Vec<String>,
+        on_trap: Vec<String>,
+        harness_command_list: &[HarnessCommand],
+        command_list: &[&serde_json::Value],
+    ) -> Result<Vec<Outcome>, WascError> {
+        let source = std::fs::read_to_string(&middle.path_c)?;
+        let mut c = super::code_builder::CodeBuilder::create(&middle.path_c);
+        // The harness has its own main function.
+        c.write("#define WASC_NO_MAIN");
+        for e in source.lines() {
+            c.write(e);
+        }
+        for e in include_str!("spectest.template").lines() {
+            c.write(e);
+        }
+        c.write("int main(int argc, char *argv[]) {");
+        c.write("if (argc > 1) {");
+        c.write("wasc_spectest_resume = atoi(argv[1]);");
+        c.write("}");
+        c.write("wasc_trap_hook = wasc_spectest_trap_hook;");
+        c.write("void *volatile context = NULL;");
+        emit_block(&mut c, 0, true, &instantiate, &on_trap);
+        for (i, e) in harness_command_list.iter().enumerate() {
+            let index = i + 1;
+            let kind = e.command["type"].as_str().unwrap_or("");
+            c.write(format!("// line {}", e.command["line"].as_u64().unwrap_or(0)));
+            let on_trap = match kind {
+                "assert_trap" | "assert_exhaustion" => vec![format!("wasc_spectest_report({}, \"pass\");", index)],
+                // An exception which escapes the wasm code is a trap of its own.
+                "assert_exception" => vec![
+                    String::from("if (trap == WASC_TRAP_UNCAUGHT_EXCEPTION) {"),
+                    format!("wasc_spectest_report({}, \"pass\");", index),
+                    String::from("} else {"),
+                    format!("wasc_spectest_report_trap({}, trap);", index),
+                    String::from("}"),
+                ],
+                _ => vec![format!("wasc_spectest_report_trap({}, trap);", index)],
+            };
+            emit_block(&mut c, index, kind == "action", &e.code, &on_trap);
+        }
+        c.write("wasc_spectest_quiet = 0;");
+        c.write("wasc_spectest_print(\"wasc-spectest end\");");
+        c.write("return 0;");
+        c.write("}");
+        c.close()?;
+        if let Err(err) = gcc::build(middle) {
+            let reason = format!("the harness does not build: {}", err);
+            return Ok(command_list.iter().map(|_| Outcome::Fail(reason.clone())).collect());
+        }
+
+        // Run the harness until every command has a result. When the harness crashes, it runs again from the command
+        // which follows the one that crashed.
+        let mut outcome_list: Vec<Option<Outcome>> = vec![None; command_list.len()];
+        let mut resume = 0;
+        loop {
+            let output = self.execute(&middle.path_output, &[resume.to_string()])?;
+            let mut end = false;
+            for line in String::from_utf8_lossy(&output).lines() {
+                let line = match line.find("wasc-spectest ") {
+                    Some(i) => &line[i + "wasc-spectest ".len()..],
+                    None => continue,
+                };
+                if line.trim() == "end" {
+                    end = true;
+                    continue;
+                }
+                let mut part = line.splitn(3, ' ');
+                let index: usize = match part.next().and_then(|e| e.parse().ok()) {
+                    Some(index) if index < outcome_list.len() => index,
+                    _ => continue,
+                };
+                outcome_list[index] = Some(match part.next() {
+                    Some("pass") => Outcome::Pass,
+                    _ => Outcome::Fail(part.next().unwrap_or("").to_string()),
+                });
+            }
+            if end {
+                break;
+            }
+            let crashed = match (resume..outcome_list.len()).find(|i| outcome_list[*i].is_none()) {
+                Some(i) => i,
+                None => break,
+            };
+            // Running out of stack crashes the process on ckb-vm, it is what assert_exhaustion expects.
+            outcome_list[crashed] = Some(match command_list[crashed]["type"].as_str() {
+                Some("assert_exhaustion") => Outcome::Pass,
+                _ => Outcome::Fail(String::from("the harness crashed")),
+            });
+            if crashed == 0 {
+                break;
+            }
+            resume = crashed + 1;
+        }
+        Ok(outcome_list
+            .into_iter()
+            .map(|e| e.unwrap_or_else(|| Outcome::Fail(String::from("no result"))))
+            .collect())
+    }
+
+    fn execute(&self, binary: &std::path::Path, args: &[String]) -> Result<Vec<u8>, WascError> {
+        if let Some(executor) = &self.executor {
+            return Ok(executor(binary, args)?);
+        }
+        let mut cmd = if self.runner.is_empty() {
+            std::process::Command::new(binary)
+        } else {
+            let mut cmd = std::process::Command::new(&self.runner[0]);
+            cmd.args(&self.runner[1..]).arg(binary);
+            cmd
+        };
+        cmd.args(args);
+        rog::debugln!("$ {:?}", cmd);
+        Ok(cmd.output()?.stdout)
+    }
+}
+
+// Emit the C code of a command, which runs until a trap jumps back to it.
+fn emit_block(
+    c: &mut super::code_builder::CodeBuilder,
+    index: usize,
+    action: bool,
+    code: &[String],
+    on_trap: &[String],
+) {
+    c.write(format!("if (wasc_spectest_begin({}, {})) {{", index, action as i32));
+    c.write("int32_t trap = WASC_SPECTEST_SETJMP(wasc_spectest_env);");
+    c.write("if (trap == 0) {");
+    for e in code {
+        c.write(e);
+    }
+    c.write("} else {");
+    for e in on_trap {
+        c.write(e);
+    }
+    c.write("}");
+    c.write("}");
+}
+
+// Emit the C code of a command which runs in the harness, or the reason why it can not run.
+fn emit_command(
+    command: &serde_json::Value,
+    index: usize,
+    module_name: Option<&str>,
+    file_stem: &str,
+) -> Result<Vec<String>, String> {
+    let action = &command["action"];
+    if let Some(name) = action["module"].as_str() {
+        if Some(name) != module_name {
+            return Err(String::from("linking modules is not supported"));
+        }
+    }
+    let field = action["field"].as_str().unwrap_or("");
+    let (call, returns_struct) = match action["type"].as_str() {
+        Some("invoke") => {
+            let mut args = vec![String::from("context")];
+            for e in action["args"].as_array().map(|e| e.as_slice()).unwrap_or(&[]) {
+                let value = e["value"].as_str().unwrap_or("");
+                args.push(match e["type"].as_str() {
+                    Some("i32") => format!("(int32_t){}u", value),
+                    Some("i64") => format!("(int64_t){}ull", value),
+                    Some("f32") => format!("wasc_spectest_f32({}u)", value),
+                    Some("f64") => format!("wasc_spectest_f64({}ull)", value),
+                    Some("externref") | Some("funcref") if value == "null" => String::from("NULL"),
+                    Some("externref") => format!("(wasc_externref)(uintptr_t){}u", value),
+                    Some("v128") => {
+                        let (bits, _, _) = parse_v128(e)?;
+                        format!("wasc_spectest_v128({}ull, {}ull)", bits as u64, (bits >> 64) as u64)
+                    }
+                    _ => return Err(format!("argument type {} is not supported", e["type"])),
+                });
+            }
+            let call = format!("wavm_exported_function_{}({})", cnaming(field), args.join(", "));
+            (call, true)
+        }
+        Some("get") => {
+            let call = format!(
+                "{}_global_{}_get(WASC_INSTANCE(context))",
+                cnaming(file_stem),
+                cnaming(field)
+            );
+            (call, false)
+        }
+        _ => return Err(format!("action {} is not supported", action["type"])),
+    };
+    let mut code = vec![];
+    match command["type"].as_str() {
+        Some("assert_return") => {
+            let expected = command["expected"].as_array().map(|e| e.as_slice()).unwrap_or(&[]);
+            let mut type_list = vec![];
+            let mut check_list = vec![];
+            for (i, e) in expected.iter().enumerate() {
+                let value = e["value"].as_str().unwrap_or("");
+                let r = format!("r{}", i);
+                let (c_type, check) = match e["type"].as_str() {
+                    Some("i32") => ("int32_t", format!("(uint32_t){} != {}u", r, value)),
+                    Some("i64") => ("int64_t", format!("(uint64_t){} != {}ull", r, value)),
+                    Some("f32") if value.starts_with("nan") => ("float", format!("{} == {}", r, r)),
+                    Some("f64") if value.starts_with("nan") => ("double", format!("{} == {}", r, r)),
+                    Some("f32") => ("float", format!("wasc_spectest_f32_bits({}) != {}u", r, value)),
+                    Some("f64") => ("double", format!("wasc_spectest_f64_bits({}) != {}ull", r, value)),
+                    Some("externref") if value == "null" => ("wasc_externref", format!("{} != NULL", r)),
+                    Some("externref") => (
+                        "wasc_externref",
+                        format!("{} != (wasc_externref)(uintptr_t){}u", r, value),
+                    ),
+                    Some("funcref") if value == "null" => ("wasc_funcref", format!("{} != NULL", r)),
+                    // The spec tests only name the functions a non null funcref may point to.
+                    Some("funcref") => ("wasc_funcref", format!("{} == NULL", r)),
+                    Some("v128") => {
+                        let (bits, lane_bits, nan_mask) = parse_v128(e)?;
+                        let check = format!(
+                            "wasc_spectest_v128_mismatch({}, {}ull, {}ull, {}, {}u)",
+                            r,
+                            bits as u64,
+                            (bits >> 64) as u64,
+                            lane_bits,
+                            nan_mask
+                        );
+                        ("wasc_v128", check)
+                    }
+                    _ => return Err(format!("result type {} is not supported", e["type"])),
+                };
+                type_list.push(c_type);
+                check_list.push(format!("({})", check));
+            }
+            match type_list.len() {
+                0 => code.push(format!("{};", call)),
+                1 if returns_struct => code.push(format!("{} r0 = ({}).value;", type_list[0], call)),
+                1 => code.push(format!("{} r0 = {};", type_list[0], call)),
+                _ => {
+                    // Multiple results come in a struct named after their types, see the glue code.
+                    code.push(format!("wavm_ret_{} r = {};", type_list.join("_"), call));
+                    for (i, e) in type_list.iter().enumerate() {
+                        code.push(format!("{} r{} = r.value{};", e, i, i));
+                    }
+                }
+            }
+            if check_list.is_empty() {
+                code.push(format!("wasc_spectest_report({}, \"pass\");", index));
+            } else {
+                code.push(format!("if ({}) {{", check_list.join(" || ")));
+                code.push(format!("wasc_spectest_report({}, \"fail result mismatch\");", index));
+                code.push(String::from("} else {"));
+                code.push(format!("wasc_spectest_report({}, \"pass\");", index));
+                code.push(String::from("}"));
+            }
+        }
+        Some("action") => {
+            code.push(format!("{};", call));
+            code.push(format!("wasc_spectest_report({}, \"pass\");", index));
+        }
+        _ => {
+            code.push(format!("{};", call));
+            code.push(format!("wasc_spectest_report({}, \"fail expected a trap\");", index));
+        }
+    }
+    Ok(code)
+}
+
+// A v128 value of a script is a list of lanes. Return its bits, the size of a lane, and the lanes which are expected to
+// be NaN.
+fn parse_v128(value: &serde_json::Value) -> Result<(u128, u32, u32), String> {
+    let lane_bits = match value["lane_type"].as_str() {
+        Some("i8") => 8,
+        Some("i16") => 16,
+        Some("i32") | Some("f32") => 32,
+        Some("i64") | Some("f64") => 64,
+        _ => return Err(format!("v128 lane type {} is not supported", value["lane_type"])),
+    };
+    let lane_list = value["value"].as_array().map(|e| e.as_slice()).unwrap_or(&[]);
+    if lane_list.len() as u32 * lane_bits != 128 {
+        return Err(format!("v128 of {} {} lanes", lane_list.len(), value["lane_type"]));
+    }
+    let mut bits: u128 = 0;
+    let mut nan_mask: u32 = 0;
+    for (i, e) in lane_list.iter().enumerate() {
+        let lane = e.as_str().unwrap_or("");
+        if lane.starts_with("nan") {
+            nan_mask |= 1 << i;
+            continue;
+        }
+        let lane = match lane.parse::<u64>() {
+            Ok(v) => v,
+            Err(_) => lane.parse::<i64>().map_err(|_| format!("v128 lane {}", lane))? as u64,
+        };
+        let mask = if lane_bits == 64 {
+            u64::MAX
+        } else {
+            (1 << lane_bits) - 1
+        };
+        bits |= ((lane & mask) as u128) << (i as u32 * lane_bits);
+    }
+    Ok((bits, lane_bits, nan_mask))
+}
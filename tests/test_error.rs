@@ -0,0 +1,409 @@
+use wasc::aot_generator;
+use wasc::context;
+
+// Run the aot generator on a handmade module, as if it had been precompiled by WAVM. The files are written in the
+// temporary folder of the integration tests.
+fn generate(name: &str, wasm: &[u8]) -> Result<(), wasc::WascError> {
+    let dest = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_error");
+    std::fs::create_dir_all(&dest)?;
+    let wasm_path = dest.join(format!("{}.wasm", name));
+    std::fs::write(&wasm_path, wasm)?;
+    let mut middle = context::Middle::default();
+    middle.init_config(context::Config {
+        platform: context::Platform::PosixX8664Spectest,
+        ..Default::default()
+    });
+    middle.init_file(&wasm_path)?;
+    middle.path_precompiled = wasm_path;
+    middle.path_object = dest.join(format!("{}.o", name));
+    middle.path_glue = dest.join(format!("{}_glue.h", name));
+    middle.path_header = dest.join(format!("{}.h", name));
+    aot_generator::generate(&mut middle)
+}
+
+// The glue code generated by generate.
+fn read_glue(name: &str) -> String {
+    let dest = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_error");
+    std::fs::read_to_string(dest.join(format!("{}_glue.h", name))).unwrap()
+}
+
+// Build a handmade module into a static library with WAVM, link it into a host program made of the generated header
+// and the given body of main, and return what the host prints.
+fn run(name: &str, wasm: &[u8], host: &str) -> String {
+    let dest = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_error_run");
+    std::fs::create_dir_all(&dest).unwrap();
+    let wasm_path = dest.join(format!("{}.wasm", name));
+    std::fs::write(&wasm_path, wasm).unwrap();
+    let artifacts = wasc::Compiler::new(context::Platform::PosixX8664Spectest)
+        .wavm("./third_party/WAVM/build/bin/wavm")
+        .emit(context::Emit::StaticLib)
+        .compile(&wasm_path)
+        .unwrap();
+    let host_path = dest.join(format!("{}_host.c", name));
+    let host = format!(
+        "#include <stdio.h>\n#include \"{}.h\"\n\nint main()\n{{{}\n  return 0;\n}}\n",
+        name, host
+    );
+    std::fs::write(&host_path, host).unwrap();
+    let exe_path = dest.join(format!("{}_host", name));
+    let status = std::process::Command::new("gcc")
+        .arg("-o")
+        .arg(&exe_path)
+        .arg(&host_path)
+        .arg(&artifacts.outputs[1])
+        .arg("-pthread")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = std::process::Command::new(&exe_path).output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_error_location() {
+    // (module (func (param nullref))), nullref is a type of an early draft of the reference types proposal.
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x05, 0x01, 0x60, 0x01, 0x6e, 0x00, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code section
+    ];
+    match generate("nullref_param", &wasm) {
+        Err(wasc::WascError::Unsupported { location, .. }) => {
+            let location = location.unwrap();
+            assert_eq!(location.section, "function");
+            assert_eq!(location.index, Some(0));
+            assert_eq!(location.offset, 18);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // (module (memory 1) (data (memory.size) "*"))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section
+        0x0b, 0x07, 0x01, 0x00, 0x3f, 0x00, 0x0b, 0x01, 0x2a, // data section
+    ];
+    match generate("memory_size_offset", &wasm) {
+        Err(wasc::WascError::Unsupported { location, .. }) => {
+            let location = location.unwrap();
+            assert_eq!(location.section, "data");
+            assert_eq!(location.index, Some(0));
+            assert_eq!(location.offset, 16);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // (module (type (func (result i32))) (tag (type 0))), the type of a tag has no results.
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type section
+        0x0d, 0x03, 0x01, 0x00, 0x00, // tag section
+    ];
+    match generate("tag_results", &wasm) {
+        Err(wasc::WascError::Malformed { location, .. }) => {
+            let location = location.unwrap();
+            assert_eq!(location.section, "tag");
+            assert_eq!(location.index, Some(0));
+            assert_eq!(location.offset, 18);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // (module (memory i64 65537)), a memory larger than 4GiB.
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x05, 0x05, 0x01, 0x04, 0x81, 0x80, 0x04, // memory section
+    ];
+    match generate("memory64_large", &wasm) {
+        Err(wasc::WascError::Unsupported { location, .. }) => {
+            let location = location.unwrap();
+            assert_eq!(location.section, "memory");
+            assert_eq!(location.index, Some(0));
+            assert_eq!(location.offset, 11);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // (module (import "env" "memory" (memory i64 65537))), an imported memory larger than 4GiB.
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x02, 0x11, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x04, 0x81, 0x80,
+        0x04, // import section
+    ];
+    match generate("imported_memory64_large", &wasm) {
+        Err(wasc::WascError::Unsupported { location, .. }) => {
+            let location = location.unwrap();
+            assert_eq!(location.section, "import");
+            assert_eq!(location.index, Some(0));
+            assert_eq!(location.offset, 11);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // A global initializer which leaves two values on the stack.
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x06, 0x08, 0x01, 0x7f, 0x00, 0x41, 0x01, 0x41, 0x02, 0x0b, // global section
+    ];
+    match generate("global_two_values", &wasm) {
+        Err(wasc::WascError::Malformed { location, .. }) => {
+            let location = location.unwrap();
+            assert_eq!(location.section, "global");
+            assert_eq!(location.index, Some(0));
+            assert_eq!(location.offset, 11);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // A truncated type section.
+    let wasm = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x06, 0x01, 0x60];
+    match generate("truncated", &wasm) {
+        Err(wasc::WascError::Malformed { location, .. }) => assert!(location.is_some()),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+// Modules which earlier versions rejected, they must now generate their glue code, and run.
+#[test]
+fn test_multi_value() {
+    // (module (func (export "f") (result i32 i32) (i32.const 1) (i32.const 2)))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x00, 0x02, 0x7f, 0x7f, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00, // export section
+        0x0a, 0x08, 0x01, 0x06, 0x00, 0x41, 0x01, 0x41, 0x02, 0x0b, // code section
+    ];
+    generate("multi_value", &wasm).unwrap();
+    assert!(read_glue("multi_value").contains("wavm_ret_int32_t_int32_t"));
+    let host = r#"
+  multi_value_instance *instance = multi_value_new();
+  multi_value_f_results results = multi_value_f(instance);
+  printf("%d %d\n", results.value0, results.value1);"#;
+    assert_eq!(run("multi_value", &wasm, host), "1 2\n");
+}
+
+#[test]
+fn test_reference_types() {
+    // (module
+    //   (table $t (export "t") 2 10 externref)
+    //   (table 1 funcref)
+    //   (func (export "f") (param externref) (result externref)
+    //     (table.set $t (i32.const 0) (local.get 0))
+    //     (table.get $t (i32.const 0)))
+    //   (func (export "grow") (param externref) (result i32) (table.grow $t (local.get 0) (i32.const 3)))
+    //   (func (export "fill") (param externref) (table.fill $t (i32.const 1) (local.get 0) (i32.const 4))))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x0f, 0x03, 0x60, 0x01, 0x6f, 0x01, 0x6f, 0x60, 0x01, 0x6f, 0x01, 0x7f, 0x60, 0x01, 0x6f,
+        0x00, // type section
+        0x03, 0x04, 0x03, 0x00, 0x01, 0x02, // function section
+        0x04, 0x08, 0x02, 0x6f, 0x01, 0x02, 0x0a, 0x70, 0x00, 0x01, // table section
+        0x07, 0x17, 0x04, 0x01, 0x74, 0x01, 0x00, 0x01, 0x66, 0x00, 0x00, 0x04, 0x67, 0x72, 0x6f, 0x77, 0x00, 0x01,
+        0x04, 0x66, 0x69, 0x6c, 0x6c, 0x00, 0x02, // export section
+        0x0a, 0x24, 0x03, 0x0c, 0x00, 0x41, 0x00, 0x20, 0x00, 0x26, 0x00, 0x41, 0x00, 0x25, 0x00, 0x0b, 0x09, 0x00,
+        0x20, 0x00, 0x41, 0x03, 0xfc, 0x0f, 0x00, 0x0b, 0x0b, 0x00, 0x41, 0x01, 0x20, 0x00, 0x41, 0x04, 0xfc, 0x11,
+        0x00, 0x0b, // code section
+    ];
+    generate("reference_types", &wasm).unwrap();
+    assert!(read_glue("reference_types").contains("wasc_externref reference_types_table_t_get("));
+    // Growing the table past its maximum fails.
+    let host = r#"
+  reference_types_instance *instance = reference_types_new();
+  int a = 0;
+  int b = 0;
+  printf("%d ", reference_types_f(instance, &a) == &a);
+  printf("%d ", reference_types_table_t_get(instance, 0) == &a);
+  printf("%d ", reference_types_grow(instance, &b));
+  printf("%u ", reference_types_table_t_size(instance));
+  printf("%d ", reference_types_table_t_get(instance, 4) == &b);
+  reference_types_fill(instance, &a);
+  printf("%d ", reference_types_table_t_get(instance, 1) == &a && reference_types_table_t_get(instance, 4) == &a);
+  printf("%d ", reference_types_grow(instance, &b));
+  printf("%d\n", reference_types_grow(instance, &b));"#;
+    assert_eq!(run("reference_types", &wasm, host), "1 1 2 5 1 1 5 -1\n");
+}
+
+#[test]
+fn test_passive_data() {
+    // (module
+    //   (memory (export "m") 1)
+    //   (data "*")
+    //   (data "+")
+    //   (func (export "init") (memory.init 1 (i32.const 0) (i32.const 0) (i32.const 1)) (data.drop 1)))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section
+        0x07, 0x0c, 0x02, 0x01, 0x6d, 0x02, 0x00, 0x04, 0x69, 0x6e, 0x69, 0x74, 0x00, 0x00, // export section
+        0x0c, 0x01, 0x02, // data count section
+        0x0a, 0x11, 0x01, 0x0f, 0x00, 0x41, 0x00, 0x41, 0x00, 0x41, 0x01, 0xfc, 0x08, 0x01, 0x00, 0xfc, 0x09, 0x01,
+        0x0b, // code section
+        0x0b, 0x07, 0x02, 0x01, 0x01, 0x2a, 0x01, 0x01, 0x2b, // data section
+    ];
+    generate("passive_data", &wasm).unwrap();
+    assert!(read_glue("passive_data").contains("instance->data_segment[1].base = data_segment1;"));
+    // A reset instance gets its dropped segment back.
+    let host = r#"
+  passive_data_instance *instance = passive_data_new();
+  uint8_t *memory = passive_data_memory_m(instance);
+  printf("%d ", memory[0]);
+  passive_data_init(instance);
+  printf("%d ", memory[0]);
+  passive_data_reset(instance);
+  printf("%d ", memory[0]);
+  passive_data_init(instance);
+  printf("%d\n", memory[0]);"#;
+    assert_eq!(run("passive_data", &wasm, host), "0 43 0 43\n");
+}
+
+#[test]
+fn test_shared_memory() {
+    // (module (memory 1 1 shared) (func (export "f") (result i32) (i32.atomic.rmw.add (i32.const 0) (i32.const 1))))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x05, 0x04, 0x01, 0x03, 0x01, 0x01, // memory section
+        0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00, // export section
+        0x0a, 0x0c, 0x01, 0x0a, 0x00, 0x41, 0x00, 0x41, 0x01, 0xfe, 0x1e, 0x02, 0x00, 0x0b, // code section
+    ];
+    generate("shared_memory", &wasm).unwrap();
+    assert!(read_glue("shared_memory").contains("if (id == WASC_MEMORY_ID("));
+    let host = r#"
+  shared_memory_instance *instance = shared_memory_new();
+  printf("%d ", shared_memory_f(instance));
+  printf("%d ", shared_memory_f(instance));
+  printf("%d\n", shared_memory_f(instance));"#;
+    assert_eq!(run("shared_memory", &wasm, host), "0 1 2\n");
+}
+
+#[test]
+fn test_simd_param() {
+    // (module (func (export "f") (param v128) (result i32) (i32x4.extract_lane 1 (local.get 0))))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7b, 0x01, 0x7f, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00, // export section
+        0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0xfd, 0x1b, 0x01, 0x0b, // code section
+    ];
+    generate("simd_param", &wasm).unwrap();
+    assert!(read_glue("simd_param").contains("(functionDef0) (void*, wasc_v128);"));
+    let host = r#"
+  simd_param_instance *instance = simd_param_new();
+  wasc_v128 value = {((int64_t)2 << 32) | 1, 0};
+  printf("%d\n", simd_param_f(instance, value));"#;
+    assert_eq!(run("simd_param", &wasm, host), "2\n");
+}
+
+#[test]
+fn test_exception_tag() {
+    // (module
+    //   (tag (export "e") (param i32))
+    //   (func (export "f") (param i32) (result i32) (try (result i32) (do (throw 0 (local.get 0))) (catch 0))))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x0a, 0x02, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x01, 0x7f, 0x01, 0x7f, // type section
+        0x03, 0x02, 0x01, 0x01, // function section
+        0x0d, 0x03, 0x01, 0x00, 0x00, // tag section
+        0x07, 0x09, 0x02, 0x01, 0x65, 0x04, 0x00, 0x01, 0x66, 0x00, 0x00, // export section
+        0x0a, 0x0d, 0x01, 0x0b, 0x00, 0x06, 0x7f, 0x20, 0x00, 0x08, 0x00, 0x07, 0x00, 0x0b, 0x0b, // code section
+    ];
+    generate("exception_tag", &wasm).unwrap();
+    assert!(read_glue("exception_tag").contains("const wasc_tag *exception_tag_tag_e(void) {"));
+    let host = r#"
+  exception_tag_instance *instance = exception_tag_new();
+  printf("%d ", exception_tag_tag_e() != NULL);
+  printf("%d\n", exception_tag_f(instance, 7));"#;
+    assert_eq!(run("exception_tag", &wasm, host), "1 7\n");
+}
+
+#[test]
+fn test_memory64() {
+    // (module
+    //   (memory (export "a") i64 1)
+    //   (memory (export "b") 1)
+    //   (func (export "grow") (param i64) (result i64) (memory.grow 0 (local.get 0))))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7e, 0x01, 0x7e, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x05, 0x05, 0x02, 0x04, 0x01, 0x00, 0x01, // memory section
+        0x07, 0x10, 0x03, 0x01, 0x61, 0x02, 0x00, 0x01, 0x62, 0x02, 0x01, 0x04, 0x67, 0x72, 0x6f, 0x77, 0x00,
+        0x00, // export section
+        0x0a, 0x08, 0x01, 0x06, 0x00, 0x20, 0x00, 0x40, 0x00, 0x0b, // code section
+    ];
+    generate("memory64", &wasm).unwrap();
+    let glue = read_glue("memory64");
+    assert!(glue.contains("uint64_t memory64_memory_a_pages("));
+    assert!(glue.contains("uint64_t memory64_memory_b_pages("));
+    // A 64-bit memory grows up to 4GiB, not past it.
+    let host = r#"
+  memory64_instance *instance = memory64_new();
+  printf("%lld ", (long long)memory64_grow(instance, 65535));
+  printf("%llu ", (unsigned long long)memory64_memory_a_pages(instance));
+  memory64_memory_a(instance)[((uint64_t)65536 << 16) - 1] = 1;
+  printf("%lld ", (long long)memory64_grow(instance, 1));
+  printf("%llu\n", (unsigned long long)memory64_memory_b_pages(instance));"#;
+    assert_eq!(run("memory64", &wasm, host), "1 65536 -1 1\n");
+}
+
+#[test]
+fn test_extended_const() {
+    // (module
+    //   (import "spectest" "global_i32" (global i32))
+    //   (global (export "g") i32 (i32.add (global.get 0) (i32.const 1))))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x02, 0x18, 0x01, 0x08, 0x73, 0x70, 0x65, 0x63, 0x74, 0x65, 0x73, 0x74, 0x0a, 0x67, 0x6c, 0x6f, 0x62, 0x61,
+        0x6c, 0x5f, 0x69, 0x33, 0x32, 0x03, 0x7f, 0x00, // import section
+        0x06, 0x09, 0x01, 0x7f, 0x00, 0x23, 0x00, 0x41, 0x01, 0x6a, 0x0b, // global section
+        0x07, 0x05, 0x01, 0x01, 0x67, 0x03, 0x01, // export section
+    ];
+    generate("extended_const", &wasm).unwrap();
+    assert!(read_glue("extended_const")
+        .contains("global1 = (int32_t) ((uint32_t) (wavm_spectest_global_i32) + (uint32_t) (1u));"));
+    // spectest.global_i32 is 42.
+    let host = r#"
+  extended_const_instance *instance = extended_const_new();
+  printf("%d\n", extended_const_global_g_get(instance));"#;
+    assert_eq!(run("extended_const", &wasm, host), "43\n");
+}
+
+#[test]
+fn test_imported_memory() {
+    // (module
+    //   (import "spectest" "memory" (memory 1 2))
+    //   (export "m" (memory 0))
+    //   (data (i32.const 8) "*")
+    //   (func (export "store") (i32.store8 (i32.const 16) (i32.const 7))))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+        0x02, 0x15, 0x01, 0x08, 0x73, 0x70, 0x65, 0x63, 0x74, 0x65, 0x73, 0x74, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72,
+        0x79, 0x02, 0x01, 0x01, 0x02, // import section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x0d, 0x02, 0x01, 0x6d, 0x02, 0x00, 0x05, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x00,
+        0x00, // export section
+        0x0a, 0x0b, 0x01, 0x09, 0x00, 0x41, 0x10, 0x41, 0x07, 0x3a, 0x00, 0x00, 0x0b, // code section
+        0x0b, 0x07, 0x01, 0x00, 0x41, 0x08, 0x0b, 0x01, 0x2a, // data section
+    ];
+    generate("imported_memory", &wasm).unwrap();
+    assert!(read_glue("imported_memory")
+        .contains("wasc_memory_import(&instance->memory0, &wavm_spectest_memory, wavm_spectest_memory_pages);"));
+    // Both instances share the memory of the host.
+    let host = r#"
+  imported_memory_instance *a = imported_memory_new();
+  imported_memory_instance *b = imported_memory_new();
+  uint8_t *memory = imported_memory_memory_m(a);
+  printf("%d ", memory == imported_memory_memory_m(b));
+  printf("%d ", memory[8]);
+  imported_memory_store(b);
+  printf("%d ", memory[16]);
+  printf("%llu\n", (unsigned long long)imported_memory_memory_m_pages(a));"#;
+    assert_eq!(run("imported_memory", &wasm, host), "1 42 7 1\n");
+}
@@ -0,0 +1,238 @@
+use wasc::instrument::clamp_call_indirect;
+
+fn leb(mut value: u64) -> Vec<u8> {
+    let mut output = vec![];
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return output;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn section(id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut output = vec![id];
+    output.extend(leb(payload.len() as u64));
+    output.extend_from_slice(payload);
+    output
+}
+
+// A function body with its groups of locals, a count and a value type each.
+fn body(local_list: &[(u32, u8)], code: &[u8]) -> Vec<u8> {
+    let mut output = leb(local_list.len() as u64);
+    for (count, value_type) in local_list {
+        output.extend(leb(u64::from(*count)));
+        output.push(*value_type);
+    }
+    output.extend_from_slice(code);
+    output
+}
+
+// A module with the types (func (result i32)) and (func (param i32) (result i32)), two tables of funcref, and one
+// function of the given type for every body.
+fn module(function_list: &[u8], body_list: &[Vec<u8>]) -> Vec<u8> {
+    let mut output = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    output.extend(section(
+        0x01,
+        &[0x02, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x01, 0x7f],
+    ));
+    let mut functions = leb(function_list.len() as u64);
+    functions.extend_from_slice(function_list);
+    output.extend(section(0x03, &functions));
+    output.extend(section(0x04, &[0x02, 0x70, 0x00, 0x01, 0x70, 0x00, 0x01]));
+    let mut code = leb(body_list.len() as u64);
+    for e in body_list {
+        code.extend(leb(e.len() as u64));
+        code.extend_from_slice(e);
+    }
+    output.extend(section(0x0a, &code));
+    output
+}
+
+// The instructions inserted in front of a call_indirect, which clamp the index on the stack to the length of the table:
+// local.set $index, local.get $index, table.size $table, local.tee $length, local.get $index, local.get $length,
+// i32.lt_u and select.
+fn clamp(table: u8, index: u8, length: u8) -> Vec<u8> {
+    vec![
+        0x21, index, 0x20, index, 0xfc, 0x10, table, 0x22, length, 0x20, index, 0x20, length, 0x49, 0x1b,
+    ]
+}
+
+#[test]
+fn test_clamp_call_indirect_table_0() {
+    // (func (result i32) (call_indirect (result i32) (i32.const 0)))
+    let wasm = module(&[0x00], &[body(&[], &[0x41, 0x00, 0x11, 0x00, 0x00, 0x0b])]);
+    let mut code = vec![0x41, 0x00];
+    code.extend(clamp(0x00, 0x00, 0x01));
+    code.extend_from_slice(&[0x11, 0x00, 0x00, 0x0b]);
+    let expected = module(&[0x00], &[body(&[(2, 0x7f)], &code)]);
+    assert_eq!(clamp_call_indirect(&wasm).unwrap(), expected);
+}
+
+#[test]
+fn test_clamp_call_indirect_table_1() {
+    // (func (param i32) (result i32) (call_indirect 1 (result i32) (local.get 0)))
+    let wasm = module(&[0x01], &[body(&[], &[0x20, 0x00, 0x11, 0x00, 0x01, 0x0b])]);
+    let mut code = vec![0x20, 0x00];
+    code.extend(clamp(0x01, 0x01, 0x02));
+    code.extend_from_slice(&[0x11, 0x00, 0x01, 0x0b]);
+    let expected = module(&[0x01], &[body(&[(2, 0x7f)], &code)]);
+    assert_eq!(clamp_call_indirect(&wasm).unwrap(), expected);
+}
+
+#[test]
+fn test_clamp_return_call_indirect() {
+    // (func (result i32) (return_call_indirect (result i32) (i32.const 0)))
+    // (func (result i32) (return_call_indirect 1 (result i32) (i32.const 0)))
+    let wasm = module(
+        &[0x00, 0x00],
+        &[
+            body(&[], &[0x41, 0x00, 0x13, 0x00, 0x00, 0x0b]),
+            body(&[], &[0x41, 0x00, 0x13, 0x00, 0x01, 0x0b]),
+        ],
+    );
+    let mut code_0 = vec![0x41, 0x00];
+    code_0.extend(clamp(0x00, 0x00, 0x01));
+    code_0.extend_from_slice(&[0x13, 0x00, 0x00, 0x0b]);
+    let mut code_1 = vec![0x41, 0x00];
+    code_1.extend(clamp(0x01, 0x00, 0x01));
+    code_1.extend_from_slice(&[0x13, 0x00, 0x01, 0x0b]);
+    let expected = module(
+        &[0x00, 0x00],
+        &[body(&[(2, 0x7f)], &code_0), body(&[(2, 0x7f)], &code_1)],
+    );
+    assert_eq!(clamp_call_indirect(&wasm).unwrap(), expected);
+}
+
+#[test]
+fn test_clamp_call_indirect_local_group() {
+    // (func (param i32) (result i32) (local i64 i64 f32)
+    //   (call_indirect (result i32) (local.get 0))
+    //   (call_indirect 1 (result i32) (i32.const 1))
+    //   (drop))
+    // The new locals follow the parameter and the three existing locals, in a group of their own.
+    let wasm = module(
+        &[0x01],
+        &[body(
+            &[(2, 0x7e), (1, 0x7d)],
+            &[0x20, 0x00, 0x11, 0x00, 0x00, 0x41, 0x01, 0x11, 0x00, 0x01, 0x1a, 0x0b],
+        )],
+    );
+    let mut code = vec![0x20, 0x00];
+    code.extend(clamp(0x00, 0x04, 0x05));
+    code.extend_from_slice(&[0x11, 0x00, 0x00, 0x41, 0x01]);
+    code.extend(clamp(0x01, 0x04, 0x05));
+    code.extend_from_slice(&[0x11, 0x00, 0x01, 0x1a, 0x0b]);
+    let expected = module(&[0x01], &[body(&[(2, 0x7e), (1, 0x7d), (2, 0x7f)], &code)]);
+    assert_eq!(clamp_call_indirect(&wasm).unwrap(), expected);
+}
+
+#[test]
+fn test_clamp_call_indirect_leb_size() {
+    // A body of 120 bytes, whose size fits one byte of LEB128, grows to 137 bytes, which take two. So does the size of
+    // the code section.
+    let mut code = vec![0x01; 113];
+    code.extend_from_slice(&[0x41, 0x00, 0x11, 0x00, 0x00, 0x0b]);
+    let wasm = module(&[0x00], &[body(&[], &code)]);
+    let code_offset = wasm.len() - 124;
+    assert_eq!(&wasm[code_offset..code_offset + 4], &[0x0a, 0x7a, 0x01, 0x78]);
+
+    let output = clamp_call_indirect(&wasm).unwrap();
+    assert_eq!(&output[..code_offset], &wasm[..code_offset]);
+    assert_eq!(
+        &output[code_offset..code_offset + 6],
+        &[0x0a, 0x8c, 0x01, 0x01, 0x89, 0x01]
+    );
+    let mut expected_code = vec![0x01; 113];
+    expected_code.extend_from_slice(&[0x41, 0x00]);
+    expected_code.extend(clamp(0x00, 0x00, 0x01));
+    expected_code.extend_from_slice(&[0x11, 0x00, 0x00, 0x0b]);
+    assert_eq!(output, module(&[0x00], &[body(&[(2, 0x7f)], &expected_code)]));
+}
+
+#[test]
+fn test_clamp_call_indirect_unchanged() {
+    // (func (result i32) (local i64)
+    //   (block (br_table 0 0 (i32.const 0)))
+    //   (call 1 (i64.const 300) (i32.wrap_i64)))
+    // (func (param i32) (result i32) (ref.is_null (ref.func 0)))
+    // followed by a custom section: without any call_indirect, the module is left as it is.
+    let mut wasm = module(
+        &[0x00, 0x01],
+        &[
+            body(
+                &[(1, 0x7e)],
+                &[
+                    0x02, 0x40, 0x41, 0x00, 0x0e, 0x01, 0x00, 0x00, 0x0b, 0x42, 0xac, 0x02, 0xa7, 0x10, 0x01, 0x0b,
+                ],
+            ),
+            body(&[], &[0xd2, 0x00, 0xd1, 0x0b]),
+        ],
+    );
+    wasm.extend(section(0x00, &[0x03, 0x61, 0x62, 0x63]));
+    assert_eq!(clamp_call_indirect(&wasm).unwrap(), wasm);
+}
+
+#[test]
+fn test_clamp_call_indirect_unsupported_opcode() {
+    // An opcode which is not assigned.
+    let wasm = module(&[0x00], &[body(&[], &[0x41, 0x00, 0xff, 0x0b])]);
+    match clamp_call_indirect(&wasm) {
+        Err(wasc::WascError::Unsupported { feature, location }) => {
+            assert_eq!(feature, "opcode 0xff");
+            let location = location.unwrap();
+            assert_eq!(location.section, "code");
+            assert_eq!(location.index, Some(0));
+            assert_eq!(location.offset, wasm.len() - 1);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // A prefixed opcode which is not assigned, in the second function.
+    let wasm = module(
+        &[0x00, 0x00],
+        &[body(&[], &[0x41, 0x00, 0x0b]), body(&[], &[0xfc, 0x20, 0x0b])],
+    );
+    match clamp_call_indirect(&wasm) {
+        Err(wasc::WascError::Unsupported { feature, location }) => {
+            assert_eq!(feature, "opcode 0xfc 0x20");
+            let location = location.unwrap();
+            assert_eq!(location.section, "code");
+            assert_eq!(location.index, Some(1));
+            assert_eq!(location.offset, wasm.len() - 1);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // An atomic opcode which is not assigned.
+    let wasm = module(&[0x00], &[body(&[], &[0xfe, 0x04, 0x0b])]);
+    match clamp_call_indirect(&wasm) {
+        Err(wasc::WascError::Unsupported { feature, .. }) => assert_eq!(feature, "opcode 0xfe 0x04"),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // A local of a type wasc does not know, nullref of an early draft of the reference types proposal.
+    let wasm = module(&[0x00], &[body(&[(1, 0x6e)], &[0x41, 0x00, 0x0b])]);
+    match clamp_call_indirect(&wasm) {
+        Err(wasc::WascError::Unsupported { feature, .. }) => assert_eq!(feature, "value type 0x6e"),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_clamp_call_indirect_malformed() {
+    match clamp_call_indirect(b"\0asn\x01\0\0\0") {
+        Err(wasc::WascError::Malformed { reason, .. }) => assert_eq!(reason, "magic header not detected"),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // The body ends in the middle of a call_indirect.
+    let wasm = module(&[0x00], &[body(&[], &[0x41, 0x00, 0x11, 0x00])]);
+    match clamp_call_indirect(&wasm) {
+        Err(wasc::WascError::Malformed { reason, .. }) => assert_eq!(reason, "unexpected end"),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
@@ -6,13 +6,21 @@ use wasc::gcc;
 mod misc;
 
 fn test_single_test<P: AsRef<std::path::Path>>(wasm_path: P) -> Result<i32, Box<dyn std::error::Error>> {
-    let mut config = wasc::context::Config::default();
-    config.platform = context::Platform::PosixX8664Spectest;
-    config.binary_wavm = String::from("./third_party/WAVM/build/bin/wavm");
+    let config = context::Config {
+        platform: context::Platform::PosixX8664Spectest,
+        binary_wavm: String::from("./third_party/WAVM/build/bin/wavm"),
+        ..Default::default()
+    };
     let middle = compile::compile(&wasm_path, config)?;
     let mut dummy_file = code_builder::CodeBuilder::append(&middle.path_c)?;
     dummy_file.write("int main() {");
-    dummy_file.write("wavm_ret_int32_t wavm_ret = wavm_exported_function_main(NULL);");
+    dummy_file.write("void *context = WASC_CONTEXT(wasc_instantiate());");
+    dummy_file.write("wavm_ret_int32_t wavm_ret = wavm_exported_function_main(context);");
+    // A reset instance must behave like a new one.
+    dummy_file.write("reset(WASC_INSTANCE(context));");
+    dummy_file.write("if (wavm_exported_function_main(context).value != wavm_ret.value) {");
+    dummy_file.write("return 1;");
+    dummy_file.write("}");
     dummy_file.write("return wavm_ret.value;");
     dummy_file.write("}");
     dummy_file.close()?;
@@ -39,5 +47,18 @@ fn test_posix_x86_64_spectest_bugs() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(exit_code, 42);
     exit_code = test_single_test("./res/posix_x86_64_spectest_bugs/import_global_add.wasm")?;
     assert_eq!(exit_code, 52);
+    // An element segment past the end of an imported table traps when the instance is created, and so does a
+    // call_indirect past its end: 200 + WASC_TRAP_OUT_OF_BOUNDS_TABLE and 200 + WASC_TRAP_CALL_INDIRECT.
+    exit_code = test_single_test("./res/posix_x86_64_spectest_bugs/import_table_elem.wasm")?;
+    assert_eq!(exit_code, 206);
+    exit_code = test_single_test("./res/posix_x86_64_spectest_bugs/import_table_call.wasm")?;
+    assert_eq!(exit_code, 204);
+    // A table with a maximum only reserves one element past it, a call_indirect far past its end still traps.
+    exit_code = test_single_test("./res/posix_x86_64_spectest_bugs/table_max_call.wasm")?;
+    assert_eq!(exit_code, 204);
+    // main returns the sum of a byte of memory, a mutable global and the result of a call through the table, 7 + 11 +
+    // 13, before changing all three. It returns the same sum after the instance is reset, or the test returns 1.
+    exit_code = test_single_test("./res/posix_x86_64_spectest_bugs/reset.wasm")?;
+    assert_eq!(exit_code, 31);
     Ok(())
 }
@@ -6,10 +6,10 @@ import os.path
 def test_append():
     print('test_append')
     subprocess.getoutput('rm /tmp/a')
-    subprocess.getoutput('res/posix_x86_64_wasi/append /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/append --dir /tmp /tmp/a')
     r = subprocess.getoutput('cat /tmp/a')
     assert(r == 'Hello World!')
-    subprocess.getoutput('res/posix_x86_64_wasi/append /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/append --dir /tmp /tmp/a')
     r = subprocess.getoutput('cat /tmp/a')
     assert(r == 'Hello World!\nHello World!')
     subprocess.getoutput('rm /tmp/a')
@@ -25,18 +25,44 @@ def test_args():
     assert(r[5] == 'argv[4]: <null>')
     r = subprocess.getoutput('res/posix_x86_64_wasi/args').split('\n')
     assert(r[0] == 'argc=1')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/args --dir /tmp -- --dir 1').split('\n')
+    assert(r[0] == 'argc=3')
+    assert(r[2] == 'argv[1]: --dir')
 
 
 def test_cat():
     print('test_cat')
     subprocess.getoutput('echo Hello World! > /tmp/a')
-    r = subprocess.getoutput('res/posix_x86_64_wasi/cat /tmp/a')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/cat --dir /tmp /tmp/a')
     assert(r == 'Hello World!')
-    r = subprocess.getoutput('res/posix_x86_64_wasi/cat /tmp/b')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/cat --dir /tmp /tmp/b')
     assert(r == 'Failed to open \'/tmp/b\' for reading: No such file or directory')
     subprocess.getoutput('rm /tmp/a /tmp/b')
 
 
+def test_dir():
+    print('test_dir')
+    subprocess.getoutput('mkdir -p /tmp/wasc_dir/a')
+    subprocess.getoutput('echo Hello World! > /tmp/wasc_dir/a/b')
+    subprocess.getoutput('echo Secret! > /tmp/wasc_dir/c')
+    subprocess.getoutput('ln -s ../c /tmp/wasc_dir/a/link')
+    subprocess.getoutput('ln -s /tmp/wasc_dir/c /tmp/wasc_dir/a/abs')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/cat --dir /tmp/wasc_dir/a::/data /data/b')
+    assert(r == 'Hello World!')
+    r = subprocess.getoutput('WASC_DIRS="/tmp/wasc_dir/a::/data" res/posix_x86_64_wasi/cat /data/b')
+    assert(r == 'Hello World!')
+    # Directories which are not given, and paths leaving the given ones, can not be opened.
+    r = subprocess.getoutput('res/posix_x86_64_wasi/cat /tmp/wasc_dir/a/b')
+    assert(r.startswith('Failed to open'))
+    r = subprocess.getoutput('res/posix_x86_64_wasi/cat --dir /tmp/wasc_dir/a::/data /data/../c')
+    assert(r.startswith('Failed to open'))
+    r = subprocess.getoutput('res/posix_x86_64_wasi/cat --dir /tmp/wasc_dir/a::/data /data/link')
+    assert(r.startswith('Failed to open'))
+    r = subprocess.getoutput('res/posix_x86_64_wasi/cat --dir /tmp/wasc_dir/a::/data /data/abs')
+    assert(r.startswith('Failed to open'))
+    subprocess.getoutput('rm -rf /tmp/wasc_dir')
+
+
 def test_clock():
     print('test_clock')
     r = subprocess.getoutput('res/posix_x86_64_wasi/clock').split('\n')
@@ -52,9 +78,18 @@ def test_exit():
     assert(r == 0)
 
 
+def test_fault():
+    print('test_fault')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/fault --dir /tmp /tmp').split('\n')
+    assert(r[0] == 'fd_write(bad buffer): Bad address')
+    assert(r[1] == 'fd_write(bad iovs): Bad address')
+    assert(r[2] == 'clock_time_get(bad result): Bad address')
+    assert(r[3] == 'open(long path): Filename too long')
+
+
 def test_fd_filestat_set_size():
     print('test_fd_filestat_set_size')
-    subprocess.getoutput('res/posix_x86_64_wasi/fd_filestat_set_size /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/fd_filestat_set_size --dir /tmp /tmp/a')
     r = os.path.getsize('/tmp/a')
     assert(r == 4201)
     subprocess.getoutput('rm /tmp/a')
@@ -63,7 +98,7 @@ def test_fd_filestat_set_size():
 def test_fd_filestat_set_times():
     print('tset_fd_filestat_set_times')
     subprocess.getoutput('echo Hello World! > /tmp/a')
-    subprocess.getoutput('res/posix_x86_64_wasi/fd_filestat_set_times /tmp/a 100 100')
+    subprocess.getoutput('res/posix_x86_64_wasi/fd_filestat_set_times --dir /tmp /tmp/a 100 100')
     r = subprocess.getoutput('stat /tmp/a').split('\n')
     assert(r[4] == 'Access: 1970-01-01 08:00:00.000000100 +0800')
     assert(r[5] == 'Modify: 1970-01-01 08:00:00.000000100 +0800')
@@ -72,15 +107,15 @@ def test_fd_filestat_set_times():
 
 def test_fd_renumber():
     print('test_fd_renumber')
-    subprocess.getoutput('res/posix_x86_64_wasi/fd_renumber /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/fd_renumber --dir /tmp /tmp/a')
     r = subprocess.getoutput('cat /tmp/a')
-    assert(r == 'Hello stdout!\nHello file!')
+    assert(r == 'Hello stdout!')
     subprocess.getoutput('rm /tmp/a')
 
 
 def test_largefile():
     print('test_largefile')
-    r = subprocess.getoutput('res/posix_x86_64_wasi/largefile /tmp/a').split('\n')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/largefile --dir /tmp /tmp/a').split('\n')
     assert(r[0] == 'pread(3GB): Hello 3GB!')
     assert(r[1] == 'pread(6GB): Hello 6GB!')
     assert(r[2] == 'pread(9GB): Hello 9GB!')
@@ -91,14 +126,16 @@ def test_largefile():
 
 def test_ls():
     print('test_ls')
-    subprocess.getoutput('res/posix_x86_64_wasi/ls /tmp/').split('\n')
+    subprocess.getoutput('res/posix_x86_64_wasi/ls --dir /tmp /tmp/').split('\n')
 
 
 def test_mkdir():
     print('test_mkdir')
-    subprocess.getoutput('res/posix_x86_64_wasi/mkdir /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/mkdir --dir /tmp /tmp/a')
     assert(os.path.exists('/tmp/a'))
-    subprocess.getoutput('res/posix_x86_64_wasi/mkdir /tmp/a/b')
+    # The directory can be searched, its mode is only restricted by the umask.
+    assert(os.stat('/tmp/a').st_mode & 0o700 == 0o700)
+    subprocess.getoutput('res/posix_x86_64_wasi/mkdir --dir /tmp /tmp/a/b')
     assert(os.path.exists('/tmp/a/b'))
     subprocess.getoutput('rm -rf /tmp/a')
 
@@ -106,16 +143,24 @@ def test_mkdir():
 def test_path_filestat_set_times():
     print('test_path_filestat_set_times')
     subprocess.getoutput('echo Hello World! > /tmp/a')
-    subprocess.getoutput('res/posix_x86_64_wasi/fd_filestat_set_times /tmp/a 100 100')
+    subprocess.getoutput('res/posix_x86_64_wasi/fd_filestat_set_times --dir /tmp /tmp/a 100 100')
     r = subprocess.getoutput('stat /tmp/a').split('\n')
     assert(r[4] == 'Access: 1970-01-01 08:00:00.000000100 +0800')
     assert(r[5] == 'Modify: 1970-01-01 08:00:00.000000100 +0800')
     subprocess.getoutput('rm /tmp/a')
 
 
+def test_poll():
+    print('test_poll')
+    r = subprocess.getoutput('echo Hello World! | res/posix_x86_64_wasi/poll').split('\n')
+    assert(r[0] == 'nanosleep(200ms): ok')
+    assert(r[1] == 'poll(stdin): 1 POLLIN ok')
+    assert(r[2] == 'poll(100ms): 0 ok')
+
+
 def test_preadwrite():
     print('test_preadwrite')
-    r = subprocess.getoutput('res/posix_x86_64_wasi/preadwrite /tmp/a').split('\n')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/preadwrite --dir /tmp /tmp/a').split('\n')
     assert(r[0] == 'pread(5000): Hello 5000!')
     assert(r[1] == 'pread(500): Hello 500!')
     subprocess.getoutput('rm /tmp/a')
@@ -128,17 +173,45 @@ def test_random():
     assert(len(r) == 1024 * 2)
 
 
+def test_readlink():
+    print('test_readlink')
+    subprocess.getoutput('ln -s b /tmp/a')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/readlink --dir /tmp /tmp/a')
+    assert(r == 'b')
+    subprocess.getoutput('rm /tmp/a')
+
+
+def test_rename():
+    print('test_rename')
+    subprocess.getoutput('echo Hello World! > /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/rename --dir /tmp /tmp/a /tmp/b')
+    assert(not os.path.exists('/tmp/a'))
+    r = subprocess.getoutput('cat /tmp/b')
+    assert(r == 'Hello World!')
+    subprocess.getoutput('rm /tmp/b')
+
+
+def test_rights():
+    print('test_rights')
+    r = subprocess.getoutput('res/posix_x86_64_wasi/rights --dir /tmp /tmp/a').split('\n')
+    assert(r[0] == 'write(O_RDONLY): Capabilities insufficient')
+    assert(r[1] == 'write(dropped FD_WRITE): Capabilities insufficient')
+    assert(r[2] == 'fd_fdstat_set_rights(add FD_WRITE): Capabilities insufficient')
+    assert(r[3] == 'write(closed): Bad file descriptor')
+    subprocess.getoutput('rm /tmp/a')
+
+
 def test_rm():
     print('test_rm')
     subprocess.getoutput('echo Hello World! > /tmp/a')
-    subprocess.getoutput('res/posix_x86_64_wasi/rm /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/rm --dir /tmp /tmp/a')
     assert(not os.path.exists('/tmp/a'))
 
 
 def test_stat():
     print('test_stat')
     subprocess.getoutput('echo Hello World! > /tmp/a')
-    subprocess.getoutput('res/posix_x86_64_wasi/stat /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/stat --dir /tmp /tmp/a')
     subprocess.getoutput('rm /tmp/a')
 
 
@@ -148,9 +221,25 @@ def test_stdout():
     assert(r == 'Hello world!')
 
 
+def test_symlink():
+    print('test_symlink')
+    subprocess.getoutput('echo Hello World! > /tmp/b')
+    subprocess.getoutput('res/posix_x86_64_wasi/symlink --dir /tmp b /tmp/a')
+    assert(os.readlink('/tmp/a') == 'b')
+    r = subprocess.getoutput('cat /tmp/a')
+    assert(r == 'Hello World!')
+    subprocess.getoutput('rm /tmp/a /tmp/b')
+
+
+def test_thread():
+    print('test_thread')
+    r, _ = subprocess.getstatusoutput('res/posix_x86_64_wasi/thread')
+    assert(r == 0)
+
+
 def test_write():
     print('test_write')
-    subprocess.getoutput('res/posix_x86_64_wasi/write /tmp/a')
+    subprocess.getoutput('res/posix_x86_64_wasi/write --dir /tmp /tmp/a')
     r = subprocess.getoutput('cat /tmp/a')
     assert(r == 'Hello World!')
     subprocess.getoutput('rm /tmp/a')
@@ -160,7 +249,9 @@ test_append()
 test_args()
 test_cat()
 test_clock()
+test_dir()
 test_exit()
+test_fault()
 test_fd_filestat_set_size()
 test_fd_filestat_set_times()
 test_fd_renumber()
@@ -168,9 +259,15 @@ test_largefile()
 test_ls()
 test_mkdir()
 test_path_filestat_set_times()
+test_poll()
 test_preadwrite()
 test_random()
+test_readlink()
+test_rename()
+test_rights()
 test_rm()
 test_stat()
 test_stdout()
+test_symlink()
+test_thread()
 test_write()